@@ -0,0 +1,12 @@
+CREATE TYPE priority AS ENUM ('Low', 'Medium', 'High');
+
+CREATE TABLE IF NOT EXISTS todos (
+    id SERIAL PRIMARY KEY,
+    text TEXT NOT NULL,
+    completed BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    due_date TIMESTAMPTZ,
+    priority priority NOT NULL DEFAULT 'Medium',
+    tags JSONB NOT NULL DEFAULT '[]'
+);
@@ -0,0 +1 @@
+ALTER TABLE todos ADD COLUMN archived BOOLEAN NOT NULL DEFAULT false;
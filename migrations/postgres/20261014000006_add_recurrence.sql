@@ -0,0 +1,3 @@
+CREATE TYPE recurrence AS ENUM ('Daily', 'Weekly');
+
+ALTER TABLE todos ADD COLUMN recurrence recurrence;
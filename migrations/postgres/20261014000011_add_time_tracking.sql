@@ -0,0 +1,2 @@
+ALTER TABLE todos ADD COLUMN estimate_minutes BIGINT;
+ALTER TABLE todos ADD COLUMN spent_minutes BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE todos ADD COLUMN attachments JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS todos (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    text TEXT NOT NULL,
+    completed BOOLEAN NOT NULL DEFAULT false,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    due_date TEXT,
+    priority TEXT NOT NULL DEFAULT 'Medium',
+    tags TEXT NOT NULL DEFAULT '[]'
+);
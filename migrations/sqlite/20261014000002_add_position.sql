@@ -0,0 +1,3 @@
+ALTER TABLE todos ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+UPDATE todos SET position = id - 1;
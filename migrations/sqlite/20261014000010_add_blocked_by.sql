@@ -0,0 +1 @@
+ALTER TABLE todos ADD COLUMN blocked_by TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,2 @@
+ALTER TABLE todos ADD COLUMN estimate_minutes INTEGER;
+ALTER TABLE todos ADD COLUMN spent_minutes INTEGER NOT NULL DEFAULT 0;
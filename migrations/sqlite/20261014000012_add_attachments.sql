@@ -0,0 +1 @@
+ALTER TABLE todos ADD COLUMN attachments TEXT NOT NULL DEFAULT '[]';
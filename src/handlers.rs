@@ -15,10 +15,13 @@ pub async fn root() -> &'static str {
 pub async fn create_todo<T: TodoRepository>(
     Json(payload): Json<CreateTodo>,
     Extension(repository): Extension<Arc<T>>,
-) -> impl IntoResponse {
-    let todo = repository.create(payload);
+) -> Result<impl IntoResponse, StatusCode> {
+    let todo = repository
+        .create(payload)
+        .await
+        .or(Err(StatusCode::UNPROCESSABLE_ENTITY))?;
 
-    (StatusCode::CREATED, Json(todo))
+    Ok((StatusCode::CREATED, Json(todo)))
 }
 
 pub async fn find_todo<T: TodoRepository>(
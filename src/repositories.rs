@@ -1,163 +1,8402 @@
 use anyhow::Context;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, NaiveDate, Utc};
+use rand::{seq::SliceRandom, Rng};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    io::{self, BufRead, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
 };
 use thiserror::Error;
+use tokio::sync::broadcast;
+
+use clock::{Clock, SystemClock};
+use in_memory_store::{Entries, IdGenerator, InMemoryStore, Locked};
+use metrics::{Metrics, NoopMetrics};
+
+pub mod cached;
+pub mod camel_case;
+pub mod clock;
+pub mod in_memory_store;
+pub mod json_file;
+pub mod metrics;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod read_only;
+pub mod retrying;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(test)]
+pub mod test_utils;
+pub mod undoable;
 
 #[derive(Debug, Error)]
-enum RepositoryError {
+pub enum RepositoryError {
     #[error("NotFound, id is {0}")]
     NotFound(i32),
+    // Every problem found, so a caller can fix them all in one go.
+    #[error("Validation, {}", .0.join("; "))]
+    Validation(Vec<String>),
+    #[error("Conflict, id is {0}")]
+    Conflict(i32),
+    #[error("StorageUnavailable")]
+    StorageUnavailable,
+    #[error("CapacityExceeded, limit is {0}")]
+    CapacityExceeded(usize),
+    #[error("IdSpaceExhausted")]
+    IdSpaceExhausted,
+    #[error("Inconsistent, {0}")]
+    Inconsistent(String),
+}
+
+#[async_trait]
+pub trait TodoRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo>;
+    // Returns the first live todo, by id, whose text is exactly `text`, or
+    // creates one. Checking and inserting happen atomically.
+    async fn find_or_create(&self, text: &str) -> anyhow::Result<Todo>;
+    // Like `create`, but fails with `RepositoryError::Conflict` carrying the
+    // existing id when a live todo already has the same text, ignoring case.
+    async fn create_unique(&self, payload: CreateTodo) -> anyhow::Result<Todo>;
+    async fn find(&self, id: i32) -> Option<Todo>;
+    async fn all(&self) -> Vec<Todo>;
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo>;
+    // Like `update`, but fails with `RepositoryError::Conflict` when the
+    // stored version is not `expected_version`.
+    async fn update_if_version(
+        &self,
+        id: i32,
+        expected_version: u32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo>;
+    // Like `update`, but returns the todo as it was just before the update
+    // along with the updated one.
+    async fn replace(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<(Todo, Todo)>;
+    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+    async fn soft_delete(&self, id: i32) -> anyhow::Result<()>;
+    async fn restore(&self, id: i32) -> anyhow::Result<Todo>;
+    async fn clear_all(&self) -> anyhow::Result<()>;
+    async fn reorder(&self, id: i32, new_position: i32) -> anyhow::Result<()>;
+    // Both return how many todos they changed, and only consider the todos
+    // `all` returns.
+    async fn complete_all(&self) -> anyhow::Result<usize>;
+    async fn clear_completed(&self) -> anyhow::Result<usize>;
+    // Archived todos are left out of `all` but can still be found by id.
+    async fn archive(&self, id: i32) -> anyhow::Result<Todo>;
+    async fn unarchive(&self, id: i32) -> anyhow::Result<Todo>;
+    async fn archived(&self) -> Vec<Todo>;
+    // Adds `minutes` to the time spent on the todo.
+    async fn log_time(&self, id: i32, minutes: u32) -> anyhow::Result<Todo>;
+
+    // For callers that read a todo, decide on a change and write it back:
+    // fails with `RepositoryError::Conflict` when anything else updated the
+    // todo after `base` was read, instead of overwriting that update.
+    async fn update_from(&self, base: &Todo, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        self.update_if_version(base.id, base.version, payload).await
+    }
+
+    // Whether the backend can serve requests, e.g. for a readiness probe.
+    // Fails with `RepositoryError::StorageUnavailable` or the backend's own
+    // error when it cannot.
+    async fn health_check(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn all_paginated(&self, offset: usize, limit: usize) -> Vec<Todo> {
+        sorted_by_id(self.all().await)
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .collect()
+    }
+
+    // Incomplete todos none of whose blockers are still incomplete. Blockers
+    // that `all` no longer returns do not hold anything up.
+    async fn ready(&self) -> Vec<Todo> {
+        let todos = self.all().await;
+        let incomplete: HashSet<i32> = todos
+            .iter()
+            .filter(|todo| !todo.completed)
+            .map(|todo| todo.id)
+            .collect();
+        let ready = todos
+            .into_iter()
+            .filter(|todo| {
+                !todo.completed && !todo.blocked_by.iter().any(|id| incomplete.contains(id))
+            })
+            .collect();
+
+        sorted_by_id(ready)
+    }
+
+    // The ready todo to work on next: highest priority first, then the
+    // earliest due date, with undated todos after dated ones, then lowest id.
+    async fn next_task(&self) -> Option<Todo> {
+        self.ready().await.into_iter().min_by_key(|todo| {
+            (
+                std::cmp::Reverse(todo.priority),
+                todo.due_date.is_none(),
+                todo.due_date,
+                todo.id,
+            )
+        })
+    }
+
+    // Unlike offsets, the cursor keeps its place when todos before it are
+    // created or deleted. Pass the last id of the previous page.
+    async fn page_after(&self, after_id: Option<i32>, limit: usize) -> Vec<Todo> {
+        sorted_by_id(self.all().await)
+            .into_iter()
+            .filter(|todo| after_id.is_none_or(|after_id| todo.id > after_id))
+            .take(limit)
+            .collect()
+    }
+
+    // `total` counts every todo `all` returns and comes from the same read as
+    // `items`, so the two always agree.
+    async fn page(&self, offset: usize, limit: usize) -> Page<Todo> {
+        let todos = sorted_by_id(self.all().await);
+
+        Page {
+            total: todos.len(),
+            items: todos.into_iter().skip(offset).take(limit).collect(),
+            offset,
+            limit,
+        }
+    }
+
+    async fn query(&self, filter: TodoFilter) -> Vec<Todo> {
+        let todos = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| filter.matches(todo))
+            .collect();
+
+        sorted_by_id(todos)
+    }
+
+    async fn query_str(&self, query: &str) -> anyhow::Result<Vec<Todo>> {
+        Ok(self.query(TodoFilter::parse(query)?).await)
+    }
+
+    async fn find_by_completed(&self, completed: bool) -> Vec<Todo> {
+        self.query(TodoFilter {
+            completed: Some(completed),
+            ..TodoFilter::default()
+        })
+        .await
+    }
+
+    async fn random_incomplete(&self) -> Option<Todo> {
+        let todos = sorted_by_id(self.find_by_completed(false).await);
+        todos.choose(&mut rand::thread_rng()).cloned()
+    }
+
+    // Like `random_incomplete`, but draws from `rng`, so a seeded one picks
+    // the same todo every time.
+    async fn random_incomplete_with<G: Rng + Send>(&self, rng: &mut G) -> Option<Todo> {
+        let todos = sorted_by_id(self.find_by_completed(false).await);
+        todos.choose(rng).cloned()
+    }
+
+    // An empty query matches every todo, since every text contains "".
+    async fn search(&self, query: &str) -> Vec<Todo> {
+        self.query(TodoFilter {
+            text_contains: Some(query.to_string()),
+            ..TodoFilter::default()
+        })
+        .await
+    }
+
+    // Groups of two or more todos whose texts differ at most in case, each
+    // sorted by id and the groups by their first id.
+    async fn find_duplicates(&self) -> Vec<Vec<Todo>> {
+        let mut groups: HashMap<String, Vec<Todo>> = HashMap::new();
+        for todo in sorted_by_id(self.all().await) {
+            groups
+                .entry(todo.text.to_lowercase())
+                .or_default()
+                .push(todo);
+        }
+        let mut duplicates: Vec<Vec<Todo>> = groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect();
+        duplicates.sort_by_key(|group| group[0].id);
+
+        duplicates
+    }
+
+    // Every word of `query` has to be close to some word of the text, allowing
+    // a typo or two in longer words. Best matches come first, ties by id. A
+    // blank query matches nothing.
+    async fn search_fuzzy(&self, query: &str, limit: usize) -> Vec<(Todo, i64)> {
+        let mut matches: Vec<(Todo, i64)> = self
+            .all()
+            .await
+            .into_iter()
+            .filter_map(|todo| fuzzy_score(query, &todo.text).map(|score| (todo, score)))
+            .collect();
+        matches.sort_by_key(|(todo, score)| (std::cmp::Reverse(*score), todo.id));
+        matches.truncate(limit);
+
+        matches
+    }
+
+    async fn overdue(&self, now: DateTime<Utc>) -> Vec<Todo> {
+        let todos = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| !todo.completed && todo.due_date.is_some_and(|due| due < now))
+            .collect();
+
+        sorted_by_id(todos)
+    }
+
+    // Incomplete todos last updated before `now - older_than`, the longest
+    // untouched first.
+    async fn stale(&self, now: DateTime<Utc>, older_than: chrono::Duration) -> Vec<Todo> {
+        let cutoff = now - older_than;
+        let mut todos: Vec<Todo> = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| !todo.completed && todo.updated_at < cutoff)
+            .collect();
+        todos.sort_by_key(|todo| (todo.updated_at, todo.id));
+
+        todos
+    }
+
+    // Incomplete todos due in `[now, now + window)`, soonest first. Overdue
+    // todos are left to `overdue`.
+    async fn due_within(&self, now: DateTime<Utc>, window: chrono::Duration) -> Vec<Todo> {
+        let end = now + window;
+        let mut todos: Vec<Todo> = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| {
+                !todo.completed && todo.due_date.is_some_and(|due| now <= due && due < end)
+            })
+            .collect();
+        todos.sort_by_key(|todo| (todo.due_date, todo.id));
+
+        todos
+    }
+
+    // Incomplete todos: overdue ones first, the most overdue leading, then
+    // upcoming ones soonest first, then those without a due date by id.
+    async fn agenda(&self, now: DateTime<Utc>) -> Vec<Todo> {
+        let mut todos: Vec<Todo> = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| !todo.completed)
+            .collect();
+        todos.sort_by_key(|todo| {
+            let bucket = match todo.due_date {
+                Some(due) if due < now => 0,
+                Some(_) => 1,
+                None => 2,
+            };
+            (bucket, todo.due_date, todo.id)
+        });
+
+        todos
+    }
+
+    async fn find_by_priority(&self, priority: Priority) -> Vec<Todo> {
+        let todos = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| todo.priority == priority)
+            .collect();
+
+        sorted_by_id(todos)
+    }
+
+    async fn find_by_assignee(&self, assignee: &str) -> Vec<Todo> {
+        let todos = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| todo.assignee.as_deref() == Some(assignee))
+            .collect();
+
+        sorted_by_id(todos)
+    }
+
+    async fn unassigned(&self) -> Vec<Todo> {
+        let todos = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| todo.assignee.is_none())
+            .collect();
+
+        sorted_by_id(todos)
+    }
+
+    async fn untagged(&self) -> Vec<Todo> {
+        let todos = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| todo.tags.is_empty())
+            .collect();
+
+        sorted_by_id(todos)
+    }
+
+    async fn find_by_tag(&self, tag: &str) -> Vec<Todo> {
+        self.query(TodoFilter {
+            tag: Some(tag.to_string()),
+            ..TodoFilter::default()
+        })
+        .await
+    }
+
+    async fn exists(&self, id: i32) -> bool {
+        self.find(id).await.is_some()
+    }
+
+    async fn count(&self) -> usize {
+        self.all().await.len()
+    }
+
+    // Whether `all` would return nothing.
+    async fn is_empty(&self) -> bool {
+        self.count().await == 0
+    }
+
+    async fn count_completed(&self) -> usize {
+        self.find_by_completed(true).await.len()
+    }
+
+    // The highest id still in use, archived todos included. Unlike the id
+    // counter this drops back when the highest todo is deleted.
+    async fn max_id(&self) -> Option<i32> {
+        let archived = self.archived().await;
+        self.all()
+            .await
+            .iter()
+            .chain(archived.iter())
+            .map(|todo| todo.id)
+            .max()
+    }
+
+    async fn bulk_create(&self, payloads: Vec<CreateTodo>) -> anyhow::Result<Vec<Todo>> {
+        for payload in payloads.iter() {
+            payload.validate()?;
+        }
+
+        let mut todos = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            todos.push(self.create(payload).await?);
+        }
+
+        Ok(todos)
+    }
+
+    async fn bulk_delete(&self, ids: Vec<i32>) -> anyhow::Result<BulkDeleteReport> {
+        let mut report = BulkDeleteReport::default();
+        for id in ids {
+            match self.delete(id).await {
+                Ok(()) => report.deleted.push(id),
+                Err(e) if is_not_found(&e) => report.not_found.push(id),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Retried like `complete`, so a write in between can't be undone by
+    // flipping a stale value.
+    async fn toggle_completed(&self, id: i32) -> anyhow::Result<Todo> {
+        loop {
+            let todo = self.find(id).await.context(RepositoryError::NotFound(id))?;
+            let payload = UpdateTodo::completed(!todo.completed);
+
+            match self.update_if_version(id, todo.version, payload).await {
+                Err(e) if is_conflict(&e) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    // Completes the todo unless it already is, so a repeated request can be
+    // told apart from the first. Another writer getting in between the read
+    // and the write is caught by the version check, and the read is retried.
+    async fn complete(&self, id: i32) -> anyhow::Result<CompleteOutcome> {
+        loop {
+            let todo = self.find(id).await.context(RepositoryError::NotFound(id))?;
+            if todo.completed {
+                return Ok(CompleteOutcome::AlreadyComplete(todo));
+            }
+
+            match self
+                .update_if_version(id, todo.version, UpdateTodo::completed(true))
+                .await
+            {
+                Ok(todo) => return Ok(CompleteOutcome::JustCompleted(todo)),
+                Err(e) if is_conflict(&e) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Moves the priority one level towards `High`, or `Low` when `up` is
+    // false, staying put at either end. Retried like `complete`.
+    async fn bump_priority(&self, id: i32, up: bool) -> anyhow::Result<Todo> {
+        loop {
+            let todo = self.find(id).await.context(RepositoryError::NotFound(id))?;
+            let payload = UpdateTodo {
+                priority: Some(todo.priority.bumped(up)),
+                ..UpdateTodo::default()
+            };
+
+            match self.update_if_version(id, todo.version, payload).await {
+                Err(e) if is_conflict(&e) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    // Fails with `RepositoryError::Validation` when the todo already has an
+    // attachment with the same name.
+    async fn add_attachment(&self, id: i32, attachment: Attachment) -> anyhow::Result<Todo> {
+        loop {
+            let todo = self.find(id).await.context(RepositoryError::NotFound(id))?;
+            let mut attachments = todo.attachments;
+            attachments.push(attachment.clone());
+            let payload = UpdateTodo {
+                attachments: Some(attachments),
+                ..UpdateTodo::default()
+            };
+
+            match self.update_if_version(id, todo.version, payload).await {
+                Err(e) if is_conflict(&e) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    async fn remove_attachment(&self, id: i32, name: &str) -> anyhow::Result<Todo> {
+        loop {
+            let todo = self.find(id).await.context(RepositoryError::NotFound(id))?;
+            let mut attachments = todo.attachments;
+            let before = attachments.len();
+            attachments.retain(|attachment| attachment.name != name);
+            if attachments.len() == before {
+                return Err(RepositoryError::Validation(vec![format!(
+                    "todo {} has no attachment named {:?}",
+                    id, name
+                )])
+                .into());
+            }
+            let payload = UpdateTodo {
+                attachments: Some(attachments),
+                ..UpdateTodo::default()
+            };
+
+            match self.update_if_version(id, todo.version, payload).await {
+                Err(e) if is_conflict(&e) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    // Creates a new, incomplete todo with the text, tags, priority and due
+    // date of `id`.
+    async fn duplicate(&self, id: i32) -> anyhow::Result<Todo> {
+        let todo = self.find(id).await.context(RepositoryError::NotFound(id))?;
+
+        self.create(CreateTodo {
+            due_date: todo.due_date,
+            priority: todo.priority,
+            tags: todo.tags,
+            ..CreateTodo::new(todo.text)
+        })
+        .await
+    }
+
+    // Folds the tags and description of `merge_id` into `keep_id`, then
+    // deletes `merge_id`. `keep_id` must not be one of `merge_id`'s
+    // descendants, which deleting it can take along.
+    async fn merge(&self, keep_id: i32, merge_id: i32) -> anyhow::Result<Todo> {
+        check_merge_ids(keep_id, merge_id)?;
+        let keep = self
+            .find(keep_id)
+            .await
+            .context(RepositoryError::NotFound(keep_id))?;
+        let merged = self
+            .find(merge_id)
+            .await
+            .context(RepositoryError::NotFound(merge_id))?;
+        let mut ancestor_id = keep.parent_id;
+        let mut seen = HashSet::new();
+        while let Some(id) = ancestor_id.filter(|id| seen.insert(*id)) {
+            if id == merge_id {
+                return Err(merge_into_descendant().into());
+            }
+            ancestor_id = self.find(id).await.and_then(|todo| todo.parent_id);
+        }
+
+        let todo = self.update(keep_id, keep.absorbing(&merged)).await?;
+        self.delete(merge_id).await?;
+
+        Ok(todo)
+    }
+
+    // Completes the incomplete todos `query(filter)` returns and hands them
+    // back, by id.
+    async fn complete_where(&self, filter: TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        let mut completed = Vec::new();
+        for todo in self.query(filter).await {
+            if !todo.completed {
+                completed.push(self.update(todo.id, UpdateTodo::completed(true)).await?);
+            }
+        }
+
+        Ok(completed)
+    }
+
+    // Applies `payload` to every todo `query(filter)` returns and hands them
+    // back updated, by id.
+    async fn update_where(
+        &self,
+        filter: TodoFilter,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let mut updated = Vec::new();
+        for todo in sorted_by_id(self.query(filter).await) {
+            updated.push(self.update(todo.id, payload.clone()).await?);
+        }
+
+        Ok(updated)
+    }
+
+    async fn find_many(&self, ids: &[i32]) -> Vec<Todo> {
+        let todos: HashMap<i32, Todo> = self
+            .all()
+            .await
+            .into_iter()
+            .map(|todo| (todo.id, todo))
+            .collect();
+
+        ids.iter().filter_map(|id| todos.get(id).cloned()).collect()
+    }
+
+    // Minutes spent across every todo `all` returns.
+    async fn total_spent(&self) -> u32 {
+        self.all()
+            .await
+            .iter()
+            .fold(0, |total, todo| total.saturating_add(todo.spent_minutes))
+    }
+
+    // Like `find_many`, but keyed by id for lookups.
+    async fn find_map(&self, ids: &[i32]) -> HashMap<i32, Todo> {
+        self.find_many(ids)
+            .await
+            .into_iter()
+            .map(|todo| (todo.id, todo))
+            .collect()
+    }
+
+    async fn all_sorted(&self, order: SortOrder) -> Vec<Todo> {
+        sorted_by(self.all().await, order)
+    }
+
+    // Newest first by created_at; todos created at the same instant come out
+    // with the higher id first.
+    async fn recent(&self, n: usize) -> Vec<Todo> {
+        let mut todos = self.all().await;
+        todos.sort_by_key(|todo| std::cmp::Reverse((todo.created_at, todo.id)));
+        todos.truncate(n);
+
+        todos
+    }
+
+    async fn all_by_position(&self) -> Vec<Todo> {
+        sorted_by_position(self.all().await)
+    }
+
+    // Shortcuts for `reorder` to either end. Archived todos keep their place
+    // in the order, so they count towards the last position.
+    async fn move_to_top(&self, id: i32) -> anyhow::Result<()> {
+        self.reorder(id, 0).await
+    }
+
+    async fn move_to_bottom(&self, id: i32) -> anyhow::Result<()> {
+        let len = self.all().await.len() + self.archived().await.len();
+        self.reorder(id, len.saturating_sub(1) as i32).await
+    }
+
+    async fn stats(&self) -> TodoStats {
+        TodoStats::collect(self.all().await.iter(), Utc::now())
+    }
+
+    // How many of the todos `all` returns carry each tag.
+    async fn tag_counts(&self) -> HashMap<String, usize> {
+        count_tags(self.all().await.iter())
+    }
+
+    // Every tag in use, sorted.
+    async fn all_tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self.tag_counts().await.into_keys().collect();
+        tags.sort();
+
+        tags
+    }
+
+    // An inverted range (start > end) matches nothing.
+    async fn created_between(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<Todo> {
+        let todos = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| start <= todo.created_at && todo.created_at <= end)
+            .collect();
+
+        sorted_by_id(todos)
+    }
+
+    // Completed todos per UTC day from `from` to `to`, both inclusive, with
+    // every day in between present. Todos don't record when they were
+    // completed, so a completed todo counts on the day it was last updated.
+    async fn completions_by_day(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> BTreeMap<NaiveDate, usize> {
+        let mut days: BTreeMap<NaiveDate, usize> = from
+            .date_naive()
+            .iter_days()
+            .take_while(|day| *day <= to.date_naive())
+            .map(|day| (day, 0))
+            .collect();
+        for todo in self.all().await {
+            if todo.completed && from <= todo.updated_at && todo.updated_at <= to {
+                *days.entry(todo.updated_at.date_naive()).or_default() += 1;
+            }
+        }
+
+        days
+    }
+
+    // Completed todos last updated from `from` to `to`, both inclusive, taking
+    // the update as the completion like `completions_by_day` does. Earliest
+    // first, ties by id.
+    async fn completed_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Todo> {
+        let mut todos: Vec<Todo> = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| todo.completed && from <= todo.updated_at && todo.updated_at <= to)
+            .collect();
+        todos.sort_by_key(|todo| (todo.updated_at, todo.id));
+
+        todos
+    }
+
+    async fn children(&self, parent_id: i32) -> Vec<Todo> {
+        let todos = self
+            .all()
+            .await
+            .into_iter()
+            .filter(|todo| todo.parent_id == Some(parent_id))
+            .collect();
+
+        sorted_by_id(todos)
+    }
+
+    // Tags are joined with `;` and missing optional values are left empty.
+    async fn export_csv(&self) -> String {
+        let mut csv = csv_row(CSV_HEADER);
+        for todo in sorted_by_id(self.all().await) {
+            let fields = [
+                todo.id.to_string(),
+                todo.text,
+                todo.completed.to_string(),
+                todo.created_at.to_rfc3339(),
+                todo.updated_at.to_rfc3339(),
+                todo.due_date
+                    .map(|due_date| due_date.to_rfc3339())
+                    .unwrap_or_default(),
+                format!("{:?}", todo.priority),
+                todo.tags.join(";"),
+                todo.position.to_string(),
+                todo.parent_id
+                    .map(|parent_id| parent_id.to_string())
+                    .unwrap_or_default(),
+            ];
+            csv.push_str(&csv_row(fields.iter().map(String::as_str)));
+        }
+
+        csv
+    }
+
+    async fn export_json(&self) -> String {
+        serde_json::to_string(&sorted_by_id(self.all().await)).unwrap()
+    }
+
+    // The todos `export_json` would write, in `format`.
+    async fn export(&self, format: SerializationFormat) -> anyhow::Result<Vec<u8>> {
+        format.serialize(&sorted_by_id(self.all().await))
+    }
+
+    // The todo as base64url JSON, for passing it along in a link. See
+    // `Todo::from_share_token`.
+    async fn share_token(&self, id: i32) -> anyhow::Result<String> {
+        let todo = self.find(id).await.context(RepositoryError::NotFound(id))?;
+
+        Ok(todo.share_token())
+    }
+
+    async fn import_json(&self, data: &str) -> anyhow::Result<usize> {
+        self.import_from(SerializationFormat::Json, data.as_bytes())
+            .await
+    }
+
+    // Imported todos are given fresh ids so they never collide with the ones
+    // already stored. Parent links and blockers are remapped to the new ids,
+    // or dropped when they point outside the import.
+    async fn import_from(&self, format: SerializationFormat, data: &[u8]) -> anyhow::Result<usize> {
+        let todos = format.deserialize(data)?;
+
+        let mut new_ids = HashMap::new();
+        for todo in sorted_by_id(todos.clone()) {
+            let imported = self
+                .create(CreateTodo {
+                    id: None,
+                    text: todo.text,
+                    due_date: todo.due_date,
+                    priority: todo.priority,
+                    tags: todo.tags,
+                    parent_id: todo.parent_id.and_then(|id| new_ids.get(&id).copied()),
+                    recurrence: todo.recurrence,
+                    description: todo.description,
+                    assignee: todo.assignee,
+                    color: todo.color,
+                    blocked_by: Vec::new(),
+                    estimate_minutes: todo.estimate_minutes,
+                    completed: Some(todo.completed),
+                    attachments: todo.attachments,
+                })
+                .await?;
+            if todo.spent_minutes > 0 {
+                self.log_time(imported.id, todo.spent_minutes).await?;
+            }
+            new_ids.insert(todo.id, imported.id);
+        }
+        // Blockers can come later in the import than the todos they block.
+        for todo in todos.iter().filter(|todo| !todo.blocked_by.is_empty()) {
+            let blocked_by = todo
+                .blocked_by
+                .iter()
+                .filter_map(|id| new_ids.get(id).copied())
+                .collect();
+            let payload = UpdateTodo {
+                blocked_by: Some(blocked_by),
+                ..UpdateTodo::default()
+            };
+            self.update(new_ids[&todo.id], payload).await?;
+        }
+
+        Ok(todos.len())
+    }
+}
+
+fn check_merge_ids(keep_id: i32, merge_id: i32) -> Result<(), RepositoryError> {
+    if keep_id == merge_id {
+        return Err(RepositoryError::Validation(vec![
+            "a todo cannot be merged into itself".to_string(),
+        ]));
+    }
+
+    Ok(())
+}
+
+fn merge_into_descendant() -> RepositoryError {
+    RepositoryError::Validation(vec![
+        "a todo cannot be merged into one of its descendants".to_string()
+    ])
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<RepositoryError>(),
+        Some(RepositoryError::NotFound(_))
+    )
+}
+
+fn is_conflict(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<RepositoryError>(),
+        Some(RepositoryError::Conflict(_))
+    )
+}
+
+fn sorted_by_position(mut todos: Vec<Todo>) -> Vec<Todo> {
+    todos.sort_by_key(|todo| (todo.position, todo.id));
+    todos
+}
+
+// Moves `id` to `new_position` within `ordered`, a list of ids sorted by
+// position, and returns the new order.
+fn reordered(
+    mut ordered: Vec<i32>,
+    id: i32,
+    new_position: i32,
+) -> Result<Vec<i32>, RepositoryError> {
+    let from = ordered
+        .iter()
+        .position(|other| *other == id)
+        .ok_or(RepositoryError::NotFound(id))?;
+    if new_position < 0 || new_position as usize >= ordered.len() {
+        return Err(RepositoryError::Validation(vec![format!(
+            "position must be between 0 and {}",
+            ordered.len() - 1
+        )]));
+    }
+
+    let id = ordered.remove(from);
+    ordered.insert(new_position as usize, id);
+
+    Ok(ordered)
+}
+
+const CSV_HEADER: [&str; 10] = [
+    "id",
+    "text",
+    "completed",
+    "created_at",
+    "updated_at",
+    "due_date",
+    "priority",
+    "tags",
+    "position",
+    "parent_id",
+];
+
+// Quotes a field only when it has to, doubling any quotes inside it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_row<'a>(fields: impl IntoIterator<Item = &'a str>) -> String {
+    let fields: Vec<String> = fields.into_iter().map(csv_field).collect();
+
+    fields.join(",") + "\r\n"
+}
+
+fn sorted_by_id(mut todos: Vec<Todo>) -> Vec<Todo> {
+    todos.sort_by_key(|todo| todo.id);
+    todos
+}
+
+// Ties are broken by ascending id so the order is always the same.
+fn sorted_by(mut todos: Vec<Todo>, order: SortOrder) -> Vec<Todo> {
+    match order {
+        SortOrder::IdAsc => todos.sort_by_key(|todo| todo.id),
+        SortOrder::IdDesc => todos.sort_by_key(|todo| std::cmp::Reverse(todo.id)),
+        SortOrder::TextAsc => todos.sort_by(|a, b| (&a.text, a.id).cmp(&(&b.text, b.id))),
+        SortOrder::CompletedFirst => todos.sort_by_key(|todo| (!todo.completed, todo.id)),
+    }
+    todos
+}
+
+fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let text = text.to_lowercase();
+    let words: Vec<Vec<char>> = text
+        .split_whitespace()
+        .map(|w| w.chars().collect())
+        .collect();
+    let query = query.to_lowercase();
+    let query: Vec<Vec<char>> = query
+        .split_whitespace()
+        .map(|q| q.chars().collect())
+        .collect();
+    if query.is_empty() {
+        return None;
+    }
+
+    query
+        .iter()
+        .map(|q| {
+            words
+                .iter()
+                .filter_map(|word| fuzzy_word_score(q, word))
+                .max()
+        })
+        .sum()
+}
+
+// 100 for the same word, 90 when `query` starts it, and less for every typo
+// after that.
+fn fuzzy_word_score(query: &[char], word: &[char]) -> Option<i64> {
+    if query == word {
+        return Some(100);
+    }
+    if word.starts_with(query) {
+        return Some(90);
+    }
+
+    let prefix = &word[..word.len().min(query.len())];
+    let typos = edit_distance(query, word).min(edit_distance(query, prefix));
+    let allowed = query.len() / 4;
+    (typos <= allowed).then(|| 80 - 20 * typos as i64)
+}
+
+// Levenshtein distance, with swapping two neighbouring characters counted as
+// a single edit.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let substitution = rows[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            row[j] = substitution.min(rows[i - 1][j] + 1).min(row[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+
+    rows[a.len()][b.len()]
+}
+
+// Tags are deduplicated on the way in, so a todo counts once per tag.
+fn count_tags<'a>(todos: impl Iterator<Item = &'a Todo>) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for tag in todos.flat_map(|todo| todo.tags.iter()) {
+        *counts.entry(tag.clone()).or_default() += 1;
+    }
+
+    counts
+}
+
+fn dedup_tags(tags: Vec<String>) -> Vec<String> {
+    let mut deduped: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        if !deduped.contains(&tag) {
+            deduped.push(tag);
+        }
+    }
+    deduped
+}
+
+// Ordered from least to most urgent.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default,
+)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(type_name = "priority"))]
+pub enum Priority {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(type_name = "recurrence"))]
+pub enum Recurrence {
+    Daily,
+    Weekly,
+}
+
+impl Priority {
+    fn bumped(self, up: bool) -> Self {
+        match (self, up) {
+            (Priority::Low, true) | (Priority::High, false) => Priority::Medium,
+            (Priority::Medium | Priority::High, true) => Priority::High,
+            (Priority::Medium | Priority::Low, false) => Priority::Low,
+        }
+    }
+}
+
+impl Recurrence {
+    fn interval(self) -> chrono::Duration {
+        match self {
+            Recurrence::Daily => chrono::Duration::days(1),
+            Recurrence::Weekly => chrono::Duration::weeks(1),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    #[default]
+    IdAsc,
+    IdDesc,
+    TextAsc,
+    CompletedFirst,
+}
+
+// What happens to text longer than a repository's `max_text_len`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlongText {
+    // The create or update fails with `RepositoryError::Validation`.
+    #[default]
+    Reject,
+    // The text is cut down to `max_text_len` characters.
+    Truncate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TextLimit {
+    max_len: usize,
+    overlong: OverlongText,
 }
 
-pub trait TodoRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
-    fn create(&self, payload: CreateTodo) -> Todo;
-    fn find(&self, id: i32) -> Option<Todo>;
-    fn all(&self) -> Vec<Todo>;
-    fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo>;
-    fn delete(&self, id: i32) -> anyhow::Result<()>;
-}
+impl Default for TextLimit {
+    fn default() -> Self {
+        Self {
+            max_len: 10_000,
+            overlong: OverlongText::default(),
+        }
+    }
+}
+
+impl TextLimit {
+    // Lengths are counted in characters, not bytes.
+    fn apply(&self, text: &mut String) -> Result<(), RepositoryError> {
+        let Some((end, _)) = text.char_indices().nth(self.max_len) else {
+            return Ok(());
+        };
+
+        match self.overlong {
+            OverlongText::Reject => Err(RepositoryError::Validation(vec![format!(
+                "text must be at most {} characters",
+                self.max_len
+            )])),
+            OverlongText::Truncate => {
+                text.truncate(end);
+                Ok(())
+            }
+        }
+    }
+}
+
+// What happens when a todo with open children is completed. Soft-deleted
+// children are never considered, and neither are children completed by the
+// same `complete_where` or `update_where`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CascadeMode {
+    // The children are left as they are.
+    #[default]
+    Ignore,
+    // Open children, and their open children in turn, are completed too.
+    CompleteChildren,
+    // The completion fails with `RepositoryError::Conflict` carrying the id of
+    // the first open child.
+    BlockIfChildrenOpen,
+}
+
+// Which todos count towards a repository's `max_todos`. Soft-deleted todos
+// never do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CountedTodos {
+    #[default]
+    All,
+    // Completed and archived todos are left out.
+    Open,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TodoLimit {
+    max_todos: usize,
+    counted: CountedTodos,
+}
+
+impl TodoLimit {
+    fn check(&self, store: &LockedTodos<'_>, adding: usize) -> Result<(), RepositoryError> {
+        let count = store
+            .values()
+            .filter(|todo| match self.counted {
+                CountedTodos::All => !todo.is_deleted(),
+                CountedTodos::Open => todo.is_listed() && !todo.completed,
+            })
+            .count();
+        if count + adding > self.max_todos {
+            return Err(RepositoryError::CapacityExceeded(self.max_todos));
+        }
+
+        Ok(())
+    }
+}
+
+// What happens to the children of a todo when it is deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnParentDelete {
+    // Children are kept and become top-level todos.
+    #[default]
+    Orphan,
+    // Children, and their children in turn, are deleted too.
+    Cascade,
+}
+
+// A file the todo refers to. Names are unique within a todo.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Attachment {
+    pub name: String,
+    pub url: String,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct Todo {
+    id: i32,
+    text: String,
+    completed: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    due_date: Option<DateTime<Utc>>,
+    priority: Priority,
+    #[cfg_attr(feature = "sqlx", sqlx(json))]
+    tags: Vec<String>,
+    deleted_at: Option<DateTime<Utc>>,
+    position: i32,
+    parent_id: Option<i32>,
+    #[cfg_attr(feature = "sqlx", sqlx(try_from = "i64"))]
+    version: u32,
+    archived: bool,
+    recurrence: Option<Recurrence>,
+    description: Option<String>,
+    assignee: Option<String>,
+    // A `#RRGGBB` hex color.
+    color: Option<String>,
+    // Ids of the todos that must be completed before this one can start.
+    #[serde(default)]
+    #[cfg_attr(feature = "sqlx", sqlx(json))]
+    blocked_by: Vec<i32>,
+    #[serde(default)]
+    #[cfg_attr(feature = "sqlx", sqlx(flatten, try_from = "EstimateColumn"))]
+    estimate_minutes: Option<u32>,
+    #[serde(default)]
+    #[cfg_attr(feature = "sqlx", sqlx(try_from = "i64"))]
+    spent_minutes: u32,
+    #[serde(default)]
+    #[cfg_attr(feature = "sqlx", sqlx(json))]
+    attachments: Vec<Attachment>,
+}
+
+// sqlx cannot decode an `Option<u32>` column directly, so the estimate is read
+// as an `i64` and checked on the way.
+#[cfg(feature = "sqlx")]
+#[derive(sqlx::FromRow)]
+struct EstimateColumn {
+    estimate_minutes: Option<i64>,
+}
+
+#[cfg(feature = "sqlx")]
+impl TryFrom<EstimateColumn> for Option<u32> {
+    type Error = std::num::TryFromIntError;
+
+    fn try_from(column: EstimateColumn) -> Result<Self, Self::Error> {
+        column.estimate_minutes.map(u32::try_from).transpose()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct CreateTodo {
+    // Lets offline clients pick ids before syncing. Left out, the repository
+    // assigns one.
+    id: Option<i32>,
+    text: String,
+    due_date: Option<DateTime<Utc>>,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    tags: Vec<String>,
+    parent_id: Option<i32>,
+    recurrence: Option<Recurrence>,
+    description: Option<String>,
+    assignee: Option<String>,
+    color: Option<String>,
+    #[serde(default)]
+    blocked_by: Vec<i32>,
+    estimate_minutes: Option<u32>,
+    // Lets already finished work be logged. Defaults to incomplete.
+    completed: Option<bool>,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct UpdateTodo {
+    text: Option<String>,
+    completed: Option<bool>,
+    due_date: Option<DateTime<Utc>>,
+    priority: Option<Priority>,
+    tags: Option<Vec<String>>,
+    // `None` leaves the description alone and `Some(None)` clears it. In
+    // JSON a missing field means the former and `null` the latter.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    description: Option<Option<String>>,
+    // Same as `description`: `Some(None)` unassigns the todo.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    assignee: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    color: Option<Option<String>>,
+    blocked_by: Option<Vec<i32>>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    estimate_minutes: Option<Option<u32>>,
+    attachments: Option<Vec<Attachment>>,
+}
+
+// Wraps whatever was given, `null` included, so it can be told apart from a
+// missing field.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// Criteria are combined with AND; a `None` criterion matches every todo.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct TodoFilter {
+    pub completed: Option<bool>,
+    pub tag: Option<String>,
+    pub due_before: Option<DateTime<Utc>>,
+    // Matched case-insensitively.
+    pub text_contains: Option<String>,
+}
+
+impl TodoFilter {
+    fn matches(&self, todo: &Todo) -> bool {
+        self.completed
+            .is_none_or(|completed| todo.completed == completed)
+            && self.tag.as_ref().is_none_or(|tag| todo.tags.contains(tag))
+            && self
+                .due_before
+                .is_none_or(|before| todo.due_date.is_some_and(|due| due < before))
+            && self
+                .text_contains
+                .as_ref()
+                .is_none_or(|text| todo.text.to_lowercase().contains(&text.to_lowercase()))
+    }
+
+    // Parses space separated terms such as `completed:false tag:work
+    // due<2024-01-01`. `text:` and bare words are searched for in the text,
+    // bare words joined back together with single spaces. A due date is
+    // either a plain date, taken as midnight UTC, or an RFC 3339 timestamp.
+    pub fn parse(query: &str) -> Result<Self, RepositoryError> {
+        let invalid = |problem| RepositoryError::Validation(vec![problem]);
+        let mut filter = TodoFilter::default();
+        let mut words = Vec::new();
+        for term in query.split_whitespace() {
+            if let Some(value) = term.strip_prefix("due<") {
+                let due_before = parse_query_date(value).ok_or_else(|| {
+                    invalid(format!(
+                        "`{}` is not a date, expected e.g. due<2024-01-01",
+                        value
+                    ))
+                })?;
+                set_once(&mut filter.due_before, "due", due_before)?;
+                continue;
+            }
+
+            let Some((key, value)) = term.split_once(':') else {
+                if term.contains(['<', '>', '=']) {
+                    return Err(invalid(format!(
+                        "`{}` is not a valid term, only due< compares",
+                        term
+                    )));
+                }
+                words.push(term);
+                continue;
+            };
+            if value.is_empty() {
+                return Err(invalid(format!("`{}` is missing a value", key)));
+            }
+            match key {
+                "completed" => {
+                    let completed = value.parse().map_err(|_| {
+                        invalid(format!(
+                            "`{}` is not a boolean, expected completed:true or completed:false",
+                            value
+                        ))
+                    })?;
+                    set_once(&mut filter.completed, key, completed)?;
+                }
+                "tag" => set_once(&mut filter.tag, key, value.to_string())?,
+                "text" => set_once(&mut filter.text_contains, key, value.to_string())?,
+                _ => {
+                    return Err(invalid(format!(
+                        "unknown field `{}`, expected completed, tag, text or due",
+                        key
+                    )))
+                }
+            }
+        }
+        if !words.is_empty() {
+            set_once(&mut filter.text_contains, "text", words.join(" "))?;
+        }
+
+        Ok(filter)
+    }
+}
+
+fn set_once<T>(slot: &mut Option<T>, key: &str, value: T) -> Result<(), RepositoryError> {
+    if slot.is_some() {
+        return Err(RepositoryError::Validation(vec![format!(
+            "`{}` is given more than once",
+            key
+        )]));
+    }
+    *slot = Some(value);
+
+    Ok(())
+}
+
+fn parse_query_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some(date.and_hms_opt(0, 0, 0)?.and_utc());
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|due| due.with_timezone(&Utc))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct BulkDeleteReport {
+    deleted: Vec<i32>,
+    not_found: Vec<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompleteOutcome {
+    JustCompleted(Todo),
+    AlreadyComplete(Todo),
+}
+
+// One change in a batch for `apply`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum TodoOp {
+    Create(CreateTodo),
+    Update { id: i32, payload: UpdateTodo },
+    Delete(i32),
+}
+
+// What came of one op in `apply`. With `all_or_nothing`, every op but the
+// failed one is `RolledBack`, whether it ran or not.
+#[derive(Debug)]
+pub enum OpResult {
+    Created(Todo),
+    Updated(Todo),
+    Deleted(i32),
+    Failed(anyhow::Error),
+    RolledBack,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    field: String,
+    before: serde_json::Value,
+    after: serde_json::Value,
+}
+
+// The fields that differ between two todos, by their JSON names in
+// alphabetical order.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Default)]
+pub struct TodoDiff {
+    changes: Vec<FieldChange>,
+}
+
+impl TodoDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+// Compares the todos as they serialize, so fields added to `Todo` later are
+// picked up without touching this.
+pub fn diff(old: &Todo, new: &Todo) -> TodoDiff {
+    let fields = |todo: &Todo| match serde_json::to_value(todo) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => unreachable!("a todo serializes to a JSON object"),
+    };
+    let (before, mut after) = (fields(old), fields(new));
+    let changes = before
+        .into_iter()
+        .filter_map(|(field, before)| {
+            let after = after.remove(&field).unwrap_or_default();
+            (before != after).then_some(FieldChange {
+                field,
+                before,
+                after,
+            })
+        })
+        .collect();
+
+    TodoDiff { changes }
+}
+
+// The update that turns `current` into `desired`, with only the fields that
+// differ set. An update cannot clear a due date, so a `desired` without one
+// keeps the current one.
+pub fn minimal_update(current: &Todo, desired: &Todo) -> UpdateTodo {
+    fn changed<T: PartialEq + Clone>(current: &T, desired: &T) -> Option<T> {
+        (current != desired).then(|| desired.clone())
+    }
+
+    UpdateTodo {
+        text: changed(&current.text, &desired.text),
+        completed: changed(&current.completed, &desired.completed),
+        due_date: changed(&current.due_date, &desired.due_date).flatten(),
+        priority: changed(&current.priority, &desired.priority),
+        tags: changed(&current.tags, &desired.tags),
+        description: changed(&current.description, &desired.description),
+        assignee: changed(&current.assignee, &desired.assignee),
+        color: changed(&current.color, &desired.color),
+        blocked_by: changed(&current.blocked_by, &desired.blocked_by),
+        estimate_minutes: changed(&current.estimate_minutes, &desired.estimate_minutes),
+        attachments: changed(&current.attachments, &desired.attachments),
+    }
+}
+
+// How `export` and `import_from` write todos down. The formats other than
+// JSON each need their feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SerializationFormat {
+    #[default]
+    Json,
+    #[cfg(feature = "toml")]
+    Toml,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+// TOML documents are tables, so the list goes under a `todos` key.
+#[cfg(feature = "toml")]
+#[derive(Serialize, Deserialize)]
+struct TomlTodos {
+    todos: Vec<Todo>,
+}
+
+impl SerializationFormat {
+    fn serialize(self, todos: &[Todo]) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            SerializationFormat::Json => serde_json::to_vec(todos)?,
+            #[cfg(feature = "toml")]
+            SerializationFormat::Toml => toml::to_string(&TomlTodos {
+                todos: todos.to_vec(),
+            })?
+            .into_bytes(),
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => rmp_serde::to_vec_named(todos)?,
+        })
+    }
+
+    fn deserialize(self, data: &[u8]) -> anyhow::Result<Vec<Todo>> {
+        Ok(match self {
+            SerializationFormat::Json => serde_json::from_slice(data)?,
+            #[cfg(feature = "toml")]
+            SerializationFormat::Toml => {
+                toml::from_str::<TomlTodos>(std::str::from_utf8(data)?)?.todos
+            }
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => rmp_serde::from_slice(data)?,
+        })
+    }
+}
+
+// What `import` does with a todo whose id is already taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    // Keeps the stored todo.
+    #[default]
+    Skip,
+    Overwrite,
+    // Imports nothing at all.
+    Fail,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct ImportReport {
+    imported: Vec<i32>,
+    skipped: Vec<i32>,
+    overwritten: Vec<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct Page<T> {
+    items: Vec<T>,
+    total: usize,
+    offset: usize,
+    limit: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct TodoStats {
+    total: usize,
+    completed: usize,
+    incomplete: usize,
+    overdue: usize,
+}
+
+impl TodoStats {
+    fn collect<'a>(todos: impl Iterator<Item = &'a Todo>, now: DateTime<Utc>) -> Self {
+        todos.fold(Self::default(), |mut stats, todo| {
+            stats.total += 1;
+            if todo.completed {
+                stats.completed += 1;
+            } else {
+                stats.incomplete += 1;
+                if todo.due_date.is_some_and(|due| due < now) {
+                    stats.overdue += 1;
+                }
+            }
+            stats
+        })
+    }
+}
+
+impl Todo {
+    pub fn new(id: i32, text: String) -> Self {
+        let now = Utc::now();
+        Self {
+            id,
+            text,
+            completed: false,
+            created_at: now,
+            updated_at: now,
+            due_date: None,
+            priority: Priority::default(),
+            tags: Vec::new(),
+            deleted_at: None,
+            position: 0,
+            parent_id: None,
+            version: 1,
+            archived: false,
+            recurrence: None,
+            description: None,
+            assignee: None,
+            color: None,
+            blocked_by: Vec::new(),
+            estimate_minutes: None,
+            spent_minutes: 0,
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn share_token(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap())
+    }
+
+    // The decoded todo is not stored anywhere; it keeps the id it had where
+    // the token was made.
+    pub fn from_share_token(token: &str) -> anyhow::Result<Self> {
+        let malformed = || RepositoryError::Validation(vec!["malformed share token".to_string()]);
+        let json = URL_SAFE_NO_PAD.decode(token).context(malformed())?;
+
+        serde_json::from_slice(&json).context(malformed())
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    // Bytes allocated for the todo's strings and lists, by capacity.
+    fn heap_bytes(&self) -> usize {
+        let optional = [&self.description, &self.assignee, &self.color];
+
+        self.text.capacity()
+            + self.tags.capacity() * std::mem::size_of::<String>()
+            + self.tags.iter().map(String::capacity).sum::<usize>()
+            + optional
+                .iter()
+                .filter_map(|text| text.as_ref().map(String::capacity))
+                .sum::<usize>()
+            + self.blocked_by.capacity() * std::mem::size_of::<i32>()
+            + self.attachments.capacity() * std::mem::size_of::<Attachment>()
+            + self
+                .attachments
+                .iter()
+                .map(|attachment| attachment.name.capacity() + attachment.url.capacity())
+                .sum::<usize>()
+    }
+
+    // Whether the todo shows up in `all` and the counts built on it.
+    fn is_listed(&self) -> bool {
+        !self.is_deleted() && !self.archived
+    }
+
+    fn from_payload(id: i32, payload: CreateTodo) -> Self {
+        Self::from_payload_at(id, payload, Utc::now())
+    }
+
+    fn from_payload_at(id: i32, payload: CreateTodo, now: DateTime<Utc>) -> Self {
+        Self {
+            created_at: now,
+            updated_at: now,
+            due_date: payload.due_date,
+            priority: payload.priority,
+            tags: dedup_tags(payload.tags),
+            parent_id: payload.parent_id,
+            recurrence: payload.recurrence,
+            description: payload.description,
+            assignee: payload.assignee,
+            color: payload.color,
+            blocked_by: payload.blocked_by,
+            estimate_minutes: payload.estimate_minutes,
+            completed: payload.completed.unwrap_or(false),
+            attachments: payload.attachments,
+            ..Self::new(id, payload.text)
+        }
+    }
+
+    // The todo that replaces a recurring one once `self` has been updated to
+    // `completed`.
+    fn next_occurrence(&self, completed: &Todo) -> Option<CreateTodo> {
+        if self.completed || !completed.completed {
+            return None;
+        }
+
+        completed.rescheduled()
+    }
+
+    // A fresh copy of a recurring todo, due one interval after this one.
+    fn rescheduled(&self) -> Option<CreateTodo> {
+        let recurrence = self.recurrence?;
+
+        Some(CreateTodo {
+            id: None,
+            text: self.text.clone(),
+            due_date: self
+                .due_date
+                .map(|due_date| due_date + recurrence.interval()),
+            priority: self.priority,
+            tags: self.tags.clone(),
+            parent_id: self.parent_id,
+            recurrence: Some(recurrence),
+            description: self.description.clone(),
+            assignee: self.assignee.clone(),
+            color: self.color.clone(),
+            blocked_by: self.blocked_by.clone(),
+            estimate_minutes: self.estimate_minutes,
+            completed: None,
+            attachments: self.attachments.clone(),
+        })
+    }
+
+    // The update that adds `other`'s tags to this todo's and appends its
+    // description to this one.
+    fn absorbing(&self, other: &Todo) -> UpdateTodo {
+        let description = match (&self.description, &other.description) {
+            (Some(mine), Some(theirs)) => Some(format!("{}\n\n{}", mine, theirs)),
+            (mine, theirs) => mine.clone().or(theirs.clone()),
+        };
+
+        UpdateTodo {
+            tags: Some([self.tags.clone(), other.tags.clone()].concat()),
+            description: Some(description),
+            ..UpdateTodo::default()
+        }
+    }
+
+    fn updated(&self, payload: UpdateTodo) -> Self {
+        self.updated_as_of(payload, Utc::now())
+    }
+
+    fn updated_as_of(&self, payload: UpdateTodo, now: DateTime<Utc>) -> Self {
+        Self {
+            text: payload.text.unwrap_or(self.text.clone()),
+            completed: payload.completed.unwrap_or(self.completed),
+            due_date: payload.due_date.or(self.due_date),
+            priority: payload.priority.unwrap_or(self.priority),
+            tags: payload.tags.map(dedup_tags).unwrap_or(self.tags.clone()),
+            description: payload.description.unwrap_or(self.description.clone()),
+            assignee: payload.assignee.unwrap_or(self.assignee.clone()),
+            color: payload.color.unwrap_or(self.color.clone()),
+            blocked_by: payload.blocked_by.unwrap_or(self.blocked_by.clone()),
+            estimate_minutes: payload.estimate_minutes.unwrap_or(self.estimate_minutes),
+            attachments: payload.attachments.unwrap_or(self.attachments.clone()),
+            updated_at: now,
+            version: self.version + 1,
+            ..self.clone()
+        }
+    }
+}
+
+impl CreateTodo {
+    pub fn new(text: String) -> Self {
+        Self {
+            id: None,
+            text,
+            due_date: None,
+            priority: Priority::default(),
+            tags: Vec::new(),
+            parent_id: None,
+            recurrence: None,
+            description: None,
+            assignee: None,
+            color: None,
+            blocked_by: Vec::new(),
+            estimate_minutes: None,
+            completed: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn builder() -> CreateTodoBuilder {
+        CreateTodoBuilder::default()
+    }
+
+    fn validate(&self) -> Result<(), RepositoryError> {
+        if self.text.trim().is_empty() {
+            return Err(RepositoryError::Validation(vec![
+                "text must not be empty".to_string()
+            ]));
+        }
+        if let Some(color) = &self.color {
+            validate_color(color)?;
+        }
+        if let Some(due_date) = self.due_date {
+            validate_due_date(due_date)?;
+        }
+        if self.id.is_some_and(|id| id <= 0) {
+            return Err(RepositoryError::Validation(vec![
+                "id must be positive".to_string()
+            ]));
+        }
+        validate_attachments(&self.attachments)?;
+
+        Ok(())
+    }
+
+    // For repositories where the database hands out the ids.
+    fn reject_client_id(&self) -> Result<(), RepositoryError> {
+        match self.id {
+            Some(_) => Err(RepositoryError::Validation(vec![
+                "ids are assigned by the database".to_string(),
+            ])),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<&str> for CreateTodo {
+    fn from(text: &str) -> Self {
+        Self::new(text.to_string())
+    }
+}
+
+impl From<String> for CreateTodo {
+    fn from(text: String) -> Self {
+        Self::new(text)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CreateTodoBuilder {
+    id: Option<i32>,
+    text: Option<String>,
+    due_date: Option<DateTime<Utc>>,
+    priority: Priority,
+    tags: Vec<String>,
+    parent_id: Option<i32>,
+    recurrence: Option<Recurrence>,
+    description: Option<String>,
+    assignee: Option<String>,
+    color: Option<String>,
+    blocked_by: Vec<i32>,
+    estimate_minutes: Option<u32>,
+    completed: Option<bool>,
+    attachments: Vec<Attachment>,
+}
+
+impl CreateTodoBuilder {
+    pub fn id(mut self, id: i32) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    pub fn due_date(mut self, due_date: DateTime<Utc>) -> Self {
+        self.due_date = Some(due_date);
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn parent_id(mut self, parent_id: i32) -> Self {
+        self.parent_id = Some(parent_id);
+        self
+    }
+
+    pub fn recurrence(mut self, recurrence: Recurrence) -> Self {
+        self.recurrence = Some(recurrence);
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn assignee(mut self, assignee: impl Into<String>) -> Self {
+        self.assignee = Some(assignee.into());
+        self
+    }
+
+    pub fn color(mut self, color: impl Into<String>) -> Self {
+        self.color = Some(color.into());
+        self
+    }
+
+    pub fn blocked_by(mut self, id: i32) -> Self {
+        self.blocked_by.push(id);
+        self
+    }
+
+    pub fn estimate_minutes(mut self, estimate_minutes: u32) -> Self {
+        self.estimate_minutes = Some(estimate_minutes);
+        self
+    }
+
+    pub fn completed(mut self, completed: bool) -> Self {
+        self.completed = Some(completed);
+        self
+    }
+
+    pub fn attachment(mut self, attachment: Attachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    pub fn build(self) -> Result<CreateTodo, RepositoryError> {
+        let text = self
+            .text
+            .ok_or_else(|| RepositoryError::Validation(vec!["text is required".to_string()]))?;
+
+        Ok(CreateTodo {
+            id: self.id,
+            text,
+            due_date: self.due_date,
+            priority: self.priority,
+            tags: self.tags,
+            parent_id: self.parent_id,
+            recurrence: self.recurrence,
+            description: self.description,
+            assignee: self.assignee,
+            color: self.color,
+            blocked_by: self.blocked_by,
+            estimate_minutes: self.estimate_minutes,
+            completed: self.completed,
+            attachments: self.attachments,
+        })
+    }
+}
+
+impl UpdateTodo {
+    fn completed(completed: bool) -> Self {
+        Self {
+            completed: Some(completed),
+            ..Self::default()
+        }
+    }
+
+    // Checks every field before giving up, so one `Validation` error lists
+    // all the problems.
+    fn validate(&self, id: i32) -> Result<(), RepositoryError> {
+        let mut problems = Vec::new();
+        if self
+            .text
+            .as_ref()
+            .is_some_and(|text| text.trim().is_empty())
+        {
+            problems.push("text must not be empty".to_string());
+        }
+        if let Some(Some(color)) = &self.color {
+            if let Err(RepositoryError::Validation(found)) = validate_color(color) {
+                problems.extend(found);
+            }
+        }
+        if let Some(due_date) = self.due_date {
+            if let Err(RepositoryError::Validation(found)) = validate_due_date(due_date) {
+                problems.extend(found);
+            }
+        }
+        if self
+            .blocked_by
+            .as_ref()
+            .is_some_and(|blocked_by| blocked_by.contains(&id))
+        {
+            problems.push("a todo cannot be blocked by itself".to_string());
+        }
+        if let Some(attachments) = &self.attachments {
+            if let Err(RepositoryError::Validation(found)) = validate_attachments(attachments) {
+                problems.extend(found);
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(RepositoryError::Validation(problems))
+    }
+}
+
+fn validate_color(color: &str) -> Result<(), RepositoryError> {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        return Err(RepositoryError::Validation(vec![format!(
+            "color must look like #RRGGBB, got {:?}",
+            color
+        )]));
+    }
+
+    Ok(())
+}
+
+// Nothing can be due before the Unix epoch; a date that early is almost
+// always a zeroed or unset timestamp on the client's side.
+fn validate_due_date(due_date: DateTime<Utc>) -> Result<(), RepositoryError> {
+    if due_date < DateTime::UNIX_EPOCH {
+        return Err(RepositoryError::Validation(vec![format!(
+            "due date must not be before 1970-01-01, got {}",
+            due_date.to_rfc3339()
+        )]));
+    }
+
+    Ok(())
+}
+
+fn validate_attachments(attachments: &[Attachment]) -> Result<(), RepositoryError> {
+    if attachments
+        .iter()
+        .any(|attachment| attachment.url.trim().is_empty())
+    {
+        return Err(RepositoryError::Validation(vec![
+            "attachment url must not be empty".to_string(),
+        ]));
+    }
+    let mut names = HashSet::new();
+    if let Some(attachment) = attachments
+        .iter()
+        .find(|attachment| !names.insert(attachment.name.as_str()))
+    {
+        return Err(RepositoryError::Validation(vec![format!(
+            "attachment {:?} appears more than once",
+            attachment.name
+        )]));
+    }
+
+    Ok(())
+}
+
+type TodoDates = Entries<Todo>;
+type LockedTodos<'a> = Locked<'a, Todo>;
+
+fn find_live<'a>(store: &'a LockedTodos<'_>, id: i32) -> Option<&'a Todo> {
+    store.get(&id).filter(|todo| !todo.is_deleted())
+}
+
+// Incomplete children of `parent_id` that are not soft-deleted, by id.
+fn open_children(store: &LockedTodos<'_>, parent_id: i32) -> Vec<Todo> {
+    let children = store
+        .values()
+        .filter(|todo| todo.parent_id == Some(parent_id) && !todo.is_deleted() && !todo.completed)
+        .cloned()
+        .collect();
+
+    sorted_by_id(children)
+}
+
+fn check_parent(store: &LockedTodos<'_>, payload: &CreateTodo) -> Result<(), RepositoryError> {
+    match payload.parent_id {
+        Some(parent_id) if find_live(store, parent_id).is_none() => {
+            Err(RepositoryError::NotFound(parent_id))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_blockers(store: &LockedTodos<'_>, blocked_by: &[i32]) -> Result<(), RepositoryError> {
+    match blocked_by
+        .iter()
+        .find(|id| find_live(store, **id).is_none())
+    {
+        Some(id) => Err(RepositoryError::NotFound(*id)),
+        None => Ok(()),
+    }
+}
+
+// Whether `ancestor_id` is somewhere up `todo`'s chain of parents.
+fn is_descendant(store: &LockedTodos<'_>, todo: &Todo, ancestor_id: i32) -> bool {
+    let mut parent_id = todo.parent_id;
+    for _ in 0..store.len() {
+        match parent_id {
+            Some(id) if id == ancestor_id => return true,
+            Some(id) => parent_id = store.get(&id).and_then(|parent| parent.parent_id),
+            None => return false,
+        }
+    }
+
+    false
+}
+
+fn remove_todo(
+    store: &mut LockedTodos<'_>,
+    id: i32,
+    on_parent_delete: OnParentDelete,
+) -> Option<Todo> {
+    let mut removed = Vec::new();
+    remove_with_children(store, id, on_parent_delete, &mut removed);
+
+    removed.into_iter().next()
+}
+
+// Pushes the todo onto `removed`, followed by the children deleted along
+// with it.
+fn remove_with_children(
+    store: &mut LockedTodos<'_>,
+    id: i32,
+    on_parent_delete: OnParentDelete,
+    removed: &mut Vec<Todo>,
+) {
+    let Some(todo) = store.remove(&id) else {
+        return;
+    };
+    removed.push(todo);
+
+    let children: Vec<i32> = store
+        .values()
+        .filter(|child| child.parent_id == Some(id))
+        .map(|child| child.id)
+        .collect();
+    for child_id in children {
+        match on_parent_delete {
+            OnParentDelete::Orphan => {
+                if let Some(child) = store.get_mut(&child_id) {
+                    child.parent_id = None;
+                    child.version += 1;
+                }
+            }
+            OnParentDelete::Cascade => {
+                remove_with_children(store, child_id, on_parent_delete, removed);
+            }
+        }
+    }
+}
+
+fn inconsistent(problem: String) -> anyhow::Error {
+    RepositoryError::Inconsistent(problem).into()
+}
+
+fn next_position(store: &LockedTodos<'_>) -> i32 {
+    store
+        .values()
+        .filter(|todo| !todo.is_deleted())
+        .map(|todo| todo.position + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+// Sent after a change to the store has been made. Deleting a todo announces
+// only the todo itself, not the children deleted or orphaned along with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TodoEvent {
+    Created(Todo),
+    Updated(Todo),
+    Deleted(i32),
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+// `before` is `None` for a created todo and `after` for a deleted one.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct ChangeLogEntry {
+    // The store's version once this change was made.
+    version: u64,
+    timestamp: DateTime<Utc>,
+    kind: ChangeKind,
+    id: i32,
+    before: Option<Todo>,
+    after: Option<Todo>,
+}
+
+// Keeps the newest `capacity` entries.
+#[derive(Debug)]
+struct ChangeLog {
+    entries: VecDeque<ChangeLogEntry>,
+    capacity: usize,
+}
+
+// The ids of the stored todos carrying each tag, soft-deleted and archived
+// ones included, so `find_by_tag` only looks at the todos it returns. It is
+// changed with the todos it indexes write-locked, and always locked after
+// the store.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct TagIndex {
+    ids: HashMap<String, HashSet<i32>>,
+}
+
+impl TagIndex {
+    fn build(store: &LockedTodos<'_>) -> Self {
+        let mut index = Self::default();
+        store.values().for_each(|todo| index.add(todo));
+
+        index
+    }
+
+    fn add(&mut self, todo: &Todo) {
+        for tag in todo.tags.iter() {
+            self.ids.entry(tag.clone()).or_default().insert(todo.id);
+        }
+    }
+
+    fn remove(&mut self, todo: &Todo) {
+        for tag in todo.tags.iter() {
+            if let Some(ids) = self.ids.get_mut(tag) {
+                ids.remove(&todo.id);
+                if ids.is_empty() {
+                    self.ids.remove(tag);
+                }
+            }
+        }
+    }
+
+    fn ids(&self, tag: &str) -> Vec<i32> {
+        let mut ids: Vec<i32> = self
+            .ids
+            .get(tag)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default();
+        ids.sort_unstable();
+
+        ids
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoSnapshot {
+    todos: TodoDates,
+    last_id: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForMemory {
+    store: InMemoryStore<Todo>,
+    on_parent_delete: OnParentDelete,
+    cascade_mode: CascadeMode,
+    normalize_text: bool,
+    text_limit: TextLimit,
+    events: Option<broadcast::Sender<TodoEvent>>,
+    metrics: Arc<dyn Metrics>,
+    changes: Option<Arc<Mutex<ChangeLog>>>,
+    // Goes up by one with every change `subscribe` would announce.
+    version: Arc<AtomicU64>,
+    max_completed: Option<usize>,
+    todo_limit: Option<TodoLimit>,
+    clock: Arc<dyn Clock>,
+    tag_index: Arc<Mutex<TagIndex>>,
+}
+
+impl TodoRepositoryForMemory {
+    pub fn new() -> Self {
+        Self {
+            store: InMemoryStore::new(),
+            on_parent_delete: OnParentDelete::default(),
+            cascade_mode: CascadeMode::default(),
+            normalize_text: false,
+            text_limit: TextLimit::default(),
+            events: None,
+            metrics: Arc::new(NoopMetrics),
+            changes: None,
+            version: Arc::default(),
+            max_completed: None,
+            todo_limit: None,
+            clock: Arc::new(SystemClock),
+            tag_index: Arc::default(),
+        }
+    }
+
+    fn from_store(store: InMemoryStore<Todo>) -> Self {
+        let tag_index = TagIndex::build(&store.read());
+        Self {
+            store,
+            on_parent_delete: OnParentDelete::default(),
+            cascade_mode: CascadeMode::default(),
+            normalize_text: false,
+            text_limit: TextLimit::default(),
+            events: None,
+            metrics: Arc::new(NoopMetrics),
+            changes: None,
+            version: Arc::default(),
+            max_completed: None,
+            todo_limit: None,
+            clock: Arc::new(SystemClock),
+            tag_index: Arc::new(Mutex::new(tag_index)),
+        }
+    }
+
+    // Unlike bulk_create, the todos keep their ids. New todos are numbered
+    // after the largest seeded id.
+    pub fn seeded(todos: Vec<Todo>) -> Self {
+        let last_id = todos.iter().map(|todo| todo.id).max().unwrap_or(0);
+        let store: TodoDates = todos.into_iter().map(|todo| (todo.id, todo)).collect();
+
+        Self::from_store(InMemoryStore::from_entries(store, last_id))
+    }
+
+    // Rebuilds a store by replaying `entries` in order. Updates and deletes of
+    // ids created before the first entry are skipped, so a log that has
+    // already dropped its oldest entries only brings back the todos created
+    // within it. Soft deletes are logged as deletes, so soft-deleted todos are
+    // left out until a logged `restore` brings them back. Deletes orphan
+    // children, as `OnParentDelete::Orphan` does. Changes that are never
+    // logged, such as `restore_snapshot`, are lost.
+    pub fn from_change_log(entries: Vec<ChangeLogEntry>) -> Self {
+        let todos = InMemoryStore::new();
+        let mut store = todos.write();
+        let mut created = HashSet::new();
+        let mut last_id = 0;
+        for entry in entries {
+            match (entry.kind, entry.after) {
+                (ChangeKind::Created, Some(todo)) => {
+                    last_id = last_id.max(entry.id);
+                    created.insert(entry.id);
+                    store.insert(entry.id, todo);
+                }
+                (ChangeKind::Updated, Some(todo)) if created.contains(&entry.id) => {
+                    store.insert(entry.id, todo);
+                }
+                (ChangeKind::Deleted, _) => {
+                    remove_todo(&mut store, entry.id, OnParentDelete::Orphan);
+                }
+                _ => {}
+            }
+        }
+        drop(store);
+        todos.set_last_id(last_id);
+
+        Self::from_store(todos)
+    }
+
+    pub fn with_on_parent_delete(mut self, on_parent_delete: OnParentDelete) -> Self {
+        self.on_parent_delete = on_parent_delete;
+        self
+    }
+
+    // Applies when `update`, `update_if_version`, `replace`,
+    // `toggle_completed`, `complete`, `toggle_many`, `complete_where`,
+    // `update_where` or `complete_all` complete a todo. `complete_all`
+    // completes every open child along with its parent, so it is never
+    // blocked.
+    pub fn with_cascade_mode(mut self, cascade_mode: CascadeMode) -> Self {
+        self.cascade_mode = cascade_mode;
+        self
+    }
+
+    // Trims the text of created and updated todos and collapses each run of
+    // whitespace inside it to a single space. Off by default.
+    pub fn with_normalized_text(mut self, normalize_text: bool) -> Self {
+        self.normalize_text = normalize_text;
+        self
+    }
+
+    // Defaults to 10,000 characters, rejecting anything longer.
+    pub fn with_max_text_len(mut self, max_text_len: usize, overlong: OverlongText) -> Self {
+        self.text_limit = TextLimit {
+            max_len: max_text_len,
+            overlong,
+        };
+        self
+    }
+
+    // Keeps the todos in id order, so `all` comes back sorted by id without
+    // sorting, at the cost of O(log n) lookups.
+    pub fn with_ordered_storage(self) -> Self {
+        self.store.make_ordered();
+        self
+    }
+
+    // Replaces the default 1, 2, 3, ... numbering.
+    pub fn with_id_generator(mut self, ids: impl IdGenerator + 'static) -> Self {
+        self.store = self.store.with_id_generator(ids);
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Keeps up to `capacity` events for each subscriber that falls behind.
+    pub fn with_events(mut self, capacity: usize) -> Self {
+        self.events = Some(broadcast::channel(capacity).0);
+        self
+    }
+
+    // Creating more todos than `max_todos` fails with
+    // `RepositoryError::CapacityExceeded`, and nothing is stored. The next
+    // occurrence of a recurring todo is still created, so completing one only
+    // fails once ids run out, and `import` is not limited either.
+    pub fn with_max_todos(mut self, max_todos: usize, counted: CountedTodos) -> Self {
+        self.todo_limit = Some(TodoLimit { max_todos, counted });
+        self
+    }
+
+    // Once more than `max_completed` listed todos are completed, the ones with
+    // the lowest ids are deleted until the cap is met again, each with its own
+    // `Deleted` event. Incomplete todos are never evicted, not even the
+    // children of an evicted todo, which are orphaned instead.
+    pub fn with_max_completed(mut self, max_completed: usize) -> Self {
+        self.max_completed = Some(max_completed);
+        self
+    }
+
+    // Records every change `subscribe` would announce, along with the todo
+    // from before it. Once `capacity` entries are held the oldest is dropped.
+    pub fn with_change_log(mut self, capacity: usize) -> Self {
+        self.changes = Some(Arc::new(Mutex::new(ChangeLog {
+            entries: VecDeque::new(),
+            capacity,
+        })));
+        self
+    }
+
+    // Oldest first. Empty without `with_change_log`.
+    pub fn change_log(&self) -> Vec<ChangeLogEntry> {
+        match &self.changes {
+            Some(changes) => changes
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entries
+                .iter()
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    // The current version and the change log entries made after `version`,
+    // oldest first. Pass the returned version to the next call to pull only
+    // what changed in between. Entries the log has already dropped are
+    // missing, so poll more often than the log fills up.
+    pub fn changes_since(&self, version: u64) -> (u64, Vec<ChangeLogEntry>) {
+        let Some(changes) = &self.changes else {
+            return (self.version(), Vec::new());
+        };
+
+        let changes = changes.lock().unwrap_or_else(PoisonError::into_inner);
+        let entries = changes
+            .entries
+            .iter()
+            .filter(|entry| entry.version > version)
+            .cloned()
+            .collect();
+        (self.version(), entries)
+    }
+
+    // Without `with_events` nothing is ever sent, so the receiver reports the
+    // channel as closed straight away.
+    pub fn subscribe(&self) -> broadcast::Receiver<TodoEvent> {
+        match &self.events {
+            Some(events) => events.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
+
+    // Callers must release the store lock first, so a subscriber reacting to
+    // the event can read the change it announces. `before` is the todo as it
+    // was before the change, for the change log.
+    fn notify(&self, before: Option<Todo>, event: TodoEvent) {
+        match &event {
+            TodoEvent::Created(todo) => self.metrics.on_create(todo.id),
+            TodoEvent::Updated(todo) => self.metrics.on_update(todo.id),
+            TodoEvent::Deleted(id) => self.metrics.on_delete(*id),
+        }
+        if let Some(changes) = &self.changes {
+            let (kind, id, after) = match &event {
+                TodoEvent::Created(todo) => (ChangeKind::Created, todo.id, Some(todo.clone())),
+                TodoEvent::Updated(todo) => (ChangeKind::Updated, todo.id, Some(todo.clone())),
+                TodoEvent::Deleted(id) => (ChangeKind::Deleted, *id, None),
+            };
+            // Bumped under the log's lock, so `changes_since` never sees a
+            // version without its entry.
+            let mut changes = changes.lock().unwrap_or_else(PoisonError::into_inner);
+            changes.entries.push_back(ChangeLogEntry {
+                version: self.version.fetch_add(1, Ordering::SeqCst) + 1,
+                timestamp: self.now(),
+                kind,
+                id,
+                before,
+                after,
+            });
+            while changes.entries.len() > changes.capacity {
+                changes.entries.pop_front();
+            }
+        } else {
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+        let completed = match &event {
+            TodoEvent::Created(todo) | TodoEvent::Updated(todo) => todo.completed,
+            TodoEvent::Deleted(_) => false,
+        };
+        if let Some(events) = &self.events {
+            // Sending only fails when nobody is subscribed.
+            let _ = events.send(event);
+        }
+        if completed {
+            self.evict_completed();
+        }
+    }
+
+    fn evict_completed(&self) {
+        let Some(max_completed) = self.max_completed else {
+            return;
+        };
+
+        let mut store = self.write_store_ref();
+        let mut completed: Vec<i32> = store
+            .values()
+            .filter(|todo| todo.is_listed() && todo.completed)
+            .map(|todo| todo.id)
+            .collect();
+        if completed.len() <= max_completed {
+            return;
+        }
+        completed.sort_unstable();
+        let excess = completed.len() - max_completed;
+        let evicted: Vec<Todo> = completed[..excess]
+            .iter()
+            .filter_map(|&id| self.remove_indexed(&mut store, id, OnParentDelete::Orphan))
+            .collect();
+        drop(store);
+
+        for todo in evicted {
+            let id = todo.id;
+            self.notify(Some(todo), TodoEvent::Deleted(id));
+        }
+    }
+
+    // Normalizes before the length check, so the limit applies to the text
+    // as it will be stored.
+    fn clean_text(&self, text: &mut String) -> Result<(), RepositoryError> {
+        if self.normalize_text {
+            *text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+
+        self.text_limit.apply(text)
+    }
+
+    fn check_capacity(
+        &self,
+        store: &LockedTodos<'_>,
+        adding: usize,
+    ) -> Result<(), RepositoryError> {
+        match &self.todo_limit {
+            Some(limit) => limit.check(store, adding),
+            None => Ok(()),
+        }
+    }
+
+    // The counter cannot go past `i32::MAX`. Payloads with an id of their own
+    // take none from it, but may move it up to that id, so the check assumes
+    // they all come first.
+    fn check_ids_left<'a>(
+        &self,
+        payloads: impl IntoIterator<Item = &'a CreateTodo>,
+    ) -> Result<(), RepositoryError> {
+        let mut last_id = i64::from(self.store.last_id());
+        let mut needed = 0;
+        for payload in payloads {
+            match payload.id {
+                Some(id) => last_id = last_id.max(i64::from(id)),
+                None => needed += 1,
+            }
+        }
+        if last_id + needed > i64::from(i32::MAX) {
+            return Err(RepositoryError::IdSpaceExhausted);
+        }
+
+        Ok(())
+    }
+
+    // The next occurrences completing `before` as `todo` would create, along
+    // with those of the children `CascadeMode::CompleteChildren` completes,
+    // for `check_ids_left`.
+    fn occurrences(&self, store: &LockedTodos<'_>, before: &Todo, todo: &Todo) -> Vec<CreateTodo> {
+        let mut occurrences: Vec<CreateTodo> = before.next_occurrence(todo).into_iter().collect();
+        if self.cascade_mode == CascadeMode::CompleteChildren && !before.completed && todo.completed
+        {
+            let mut pending = VecDeque::from(open_children(store, todo.id));
+            while let Some(child) = pending.pop_front() {
+                occurrences.extend(child.rescheduled());
+                pending.extend(open_children(store, child.id));
+            }
+        }
+
+        occurrences
+    }
+
+    // A client-supplied id must not be held by any todo, soft-deleted ones
+    // included.
+    fn check_id(
+        &self,
+        store: &LockedTodos<'_>,
+        payload: &CreateTodo,
+    ) -> Result<(), RepositoryError> {
+        match payload.id {
+            Some(id) if store.contains_key(&id) => Err(RepositoryError::Conflict(id)),
+            _ => Ok(()),
+        }
+    }
+
+    // Callers check a client-supplied id with `check_id` first. Ids handed out
+    // later go past it. A generated id that is already taken is a bug in the
+    // generator: it fails with `RepositoryError::Conflict` rather than
+    // overwrite that todo, though whatever the call changed before stays.
+    fn insert_payload(
+        &self,
+        store: &mut LockedTodos<'_>,
+        payload: CreateTodo,
+    ) -> Result<Todo, RepositoryError> {
+        let id = match payload.id {
+            Some(id) => {
+                if id > self.store.last_id() {
+                    self.store.set_last_id(id);
+                }
+                id
+            }
+            None => {
+                let id = self.next_id();
+                if store.contains_key(&id) {
+                    return Err(RepositoryError::Conflict(id));
+                }
+                id
+            }
+        };
+        let todo = Todo {
+            position: next_position(store),
+            ..Todo::from_payload_at(id, payload, self.now())
+        };
+        self.insert_indexed(store, todo.clone());
+
+        Ok(todo)
+    }
+
+    // Stores `updated` in place of `before`, and creates the next occurrence
+    // when that completes a recurring todo. The occurrence goes in first, so
+    // `updated` isn't stored when it can't be.
+    fn store_updated(
+        &self,
+        store: &mut LockedTodos<'_>,
+        before: &Todo,
+        updated: &Todo,
+    ) -> Result<Option<Todo>, RepositoryError> {
+        let next = before
+            .next_occurrence(updated)
+            .map(|payload| self.insert_payload(store, payload))
+            .transpose()?;
+        self.insert_indexed(store, updated.clone());
+
+        Ok(next)
+    }
+
+    // Runs before `todo` replaces `before` in the store, so nothing has
+    // changed yet when the completion is blocked. Returns the children it
+    // completed, for `notify_updates`.
+    fn cascade_completion(
+        &self,
+        store: &mut LockedTodos<'_>,
+        before: &Todo,
+        todo: &Todo,
+    ) -> Result<Vec<(Todo, Todo, Option<Todo>)>, RepositoryError> {
+        if before.completed || !todo.completed {
+            return Ok(Vec::new());
+        }
+        self.check_ids_left(&self.occurrences(store, before, todo))?;
+
+        match self.cascade_mode {
+            CascadeMode::Ignore => Ok(Vec::new()),
+            CascadeMode::BlockIfChildrenOpen => match open_children(store, todo.id).first() {
+                Some(child) => Err(RepositoryError::Conflict(child.id)),
+                None => Ok(Vec::new()),
+            },
+            CascadeMode::CompleteChildren => {
+                let mut pending = VecDeque::from(open_children(store, todo.id));
+                let mut changes = Vec::new();
+                while let Some(before) = pending.pop_front() {
+                    let child = before.updated_as_of(UpdateTodo::completed(true), self.now());
+                    let next = self.store_updated(store, &before, &child)?;
+                    pending.extend(open_children(store, child.id));
+                    changes.push((before, child, next));
+                }
+                Ok(changes)
+            }
+        }
+    }
+
+    // `cascade_completion` for a batch of updates made at once, so a child
+    // completed in the same batch doesn't block its parent. Returns the
+    // children `CascadeMode::CompleteChildren` completes from outside the
+    // batch, without storing anything yet.
+    fn cascade_batch(
+        &self,
+        store: &LockedTodos<'_>,
+        updates: &[(Todo, Todo)],
+    ) -> Result<Vec<(Todo, Todo)>, RepositoryError> {
+        let mut batch: HashSet<i32> = updates.iter().map(|(before, _)| before.id).collect();
+        let completing = updates
+            .iter()
+            .filter(|(before, todo)| !before.completed && todo.completed)
+            .map(|(_, todo)| todo.id);
+
+        match self.cascade_mode {
+            CascadeMode::Ignore => Ok(Vec::new()),
+            CascadeMode::BlockIfChildrenOpen => {
+                let blocker = completing
+                    .flat_map(|id| open_children(store, id))
+                    .find(|child| !batch.contains(&child.id));
+                match blocker {
+                    Some(child) => Err(RepositoryError::Conflict(child.id)),
+                    None => Ok(Vec::new()),
+                }
+            }
+            CascadeMode::CompleteChildren => {
+                let mut pending: VecDeque<Todo> =
+                    completing.flat_map(|id| open_children(store, id)).collect();
+                let mut cascaded = Vec::new();
+                while let Some(before) = pending.pop_front() {
+                    if !batch.insert(before.id) {
+                        continue;
+                    }
+                    pending.extend(open_children(store, before.id));
+                    let child = before.updated_as_of(UpdateTodo::completed(true), self.now());
+                    cascaded.push((before, child));
+                }
+                Ok(cascaded)
+            }
+        }
+    }
+
+    // Stores a batch of updates along with the children they cascade to, and
+    // sends the events once the lock is released. A blocked cascade or a
+    // lack of ids for the next occurrences leaves the store alone.
+    fn store_batch(
+        &self,
+        mut store: LockedTodos<'_>,
+        mut updates: Vec<(Todo, Todo)>,
+    ) -> Result<(), RepositoryError> {
+        updates.extend(self.cascade_batch(&store, &updates)?);
+        let occurrences: Vec<CreateTodo> = updates
+            .iter()
+            .flat_map(|(before, todo)| before.next_occurrence(todo))
+            .collect();
+        self.check_ids_left(&occurrences)?;
+        let changes = updates
+            .into_iter()
+            .map(|(before, todo)| {
+                let next = self.store_updated(&mut store, &before, &todo)?;
+                Ok((before, todo, next))
+            })
+            .collect::<Result<Vec<_>, RepositoryError>>()?;
+        drop(store);
+
+        self.notify_updates(changes);
+        Ok(())
+    }
+
+    fn notify_updates(&self, cascaded: Vec<(Todo, Todo, Option<Todo>)>) {
+        for (before, todo, next) in cascaded {
+            self.notify_updated(&before, &todo, next);
+        }
+    }
+
+    // Returns the todo from before the update and the updated one.
+    fn update_checked(
+        &self,
+        id: i32,
+        expected_version: Option<u32>,
+        mut payload: UpdateTodo,
+    ) -> anyhow::Result<(Todo, Todo)> {
+        payload.validate(id)?;
+        if let Some(text) = payload.text.as_mut() {
+            self.clean_text(text)?;
+        }
+
+        // Without a completion there is nothing to cascade to or recur, and
+        // without blockers nothing else to check, so only the todo's shard is
+        // locked.
+        if payload.blocked_by.is_none() && payload.completed != Some(true) {
+            let now = self.now();
+            let (before, todo) = self.store.with_entity_mut(id, |todo| {
+                let todo = todo
+                    .filter(|todo| !todo.is_deleted())
+                    .ok_or(RepositoryError::NotFound(id))?;
+                if expected_version.is_some_and(|version| version != todo.version) {
+                    return Err(RepositoryError::Conflict(id));
+                }
+                let before = todo.clone();
+                *todo = before.updated_as_of(payload, now);
+                let mut tag_index = self.tag_index();
+                tag_index.remove(&before);
+                tag_index.add(todo);
+                Ok((before, todo.clone()))
+            })?;
+
+            self.notify_updated(&before, &todo, None);
+            return Ok((before, todo));
+        }
+
+        let mut store = self.write_store_ref();
+        if let Some(blocked_by) = &payload.blocked_by {
+            check_blockers(&store, blocked_by)?;
+        }
+
+        let before = find_live(&store, id)
+            .context(RepositoryError::NotFound(id))?
+            .clone();
+        if expected_version.is_some_and(|version| version != before.version) {
+            return Err(RepositoryError::Conflict(id).into());
+        }
+        let todo = before.updated_as_of(payload, self.now());
+        let cascaded = self.cascade_completion(&mut store, &before, &todo)?;
+        let next = self.store_updated(&mut store, &before, &todo)?;
+        drop(store);
+
+        self.notify_updated(&before, &todo, next);
+        self.notify_updates(cascaded);
+        Ok((before, todo))
+    }
+
+    fn notify_updated(&self, before: &Todo, todo: &Todo, next: Option<Todo>) {
+        self.notify(Some(before.clone()), TodoEvent::Updated(todo.clone()));
+        if let Some(next) = next {
+            self.notify(None, TodoEvent::Created(next));
+        }
+    }
+
+    // The todos `all` would return, sorted by id, for embedding into a larger
+    // response without serializing them twice.
+    pub fn all_json(&self) -> serde_json::Value {
+        let store = self.read_store_ref();
+        let mut todos: Vec<&Todo> = store.values().filter(|todo| todo.is_listed()).collect();
+        todos.sort_by_key(|todo| todo.id);
+
+        serde_json::to_value(todos).unwrap()
+    }
+
+    // The ids of the todos `all` would return, sorted.
+    pub fn all_ids(&self) -> Vec<i32> {
+        let store = self.read_store_ref();
+        let mut ids: Vec<i32> = store
+            .values()
+            .filter(|todo| todo.is_listed())
+            .map(|todo| todo.id)
+            .collect();
+        ids.sort_unstable();
+
+        ids
+    }
+
+    // Like `find`, but lends the todo to `f` under its read lock instead of
+    // cloning it. `f` must not call back into the repository.
+    pub fn with_todo<F, T>(&self, id: i32, f: F) -> Option<T>
+    where
+        F: FnOnce(&Todo) -> T,
+    {
+        let result = self
+            .store
+            .with_entity(id, |todo| todo.filter(|todo| !todo.is_deleted()).map(f));
+
+        if result.is_none() {
+            self.metrics.on_find_miss(id);
+        }
+        result
+    }
+
+    // The todos `all` would return that `predicate` accepts, sorted by id. As
+    // with `for_each`, `predicate` runs under the read lock and must not call
+    // back into the repository.
+    pub fn filter<F: Fn(&Todo) -> bool>(&self, predicate: F) -> Vec<Todo> {
+        let store = self.read_store_ref();
+        let todos = store
+            .values()
+            .filter(|todo| todo.is_listed() && predicate(todo))
+            .cloned()
+            .collect();
+        drop(store);
+
+        sorted_by_id(todos)
+    }
+
+    // The todos `all` would return, sorted by id and split into runs of
+    // `chunk_size`, the last one possibly shorter. A `chunk_size` of zero puts
+    // every todo in a single chunk. An empty store gives no chunks at all.
+    pub fn chunks(&self, chunk_size: usize) -> Vec<Vec<Todo>> {
+        let store = self.read_store_ref();
+        let todos: Vec<Todo> = store
+            .values()
+            .filter(|todo| todo.is_listed())
+            .cloned()
+            .collect();
+        drop(store);
+
+        let todos = sorted_by_id(todos);
+        if todos.is_empty() {
+            return Vec::new();
+        }
+        let chunk_size = if chunk_size == 0 {
+            todos.len()
+        } else {
+            chunk_size
+        };
+        todos.chunks(chunk_size).map(<[Todo]>::to_vec).collect()
+    }
+
+    // Flips each todo in turn under one write lock, so an id given twice ends
+    // up where it started. Results come back in the order of `ids`.
+    pub fn toggle_many(&self, ids: Vec<i32>) -> Vec<(i32, anyhow::Result<Todo>)> {
+        let mut store = self.write_store_ref();
+        let mut results = Vec::with_capacity(ids.len());
+        let mut changes = Vec::new();
+        for id in ids {
+            let Some(before) = find_live(&store, id).cloned() else {
+                results.push((id, Err(RepositoryError::NotFound(id).into())));
+                continue;
+            };
+            let todo = before.updated_as_of(UpdateTodo::completed(!before.completed), self.now());
+            let cascaded = match self.cascade_completion(&mut store, &before, &todo) {
+                Ok(cascaded) => cascaded,
+                Err(e) => {
+                    results.push((id, Err(e.into())));
+                    continue;
+                }
+            };
+            let next = match self.store_updated(&mut store, &before, &todo) {
+                Ok(next) => next,
+                Err(e) => {
+                    results.push((id, Err(e.into())));
+                    changes.extend(cascaded);
+                    continue;
+                }
+            };
+            results.push((id, Ok(todo.clone())));
+            changes.push((before, todo, next));
+            changes.extend(cascaded);
+        }
+        drop(store);
+
+        self.notify_updates(changes);
+        results
+    }
+
+    // For tests and debugging: fails with `RepositoryError::Inconsistent` on
+    // the first thing found wrong with the store, checking todos by id.
+    pub fn check_invariants(&self) -> anyhow::Result<()> {
+        let store = self.read_store_ref();
+
+        let last_id = self.store.last_id();
+        let mut keys: Vec<i32> = store.keys().copied().collect();
+        keys.sort_unstable();
+        for key in keys {
+            let id = store[&key].id;
+            if id != key {
+                return Err(inconsistent(format!("todo {} is stored under {}", id, key)));
+            }
+            if id > last_id {
+                return Err(inconsistent(format!(
+                    "todo {} is past the last id handed out, {}",
+                    id, last_id
+                )));
+            }
+        }
+
+        let tag_index = self.tag_index();
+        let expected = TagIndex::build(&store);
+        if *tag_index != expected {
+            let mut tags: Vec<&String> = tag_index.ids.keys().chain(expected.ids.keys()).collect();
+            tags.sort_unstable();
+            let tag = tags
+                .into_iter()
+                .find(|tag| tag_index.ids(tag) != expected.ids(tag))
+                .unwrap();
+            return Err(inconsistent(format!(
+                "tag {} is indexed for {:?} but held by {:?}",
+                tag,
+                tag_index.ids(tag),
+                expected.ids(tag)
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Hands every todo assigned to `from`, archived ones included, to `to`
+    // under one write lock, and returns how many there were. Soft-deleted
+    // todos keep their assignee.
+    pub fn reassign_all(&self, from: &str, to: &str) -> usize {
+        if from == to {
+            return 0;
+        }
+        let mut store = self.write_store_ref();
+
+        let assigned: Vec<Todo> = store
+            .values()
+            .filter(|todo| !todo.is_deleted() && todo.assignee.as_deref() == Some(from))
+            .cloned()
+            .collect();
+        let payload = UpdateTodo {
+            assignee: Some(Some(to.to_string())),
+            ..UpdateTodo::default()
+        };
+        // Changing the assignee never completes a todo, so there are no next
+        // occurrences to create.
+        let mut changes = Vec::new();
+        for before in sorted_by_id(assigned) {
+            let todo = before.updated_as_of(payload.clone(), self.now());
+            self.insert_indexed(&mut store, todo.clone());
+            changes.push((before, todo, None));
+        }
+        drop(store);
+
+        let reassigned = changes.len();
+        self.notify_updates(changes);
+        reassigned
+    }
+
+    // Runs `ops` in order under one write lock, going on past a failed op
+    // unless `all_or_nothing` is set. Then the first failure puts the store
+    // and the id counter back as they were, and no events are sent.
+    pub fn apply(&self, ops: Vec<TodoOp>, all_or_nothing: bool) -> Vec<OpResult> {
+        let mut store = self.write_store_ref();
+        let mut original = all_or_nothing.then(|| (store.to_entries(), self.store.last_id()));
+
+        let count = ops.len();
+        let mut results = Vec::with_capacity(count);
+        let mut events = Vec::new();
+        for op in ops {
+            match self.apply_op(&mut store, op, &mut events) {
+                Ok(result) => results.push(result),
+                Err(e) => match original.take() {
+                    Some((todos, last_id)) => {
+                        store.replace(todos);
+                        self.rebuild_tag_index(&store);
+                        self.store.set_last_id(last_id);
+                        let failed = results.len();
+                        let mut results: Vec<OpResult> =
+                            (0..count).map(|_| OpResult::RolledBack).collect();
+                        results[failed] = OpResult::Failed(e);
+                        return results;
+                    }
+                    None => results.push(OpResult::Failed(e)),
+                },
+            }
+        }
+        drop(store);
+
+        for (before, event) in events {
+            self.notify(before, event);
+        }
+        results
+    }
+
+    // Makes the same checks as the single-op methods, and adds the events to
+    // send once the lock is released to `events`.
+    fn apply_op(
+        &self,
+        store: &mut LockedTodos<'_>,
+        op: TodoOp,
+        events: &mut Vec<(Option<Todo>, TodoEvent)>,
+    ) -> anyhow::Result<OpResult> {
+        match op {
+            TodoOp::Create(mut payload) => {
+                payload.validate()?;
+                self.clean_text(&mut payload.text)?;
+                check_parent(store, &payload)?;
+                check_blockers(store, &payload.blocked_by)?;
+                self.check_id(store, &payload)?;
+                self.check_capacity(store, 1)?;
+                self.check_ids_left([&payload])?;
+
+                let todo = self.insert_payload(store, payload)?;
+                events.push((None, TodoEvent::Created(todo.clone())));
+                Ok(OpResult::Created(todo))
+            }
+            TodoOp::Update { id, mut payload } => {
+                payload.validate(id)?;
+                if let Some(text) = payload.text.as_mut() {
+                    self.clean_text(text)?;
+                }
+                if let Some(blocked_by) = &payload.blocked_by {
+                    check_blockers(store, blocked_by)?;
+                }
+
+                let before = find_live(store, id)
+                    .context(RepositoryError::NotFound(id))?
+                    .clone();
+                let todo = before.updated_as_of(payload, self.now());
+                let cascaded = self.cascade_completion(store, &before, &todo)?;
+                let next = self.store_updated(store, &before, &todo)?;
+                let mut changes = vec![(before, todo.clone(), next)];
+                changes.extend(cascaded);
+                for (before, todo, next) in changes {
+                    events.push((Some(before), TodoEvent::Updated(todo)));
+                    if let Some(next) = next {
+                        events.push((None, TodoEvent::Created(next)));
+                    }
+                }
+                Ok(OpResult::Updated(todo))
+            }
+            TodoOp::Delete(id) => {
+                let before = self
+                    .remove_indexed(store, id, self.on_parent_delete)
+                    .context(RepositoryError::NotFound(id))?;
+                events.push((Some(before), TodoEvent::Deleted(id)));
+                Ok(OpResult::Deleted(id))
+            }
+        }
+    }
+
+    // Runs the checks `create` would run on `payload` without storing
+    // anything.
+    pub fn validate(&self, payload: &CreateTodo) -> Result<(), RepositoryError> {
+        payload.validate()?;
+        self.clean_text(&mut payload.text.clone())?;
+
+        let store = self.read_store_ref();
+        check_parent(&store, payload)?;
+        check_blockers(&store, &payload.blocked_by)
+    }
+
+    // Equal for stores holding the same todos, soft-deleted and archived ones
+    // included, however they were inserted. Every field counts, timestamps
+    // too. The hasher may change between Rust releases, so only compare
+    // digests taken by the same build.
+    pub fn store_hash(&self) -> u64 {
+        let store = self.read_store_ref();
+
+        store.values().fold(0, |digest, todo| {
+            let mut hasher = DefaultHasher::new();
+            todo.hash(&mut hasher);
+            digest ^ hasher.finish()
+        })
+    }
+
+    // A rough figure for diagnostics. Each slot the map has room for is
+    // counted as an id, a todo and one control byte, whether it is used or
+    // not, plus what the stored todos allocate, soft-deleted ones included.
+    pub fn approx_memory_bytes(&self) -> usize {
+        let store = self.read_store_ref();
+        let slot = std::mem::size_of::<i32>() + std::mem::size_of::<Todo>() + 1;
+
+        store.capacity() * slot + store.values().map(Todo::heap_bytes).sum::<usize>()
+    }
+
+    // Visits the todos `all` would return without cloning them. The read lock
+    // is held throughout, so `f` must not call back into the repository: any
+    // call that takes the write lock would deadlock.
+    pub fn for_each<F: FnMut(&Todo)>(&self, f: F) {
+        let store = self.read_store_ref();
+
+        store.values().filter(|todo| todo.is_listed()).for_each(f);
+    }
+
+    // Ids are only handed out under the write lock, so the counter read under
+    // the read lock always matches the store.
+    pub fn snapshot(&self) -> TodoSnapshot {
+        let store = self.read_store_ref();
+
+        TodoSnapshot {
+            todos: store.to_entries(),
+            last_id: self.store.last_id(),
+        }
+    }
+
+    // Puts the store and the id counter back as they were. No events are sent
+    // for the changes this undoes.
+    pub fn restore_snapshot(&self, snapshot: TodoSnapshot) {
+        let mut store = self.write_store_ref();
+        store.replace(snapshot.todos);
+        self.rebuild_tag_index(&store);
+        self.store.set_last_id(snapshot.last_id);
+    }
+
+    // Unlike `import_json`, the todos keep their ids, e.g. when restoring a
+    // backup. Soft-deleted todos still hold their id, so they conflict too,
+    // as does an id that comes up twice in `todos`.
+    pub fn import(&self, todos: Vec<Todo>, policy: ConflictPolicy) -> anyhow::Result<ImportReport> {
+        let mut store = self.write_store_ref();
+
+        if policy == ConflictPolicy::Fail {
+            let mut taken: HashSet<i32> = store.keys().copied().collect();
+            if let Some(todo) = todos.iter().find(|todo| !taken.insert(todo.id)) {
+                return Err(RepositoryError::Conflict(todo.id).into());
+            }
+        }
+
+        let mut report = ImportReport::default();
+        let mut events = Vec::new();
+        for todo in todos {
+            let id = todo.id;
+            if !store.contains_key(&id) {
+                report.imported.push(id);
+                events.push((None, TodoEvent::Created(todo.clone())));
+            } else if policy == ConflictPolicy::Overwrite {
+                report.overwritten.push(id);
+                events.push((store.get(&id).cloned(), TodoEvent::Updated(todo.clone())));
+            } else {
+                report.skipped.push(id);
+                continue;
+            }
+            self.insert_indexed(&mut store, todo);
+            if id > self.store.last_id() {
+                self.store.set_last_id(id);
+            }
+        }
+        drop(store);
+
+        for (before, event) in events {
+            self.notify(before, event);
+        }
+        Ok(report)
+    }
+
+    // One todo per line, sorted by id, with the todos `export_json` would
+    // write.
+    pub fn export_ndjson<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let store = self.read_store_ref();
+        let mut todos: Vec<&Todo> = store.values().filter(|todo| todo.is_listed()).collect();
+        todos.sort_by_key(|todo| todo.id);
+
+        for todo in todos {
+            serde_json::to_writer(&mut w, todo)?;
+            w.write_all(b"\n")?;
+        }
+        w.flush()
+    }
+
+    // Reads what `export_ndjson` writes, skipping blank lines, and hands the
+    // todos to `import`. Nothing is imported if any line fails to parse.
+    pub fn import_ndjson<R: BufRead>(
+        &self,
+        r: R,
+        policy: ConflictPolicy,
+    ) -> anyhow::Result<ImportReport> {
+        let mut todos = Vec::new();
+        for (number, line) in r.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let todo = serde_json::from_str(&line)
+                .with_context(|| format!("line {} is not a todo", number + 1))?;
+            todos.push(todo);
+        }
+
+        self.import(todos, policy)
+    }
+
+    // Numbers new todos from 1 again. Only allowed once the store is empty,
+    // soft-deleted todos included, so no id can be handed out twice.
+    pub fn reset_ids(&self) -> anyhow::Result<()> {
+        let store = self.write_store_ref();
+
+        if !store.is_empty() {
+            return Err(RepositoryError::Validation(vec![
+                "ids can only be reset while the store is empty".to_string(),
+            ])
+            .into());
+        }
+        self.store.set_last_id(0);
+
+        Ok(())
+    }
+
+    // Runs `f` against a copy of the store under the write lock and keeps its
+    // changes only if it returns `Ok`. As with `restore_snapshot`, no events
+    // are sent. Ids `f` inserts are never handed out afterwards.
+    pub fn transaction<F, T>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut TodoDates) -> anyhow::Result<T>,
+    {
+        let mut store = self.write_store_ref();
+
+        let mut draft = store.to_entries();
+        let value = f(&mut draft)?;
+        let last_id = draft.keys().copied().max().unwrap_or(0);
+        store.replace(draft);
+        self.rebuild_tag_index(&store);
+        if last_id > self.store.last_id() {
+            self.store.set_last_id(last_id);
+        }
+
+        Ok(value)
+    }
+
+    // Replaces the todo at `id`, soft-deleted or not, keeping its
+    // `created_at`, position and version count, or creates one there, moving
+    // the counter past `id`. Everything else comes from `payload`, whose own
+    // id, if set, must be `id`.
+    pub fn upsert(&self, id: i32, mut payload: CreateTodo) -> anyhow::Result<Todo> {
+        if payload.id.is_some_and(|payload_id| payload_id != id) {
+            return Err(RepositoryError::Validation(vec![format!(
+                "payload id does not match {}",
+                id
+            )])
+            .into());
+        }
+        payload.id = Some(id);
+        payload.validate()?;
+        self.clean_text(&mut payload.text)?;
+
+        let mut store = self.write_store_ref();
+        check_parent(&store, &payload)?;
+        check_blockers(&store, &payload.blocked_by)?;
+
+        let Some(before) = store.get(&id).cloned() else {
+            self.check_capacity(&store, 1)?;
+            let todo = self.insert_payload(&mut store, payload)?;
+            drop(store);
+
+            self.notify(None, TodoEvent::Created(todo.clone()));
+            return Ok(todo);
+        };
+        let todo = Todo {
+            created_at: before.created_at,
+            position: before.position,
+            version: before.version + 1,
+            ..Todo::from_payload_at(id, payload, self.now())
+        };
+        self.insert_indexed(&mut store, todo.clone());
+        drop(store);
+
+        self.notify(Some(before), TodoEvent::Updated(todo.clone()));
+        Ok(todo)
+    }
+
+    // Moves a todo to `new_id`, along with the parent links and blockers that
+    // point at it. Soft-deleted todos still hold their id, so they count as
+    // taking it. The todos pointing at it count as updated.
+    pub fn reassign_id(&self, old_id: i32, new_id: i32) -> anyhow::Result<Todo> {
+        if new_id <= 0 {
+            return Err(
+                RepositoryError::Validation(vec!["id must be positive".to_string()]).into(),
+            );
+        }
+        let mut store = self.write_store_ref();
+
+        find_live(&store, old_id).context(RepositoryError::NotFound(old_id))?;
+        if old_id == new_id {
+            return Ok(store[&old_id].clone());
+        }
+        if store.contains_key(&new_id) {
+            return Err(RepositoryError::Conflict(new_id).into());
+        }
+
+        let now = self.now();
+        let before = store.remove(&old_id).unwrap();
+        self.tag_index().remove(&before);
+        let mut todo = before.clone();
+        todo.id = new_id;
+        todo.updated_at = now;
+        self.insert_indexed(&mut store, todo.clone());
+        let mut pointing = Vec::new();
+        for other in store.values_mut() {
+            let points_at_it =
+                other.parent_id == Some(old_id) || other.blocked_by.contains(&old_id);
+            if !points_at_it {
+                continue;
+            }
+            let other_before = other.clone();
+            if other.parent_id == Some(old_id) {
+                other.parent_id = Some(new_id);
+            }
+            for blocker in other.blocked_by.iter_mut().filter(|id| **id == old_id) {
+                *blocker = new_id;
+            }
+            other.version += 1;
+            other.updated_at = now;
+            pointing.push((other_before, other.clone()));
+        }
+        if new_id > self.store.last_id() {
+            self.store.set_last_id(new_id);
+        }
+        drop(store);
+
+        self.notify(Some(before), TodoEvent::Deleted(old_id));
+        self.notify(None, TodoEvent::Created(todo.clone()));
+        pointing.sort_by_key(|(before, _)| before.id);
+        for (before, other) in pointing {
+            self.notify(Some(before), TodoEvent::Updated(other));
+        }
+        Ok(todo)
+    }
+
+    // Renumbers every todo, soft-deleted ones included, from 1 in id order,
+    // along with the parent links and blockers, and returns how many got a
+    // new id. New todos continue from the last one. Ids kept outside the
+    // repository, in links or bookmarks, point at other todos afterwards.
+    pub fn reindex(&self) -> usize {
+        let mut store = self.write_store_ref();
+
+        let mut old_ids: Vec<i32> = store.keys().copied().collect();
+        old_ids.sort_unstable();
+        let new_ids: HashMap<i32, i32> = old_ids
+            .iter()
+            .zip(1..)
+            .map(|(old_id, new_id)| (*old_id, new_id))
+            .collect();
+        let renumber = |id: &mut i32| *id = new_ids[id];
+
+        let mut events = Vec::new();
+        let now = self.now();
+        let todos: Vec<Todo> = store.drain().map(|(_, todo)| todo).collect();
+        for mut todo in todos {
+            let before = todo.clone();
+            renumber(&mut todo.id);
+            if let Some(parent_id) = todo.parent_id.as_mut() {
+                renumber(parent_id);
+            }
+            todo.blocked_by.iter_mut().for_each(renumber);
+            if todo.id != before.id {
+                todo.updated_at = now;
+                events.push((before, todo.clone()));
+            }
+            store.insert(todo.id, todo);
+        }
+        self.rebuild_tag_index(&store);
+        self.store.set_last_id(old_ids.len() as i32);
+        drop(store);
+
+        events.sort_by_key(|(before, _)| before.id);
+        let renumbered = events.len();
+        for (before, todo) in events {
+            let old_id = before.id;
+            self.notify(Some(before), TodoEvent::Deleted(old_id));
+            self.notify(None, TodoEvent::Created(todo));
+        }
+        renumbered
+    }
+
+    fn set_archived(&self, id: i32, archived: bool) -> anyhow::Result<Todo> {
+        let now = self.now();
+        let (before, todo) = self.change_live(id, |todo| {
+            todo.archived = archived;
+            todo.updated_at = now;
+        })?;
+
+        self.notify(Some(before), TodoEvent::Updated(todo.clone()));
+        Ok(todo)
+    }
+
+    // Changes a live todo in place and bumps its version, with only its shard
+    // locked. Returns the todo from before the change and the changed one.
+    fn change_live(
+        &self,
+        id: i32,
+        change: impl FnOnce(&mut Todo),
+    ) -> Result<(Todo, Todo), RepositoryError> {
+        self.store.with_entity_mut(id, |todo| {
+            let todo = todo
+                .filter(|todo| !todo.is_deleted())
+                .ok_or(RepositoryError::NotFound(id))?;
+            let before = todo.clone();
+            change(todo);
+            todo.version += 1;
+
+            Ok((before, todo.clone()))
+        })
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    fn next_id(&self) -> i32 {
+        self.store.next_id()
+    }
+
+    // Every change to which todos are stored, or to their tags, goes through
+    // here, `remove_indexed`, `rebuild_tag_index` or the in-place change in
+    // `update_checked`, so the tag index stays in step with the store.
+    fn insert_indexed(&self, store: &mut LockedTodos<'_>, todo: Todo) {
+        let mut tag_index = self.tag_index();
+        if let Some(before) = store.get(&todo.id) {
+            tag_index.remove(before);
+        }
+        tag_index.add(&todo);
+        store.insert(todo.id, todo);
+    }
+
+    fn remove_indexed(
+        &self,
+        store: &mut LockedTodos<'_>,
+        id: i32,
+        on_parent_delete: OnParentDelete,
+    ) -> Option<Todo> {
+        let mut removed = Vec::new();
+        remove_with_children(store, id, on_parent_delete, &mut removed);
+        let mut tag_index = self.tag_index();
+        removed.iter().for_each(|todo| tag_index.remove(todo));
+
+        removed.into_iter().next()
+    }
+
+    fn rebuild_tag_index(&self, store: &LockedTodos<'_>) {
+        *self.tag_index() = TagIndex::build(store);
+    }
+
+    fn tag_index(&self) -> MutexGuard<'_, TagIndex> {
+        self.tag_index
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_store_ref(&self) -> LockedTodos<'_> {
+        self.store.write()
+    }
+
+    fn read_store_ref(&self) -> LockedTodos<'_> {
+        self.store.read()
+    }
+}
+
+#[async_trait]
+impl TodoRepository for TodoRepositoryForMemory {
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn create(&self, mut payload: CreateTodo) -> anyhow::Result<Todo> {
+        payload.validate()?;
+        self.clean_text(&mut payload.text)?;
+
+        let mut store = self.write_store_ref();
+        check_parent(&store, &payload)?;
+        check_blockers(&store, &payload.blocked_by)?;
+        self.check_id(&store, &payload)?;
+        self.check_capacity(&store, 1)?;
+        self.check_ids_left([&payload])?;
+
+        let todo = self.insert_payload(&mut store, payload)?;
+        drop(store);
+
+        self.notify(None, TodoEvent::Created(todo.clone()));
+        Ok(todo)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn find_or_create(&self, text: &str) -> anyhow::Result<Todo> {
+        let mut payload = CreateTodo::new(text.to_string());
+        payload.validate()?;
+        self.clean_text(&mut payload.text)?;
+
+        let mut store = self.write_store_ref();
+        let existing = store
+            .values()
+            .filter(|todo| !todo.is_deleted() && todo.text == payload.text)
+            .min_by_key(|todo| todo.id);
+        if let Some(todo) = existing {
+            return Ok(todo.clone());
+        }
+        self.check_capacity(&store, 1)?;
+        self.check_ids_left([&payload])?;
+
+        let todo = self.insert_payload(&mut store, payload)?;
+        drop(store);
+
+        self.notify(None, TodoEvent::Created(todo.clone()));
+        Ok(todo)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn create_unique(&self, mut payload: CreateTodo) -> anyhow::Result<Todo> {
+        payload.validate()?;
+        self.clean_text(&mut payload.text)?;
+
+        let mut store = self.write_store_ref();
+        let text = payload.text.to_lowercase();
+        let duplicate = store
+            .values()
+            .filter(|todo| !todo.is_deleted() && todo.text.to_lowercase() == text)
+            .map(|todo| todo.id)
+            .min();
+        if let Some(id) = duplicate {
+            return Err(RepositoryError::Conflict(id).into());
+        }
+        check_parent(&store, &payload)?;
+        check_blockers(&store, &payload.blocked_by)?;
+        self.check_id(&store, &payload)?;
+        self.check_capacity(&store, 1)?;
+        self.check_ids_left([&payload])?;
+
+        let todo = self.insert_payload(&mut store, payload)?;
+        drop(store);
+
+        self.notify(None, TodoEvent::Created(todo.clone()));
+        Ok(todo)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn find(&self, id: i32) -> Option<Todo> {
+        let todo = self
+            .store
+            .with_entity(id, |todo| todo.filter(|todo| !todo.is_deleted()).cloned());
+
+        if todo.is_none() {
+            self.metrics.on_find_miss(id);
+        }
+        todo
+    }
+
+    // Soft-deleted todos do not count, matching `find`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn exists(&self, id: i32) -> bool {
+        self.store
+            .with_entity(id, |todo| todo.is_some_and(|todo| !todo.is_deleted()))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn all(&self) -> Vec<Todo> {
+        let store = self.read_store_ref();
+
+        store
+            .values()
+            .filter(|todo| todo.is_listed())
+            .cloned()
+            .collect()
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        let (_, todo) = self.update_checked(id, None, payload)?;
+
+        Ok(todo)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn update_if_version(
+        &self,
+        id: i32,
+        expected_version: u32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo> {
+        let (_, todo) = self.update_checked(id, Some(expected_version), payload)?;
+
+        Ok(todo)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn replace(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<(Todo, Todo)> {
+        self.update_checked(id, None, payload)
+    }
+
+    // Removes the todo for good, whether or not it was soft-deleted. Its
+    // children are orphaned or deleted depending on `on_parent_delete`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let mut store = self.write_store_ref();
+        let before = self
+            .remove_indexed(&mut store, id, self.on_parent_delete)
+            .context(RepositoryError::NotFound(id))?;
+        drop(store);
+
+        self.notify(Some(before), TodoEvent::Deleted(id));
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn count(&self) -> usize {
+        let store = self.read_store_ref();
+
+        store.values().filter(|todo| todo.is_listed()).count()
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn is_empty(&self) -> bool {
+        let store = self.read_store_ref();
+        let listed = store.values().any(|todo| todo.is_listed());
+
+        !listed
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn find_by_tag(&self, tag: &str) -> Vec<Todo> {
+        let store = self.read_store_ref();
+
+        self.tag_index()
+            .ids(tag)
+            .iter()
+            .filter_map(|id| store.get(id))
+            .filter(|todo| todo.is_listed())
+            .cloned()
+            .collect()
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn max_id(&self) -> Option<i32> {
+        let store = self.read_store_ref();
+
+        store
+            .values()
+            .filter(|todo| !todo.is_deleted())
+            .map(|todo| todo.id)
+            .max()
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn count_completed(&self) -> usize {
+        let store = self.read_store_ref();
+
+        store
+            .values()
+            .filter(|todo| todo.is_listed() && todo.completed)
+            .count()
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn bulk_create(&self, mut payloads: Vec<CreateTodo>) -> anyhow::Result<Vec<Todo>> {
+        for payload in payloads.iter_mut() {
+            payload.validate()?;
+            self.clean_text(&mut payload.text)?;
+        }
+
+        let mut store = self.write_store_ref();
+        let mut client_ids = HashSet::new();
+        for payload in payloads.iter() {
+            check_parent(&store, payload)?;
+            check_blockers(&store, &payload.blocked_by)?;
+            self.check_id(&store, payload)?;
+            if let Some(id) = payload.id.filter(|id| !client_ids.insert(*id)) {
+                return Err(RepositoryError::Conflict(id).into());
+            }
+        }
+        self.check_capacity(&store, payloads.len())?;
+        self.check_ids_left(&payloads)?;
+
+        // Every id is settled before anything is stored, client ids first, so
+        // a generated id can't land on a client id later in the batch.
+        if let Some(&max_client_id) = client_ids.iter().max() {
+            if max_client_id > self.store.last_id() {
+                self.store.set_last_id(max_client_id);
+            }
+        }
+        for payload in payloads.iter_mut().filter(|payload| payload.id.is_none()) {
+            let id = self.next_id();
+            if store.contains_key(&id) || !client_ids.insert(id) {
+                return Err(RepositoryError::Conflict(id).into());
+            }
+            payload.id = Some(id);
+        }
+        let todos = payloads
+            .into_iter()
+            .map(|payload| self.insert_payload(&mut store, payload))
+            .collect::<Result<Vec<_>, RepositoryError>>()?;
+        drop(store);
+
+        for todo in todos.iter() {
+            self.notify(None, TodoEvent::Created(todo.clone()));
+        }
+        Ok(todos)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn bulk_delete(&self, ids: Vec<i32>) -> anyhow::Result<BulkDeleteReport> {
+        let mut store = self.write_store_ref();
+
+        let mut report = BulkDeleteReport::default();
+        let mut removed = Vec::new();
+        for id in ids {
+            match self.remove_indexed(&mut store, id, self.on_parent_delete) {
+                Some(todo) => {
+                    report.deleted.push(id);
+                    removed.push(todo);
+                }
+                None => report.not_found.push(id),
+            }
+        }
+        drop(store);
+
+        for todo in removed {
+            let id = todo.id;
+            self.notify(Some(todo), TodoEvent::Deleted(id));
+        }
+        Ok(report)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn toggle_completed(&self, id: i32) -> anyhow::Result<Todo> {
+        let mut store = self.write_store_ref();
+
+        let before = find_live(&store, id)
+            .context(RepositoryError::NotFound(id))?
+            .clone();
+        let todo = before.updated_as_of(UpdateTodo::completed(!before.completed), self.now());
+        let cascaded = self.cascade_completion(&mut store, &before, &todo)?;
+        let next = self.store_updated(&mut store, &before, &todo)?;
+        drop(store);
+
+        self.notify_updated(&before, &todo, next);
+        self.notify_updates(cascaded);
+        Ok(todo)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn complete(&self, id: i32) -> anyhow::Result<CompleteOutcome> {
+        let mut store = self.write_store_ref();
+
+        let before = find_live(&store, id)
+            .context(RepositoryError::NotFound(id))?
+            .clone();
+        if before.completed {
+            return Ok(CompleteOutcome::AlreadyComplete(before));
+        }
+        let todo = before.updated_as_of(UpdateTodo::completed(true), self.now());
+        let cascaded = self.cascade_completion(&mut store, &before, &todo)?;
+        let next = self.store_updated(&mut store, &before, &todo)?;
+        drop(store);
+
+        self.notify_updated(&before, &todo, next);
+        self.notify_updates(cascaded);
+        Ok(CompleteOutcome::JustCompleted(todo))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(keep_id = keep_id, merge_id = merge_id)))]
+    async fn merge(&self, keep_id: i32, merge_id: i32) -> anyhow::Result<Todo> {
+        check_merge_ids(keep_id, merge_id)?;
+        let mut store = self.write_store_ref();
+
+        let keep = find_live(&store, keep_id).context(RepositoryError::NotFound(keep_id))?;
+        let merged = find_live(&store, merge_id).context(RepositoryError::NotFound(merge_id))?;
+        if is_descendant(&store, keep, merge_id) {
+            return Err(merge_into_descendant().into());
+        }
+        let todo = keep.updated_as_of(keep.absorbing(merged), self.now());
+        let keep = keep.clone();
+        self.insert_indexed(&mut store, todo.clone());
+        let merged = self.remove_indexed(&mut store, merge_id, self.on_parent_delete);
+        drop(store);
+
+        self.notify(Some(keep), TodoEvent::Updated(todo.clone()));
+        self.notify(merged, TodoEvent::Deleted(merge_id));
+        Ok(todo)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn soft_delete(&self, id: i32) -> anyhow::Result<()> {
+        let now = self.now();
+        let (before, _) = self.change_live(id, |todo| todo.deleted_at = Some(now))?;
+
+        self.notify(Some(before), TodoEvent::Deleted(id));
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn restore(&self, id: i32) -> anyhow::Result<Todo> {
+        let (before, todo) = self.store.with_entity_mut(id, |todo| {
+            let todo = todo.ok_or(RepositoryError::NotFound(id))?;
+            let before = todo.clone();
+            todo.deleted_at = None;
+            todo.version += 1;
+
+            Ok::<_, RepositoryError>((before, todo.clone()))
+        })?;
+
+        self.notify(Some(before), TodoEvent::Updated(todo.clone()));
+        Ok(todo)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn find_many(&self, ids: &[i32]) -> Vec<Todo> {
+        let store = self.read_store_ref();
+
+        ids.iter()
+            .filter_map(|id| find_live(&store, *id).cloned())
+            .collect()
+    }
+
+    // The id counter keeps advancing, so ids handed out before the clear are
+    // never reused.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn clear_all(&self) -> anyhow::Result<()> {
+        let mut store = self.write_store_ref();
+        let removed: Vec<Todo> = store.drain().map(|(_, todo)| todo).collect();
+        self.rebuild_tag_index(&store);
+        drop(store);
+
+        for todo in sorted_by_id(removed) {
+            let id = todo.id;
+            self.notify(Some(todo), TodoEvent::Deleted(id));
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn reorder(&self, id: i32, new_position: i32) -> anyhow::Result<()> {
+        let mut store = self.write_store_ref();
+
+        let live: Vec<Todo> = store
+            .values()
+            .filter(|todo| !todo.is_deleted())
+            .cloned()
+            .collect();
+        let ordered = sorted_by_position(live)
+            .into_iter()
+            .map(|todo| todo.id)
+            .collect();
+        let mut moved = Vec::new();
+        for (position, id) in reordered(ordered, id, new_position)?
+            .into_iter()
+            .enumerate()
+        {
+            if let Some(todo) = store.get_mut(&id) {
+                if todo.position != position as i32 {
+                    let before = todo.clone();
+                    todo.position = position as i32;
+                    todo.version += 1;
+                    moved.push((before, todo.clone()));
+                }
+            }
+        }
+        drop(store);
+
+        for (before, todo) in moved {
+            self.notify(Some(before), TodoEvent::Updated(todo));
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn tag_counts(&self) -> HashMap<String, usize> {
+        let store = self.read_store_ref();
+
+        count_tags(store.values().filter(|todo| todo.is_listed()))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn stats(&self) -> TodoStats {
+        let store = self.read_store_ref();
+
+        TodoStats::collect(store.values().filter(|todo| todo.is_listed()), self.now())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn complete_all(&self) -> anyhow::Result<usize> {
+        let completed = self.complete_where(TodoFilter::default()).await?;
+
+        Ok(completed.len())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn complete_where(&self, filter: TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        let store = self.write_store_ref();
+
+        let incomplete: Vec<Todo> = store
+            .values()
+            .filter(|todo| todo.is_listed() && !todo.completed && filter.matches(todo))
+            .cloned()
+            .collect();
+        let updates: Vec<(Todo, Todo)> = sorted_by_id(incomplete)
+            .into_iter()
+            .map(|before| {
+                let todo = before.updated_as_of(UpdateTodo::completed(true), self.now());
+                (before, todo)
+            })
+            .collect();
+        let completed = updates.iter().map(|(_, todo)| todo.clone()).collect();
+        self.store_batch(store, updates)?;
+
+        Ok(completed)
+    }
+
+    // Every matching todo is checked before any is changed, so an invalid
+    // payload leaves them all alone.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn update_where(
+        &self,
+        filter: TodoFilter,
+        mut payload: UpdateTodo,
+    ) -> anyhow::Result<Vec<Todo>> {
+        if let Some(text) = payload.text.as_mut() {
+            self.clean_text(text)?;
+        }
+
+        let store = self.write_store_ref();
+        if let Some(blocked_by) = &payload.blocked_by {
+            check_blockers(&store, blocked_by)?;
+        }
+
+        let matching: Vec<Todo> = store
+            .values()
+            .filter(|todo| todo.is_listed() && filter.matches(todo))
+            .cloned()
+            .collect();
+        let matching = sorted_by_id(matching);
+        for todo in matching.iter() {
+            payload.validate(todo.id)?;
+        }
+        let updates: Vec<(Todo, Todo)> = matching
+            .into_iter()
+            .map(|before| {
+                let todo = before.updated_as_of(payload.clone(), self.now());
+                (before, todo)
+            })
+            .collect();
+        let updated = updates.iter().map(|(_, todo)| todo.clone()).collect();
+        self.store_batch(store, updates)?;
+
+        Ok(updated)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn clear_completed(&self) -> anyhow::Result<usize> {
+        let mut store = self.write_store_ref();
+
+        let completed: Vec<Todo> = store
+            .values()
+            .filter(|todo| todo.is_listed() && todo.completed)
+            .cloned()
+            .collect();
+        let removed: Vec<Todo> = sorted_by_id(completed)
+            .into_iter()
+            .filter_map(|todo| self.remove_indexed(&mut store, todo.id, self.on_parent_delete))
+            .collect();
+        drop(store);
+
+        let count = removed.len();
+        for todo in removed {
+            let id = todo.id;
+            self.notify(Some(todo), TodoEvent::Deleted(id));
+        }
+        Ok(count)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn archive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.set_archived(id, true)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn unarchive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.set_archived(id, false)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn archived(&self) -> Vec<Todo> {
+        let store = self.read_store_ref();
+        let todos = store
+            .values()
+            .filter(|todo| !todo.is_deleted() && todo.archived)
+            .cloned()
+            .collect();
+
+        sorted_by_id(todos)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = id)))]
+    async fn log_time(&self, id: i32, minutes: u32) -> anyhow::Result<Todo> {
+        let now = self.now();
+        let (before, todo) = self.change_live(id, |todo| {
+            todo.spent_minutes = todo.spent_minutes.saturating_add(minutes);
+            todo.updated_at = now;
+        })?;
+
+        self.notify(Some(before), TodoEvent::Updated(todo.clone()));
+        Ok(todo)
+    }
+
+    // Calls keep working on a poisoned lock, but what a panicking writer left
+    // behind is worth a look, so the store no longer reports healthy.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn health_check(&self) -> anyhow::Result<()> {
+        if self.store.is_poisoned() {
+            return Err(RepositoryError::StorageUnavailable.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn conformance() {
+        test_utils::run_conformance(TodoRepositoryForMemory::new()).await;
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingMetrics {
+        creates: AtomicUsize,
+        updates: AtomicUsize,
+        deletes: AtomicUsize,
+        find_misses: AtomicUsize,
+    }
+
+    impl Metrics for CountingMetrics {
+        fn on_create(&self, _id: i32) {
+            self.creates.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_update(&self, _id: i32) {
+            self.updates.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_delete(&self, _id: i32) {
+            self.deletes.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_find_miss(&self, _id: i32) {
+            self.find_misses.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn metrics_count_a_crud_run() {
+        let metrics = Arc::new(CountingMetrics::default());
+        let repository = TodoRepositoryForMemory::new().with_metrics(metrics.clone());
+
+        create_todos(&repository, 2).await;
+        complete(&repository, 1).await;
+        repository.toggle_completed(2).await.unwrap();
+        repository.find(1).await.unwrap();
+        repository.delete(1).await.unwrap();
+        assert_eq!(None, repository.find(1).await);
+        assert_eq!(None, repository.find(9).await);
+        assert!(repository.delete(9).await.is_err());
+
+        let count = |counter: &AtomicUsize| counter.load(Ordering::SeqCst);
+        assert_eq!(2, count(&metrics.creates));
+        assert_eq!(2, count(&metrics.updates));
+        assert_eq!(1, count(&metrics.deletes));
+        assert_eq!(2, count(&metrics.find_misses));
+    }
+
+    #[tokio::test]
+    async fn todo_crud_scenario() {
+        let text = "todo  text";
+        let id = 1;
+
+        let reopsitory = TodoRepositoryForMemory::new();
+        let todo = reopsitory
+            .create(CreateTodo::new(text.to_string()))
+            .await
+            .unwrap();
+        let expected = Todo {
+            id,
+            text: text.to_string(),
+            completed: false,
+            created_at: todo.created_at,
+            updated_at: todo.created_at,
+            due_date: None,
+            priority: Priority::Medium,
+            tags: vec![],
+            deleted_at: None,
+            position: 0,
+            parent_id: None,
+            version: 1,
+            archived: false,
+            recurrence: None,
+            description: None,
+            assignee: None,
+            color: None,
+            blocked_by: vec![],
+            estimate_minutes: None,
+            spent_minutes: 0,
+            attachments: vec![],
+        };
+        let created_at = expected.created_at;
+        assert_eq!(expected, todo);
+
+        let todo = reopsitory.find(id).await.unwrap();
+        assert_eq!(expected, todo);
+
+        let todos = reopsitory.all().await;
+        assert_eq!(vec![expected], todos);
+
+        let text = "update todo text";
+        let todo = reopsitory
+            .update(
+                id,
+                UpdateTodo {
+                    text: Some(text.to_string()),
+                    completed: Some(true),
+                    due_date: None,
+                    priority: None,
+                    tags: None,
+                    description: None,
+                    assignee: None,
+                    color: None,
+                    blocked_by: None,
+                    estimate_minutes: None,
+                    attachments: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            Todo {
+                id,
+                text: text.to_string(),
+                completed: true,
+                created_at,
+                updated_at: todo.updated_at,
+                due_date: None,
+                priority: Priority::Medium,
+                tags: vec![],
+                deleted_at: None,
+                position: 0,
+                parent_id: None,
+                version: 2,
+                archived: false,
+                recurrence: None,
+                description: None,
+                assignee: None,
+                color: None,
+                blocked_by: vec![],
+                estimate_minutes: None,
+                spent_minutes: 0,
+                attachments: vec![],
+            },
+            todo
+        );
+
+        let res = reopsitory.delete(id).await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn ids_are_not_reused_after_delete() {
+        let repository = TodoRepositoryForMemory::new();
+        for text in ["first", "second", "third"] {
+            repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .unwrap();
+        }
+
+        repository.delete(2).await.unwrap();
+        let todo = repository
+            .create(CreateTodo::new("fourth".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(4, todo.id);
+
+        let mut ids: Vec<i32> = repository.all().await.iter().map(|todo| todo.id).collect();
+        ids.sort();
+        assert_eq!(vec![1, 3, 4], ids);
+        assert_eq!("third", repository.find(3).await.unwrap().text);
+    }
+
+    async fn create_todos(repository: &TodoRepositoryForMemory, count: usize) {
+        for i in 1..=count {
+            repository
+                .create(CreateTodo::new(format!("todo {}", i)))
+                .await
+                .unwrap();
+        }
+    }
+
+    fn ids(todos: &[Todo]) -> Vec<i32> {
+        todos.iter().map(|todo| todo.id).collect()
+    }
+
+    #[derive(Debug)]
+    struct FixedIds(std::sync::Mutex<Vec<i32>>);
+
+    impl IdGenerator for FixedIds {
+        fn next_id(&self) -> i32 {
+            self.0.lock().unwrap().remove(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn create_uses_a_client_supplied_id() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+
+        let payload = CreateTodo::builder()
+            .id(10)
+            .text("offline")
+            .build()
+            .unwrap();
+        let todo = repository.create(payload).await.unwrap();
+        assert_eq!(10, todo.id);
+        assert_eq!(Some(todo), repository.find(10).await);
+
+        let todo = repository.create("online".into()).await.unwrap();
+        assert_eq!(11, todo.id);
+
+        let payload = CreateTodo::builder().id(5).text("gap").build().unwrap();
+        assert_eq!(5, repository.create(payload).await.unwrap().id);
+        assert_eq!(12, repository.create("next".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn taken_client_ids_conflict() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+        repository.soft_delete(2).await.unwrap();
+
+        for id in [1, 2] {
+            let payload = CreateTodo::builder().id(id).text("taken").build().unwrap();
+            let err = repository.create(payload).await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::Conflict(taken)) if *taken == id
+            ));
+        }
+
+        let payloads =
+            ["a", "b"].map(|text| CreateTodo::builder().id(5).text(text).build().unwrap());
+        let err = repository.bulk_create(payloads.to_vec()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(5))
+        ));
+
+        let payload = CreateTodo::builder().id(0).text("zero").build().unwrap();
+        let err = repository.create(payload).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+        assert_eq!(vec![1], ids(&repository.all().await));
+        assert_eq!(3, repository.create("fallback".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn upsert_creates_missing_ids() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+
+        let payload = CreateTodo::builder()
+            .text("synced")
+            .priority(Priority::High)
+            .tag("work")
+            .build()
+            .unwrap();
+        let todo = repository.upsert(5, payload).unwrap();
+        assert_eq!(5, todo.id);
+        assert_eq!("synced", todo.text);
+        assert_eq!(Priority::High, todo.priority);
+        assert_eq!(tags(&["work"]), todo.tags);
+        assert_eq!(Some(todo), repository.find(5).await);
+        assert_eq!(6, repository.create("next".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn upsert_replaces_existing_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        let payload = CreateTodo::builder()
+            .text("original")
+            .tag("home")
+            .description("to be dropped")
+            .build()
+            .unwrap();
+        let original = repository.create(payload).await.unwrap();
+
+        let payload = CreateTodo::builder()
+            .text("replacement")
+            .completed(true)
+            .build()
+            .unwrap();
+        let todo = repository.upsert(1, payload).unwrap();
+        assert_eq!("replacement", todo.text);
+        assert!(todo.completed);
+        assert!(todo.tags.is_empty());
+        assert_eq!(None, todo.description);
+        assert_eq!(original.created_at, todo.created_at);
+        assert_eq!(original.version + 1, todo.version);
+        assert_eq!(Some(todo), repository.find(1).await);
+        assert_eq!(1, repository.count().await);
+
+        let payload = CreateTodo::builder()
+            .id(2)
+            .text("mismatch")
+            .build()
+            .unwrap();
+        assert!(repository.upsert(1, payload).is_err());
+    }
+
+    #[tokio::test]
+    async fn todos_take_ids_from_an_injected_generator() {
+        let ids = FixedIds(std::sync::Mutex::new(vec![42, 7, 100]));
+        let repository = TodoRepositoryForMemory::new().with_id_generator(ids);
+
+        for (id, text) in [(42, "first"), (7, "second"), (100, "third")] {
+            assert_eq!(id, repository.create(text.into()).await.unwrap().id);
+        }
+        assert_eq!("second", repository.find(7).await.unwrap().text);
+        assert_eq!(100, repository.snapshot().last_id);
+    }
+
+    #[tokio::test]
+    async fn a_generated_id_that_is_taken_is_a_conflict() {
+        let ids = FixedIds(std::sync::Mutex::new(vec![1, 1, 1]));
+        let repository = TodoRepositoryForMemory::new().with_id_generator(ids);
+        let payload = CreateTodo::builder()
+            .text("water the plants")
+            .recurrence(Recurrence::Daily)
+            .build()
+            .unwrap();
+        let todo = repository.create(payload).await.unwrap();
+
+        let err = repository.create("second".into()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(1))
+        ));
+        let err = repository
+            .update(1, UpdateTodo::completed(true))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(1))
+        ));
+        assert_eq!(vec![todo], repository.all().await);
+    }
+
+    #[tokio::test]
+    async fn all_paginated_returns_id_ordered_pages() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 5).await;
+
+        assert_eq!(vec![2, 3], ids(&repository.all_paginated(1, 2).await));
+        assert_eq!(
+            vec![1, 2, 3, 4, 5],
+            ids(&repository.all_paginated(0, 100).await)
+        );
+        assert!(repository.all_paginated(5, 10).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn page_after_continues_from_the_cursor() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 5).await;
+
+        let first = repository.page_after(None, 2).await;
+        assert_eq!(vec![1, 2], ids(&first));
+
+        repository.delete(1).await.unwrap();
+        let cursor = first.last().map(|todo| todo.id);
+        assert_eq!(vec![3, 4], ids(&repository.page_after(cursor, 2).await));
+        assert_eq!(vec![5], ids(&repository.page_after(Some(4), 2).await));
+        assert!(repository.page_after(Some(5), 2).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn page_reports_the_total_regardless_of_the_page() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 5).await;
+        repository.soft_delete(5).await.unwrap();
+
+        let page = repository.page(2, 2).await;
+        assert_eq!(vec![3, 4], ids(&page.items));
+        assert_eq!((4, 2, 2), (page.total, page.offset, page.limit));
+
+        let past_the_end = repository.page(10, 2).await;
+        assert!(past_the_end.items.is_empty());
+        assert_eq!(4, past_the_end.total);
+    }
+
+    async fn complete(repository: &TodoRepositoryForMemory, id: i32) {
+        repository
+            .update(id, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn random_incomplete_only_picks_incomplete_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(None, repository.random_incomplete_with(&mut rng).await);
+        assert_eq!(None, repository.random_incomplete().await);
+
+        create_todos(&repository, 4).await;
+        complete(&repository, 2).await;
+        let picks = |seed| {
+            let repository = repository.clone();
+            async move {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut picks = Vec::new();
+                for _ in 0..30 {
+                    picks.push(
+                        repository
+                            .random_incomplete_with(&mut rng)
+                            .await
+                            .unwrap()
+                            .id,
+                    );
+                }
+                picks
+            }
+        };
+
+        let first = picks(7).await;
+        assert_eq!(first, picks(7).await);
+        let mut seen = first.clone();
+        seen.sort_unstable();
+        seen.dedup();
+        assert_eq!(vec![1, 3, 4], seen);
+        assert!(!repository.random_incomplete().await.unwrap().completed);
+
+        for id in [1, 3, 4] {
+            complete(&repository, id).await;
+        }
+        assert_eq!(None, repository.random_incomplete_with(&mut rng).await);
+    }
+
+    #[tokio::test]
+    async fn find_by_completed_filters_by_status() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 5).await;
+        complete(&repository, 2).await;
+        complete(&repository, 4).await;
+
+        assert_eq!(vec![2, 4], ids(&repository.find_by_completed(true).await));
+        assert_eq!(
+            vec![1, 3, 5],
+            ids(&repository.find_by_completed(false).await)
+        );
+    }
+
+    #[tokio::test]
+    async fn find_duplicates_groups_texts_that_differ_only_in_case() {
+        let repository = TodoRepositoryForMemory::new();
+        for text in [
+            "Call mom",
+            "buy milk",
+            "call mom",
+            "buy milk",
+            "walk the dog",
+        ] {
+            repository.create(text.into()).await.unwrap();
+        }
+
+        let groups: Vec<Vec<i32>> = repository
+            .find_duplicates()
+            .await
+            .iter()
+            .map(|group| ids(group))
+            .collect();
+        assert_eq!(vec![vec![1, 3], vec![2, 4]], groups);
+    }
+
+    #[tokio::test]
+    async fn search_fuzzy_tolerates_typos() {
+        let repository = TodoRepositoryForMemory::new();
+        for text in ["Buy milk", "Walk the dog", "Call mom"] {
+            repository.create(text.into()).await.unwrap();
+        }
+
+        let found = repository.search_fuzzy("buy mlik", 10).await;
+        assert_eq!(
+            vec![1],
+            found.iter().map(|(todo, _)| todo.id).collect::<Vec<_>>()
+        );
+        assert!(repository.search_fuzzy("groceries", 10).await.is_empty());
+        assert!(repository.search_fuzzy(" ", 10).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_fuzzy_ranks_closer_matches_first() {
+        let repository = TodoRepositoryForMemory::new();
+        for text in [
+            "paint the fence",
+            "painting class",
+            "pay the rent",
+            "print tickets",
+        ] {
+            repository.create(text.into()).await.unwrap();
+        }
+
+        let found = repository.search_fuzzy("paint", 10).await;
+        let texts: Vec<&str> = found.iter().map(|(todo, _)| todo.text.as_str()).collect();
+        assert_eq!(
+            vec!["paint the fence", "painting class", "print tickets"],
+            texts
+        );
+        assert!(found.windows(2).all(|pair| pair[0].1 > pair[1].1));
+
+        let found = repository.search_fuzzy("paint", 1).await;
+        assert_eq!("paint the fence", found[0].0.text);
+        assert_eq!(1, found.len());
+    }
+
+    #[tokio::test]
+    async fn search_matches_text_case_insensitively() {
+        let repository = TodoRepositoryForMemory::new();
+        for text in ["Buy Milk", "walk the dog", "buy bread"] {
+            repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(vec![1, 3], ids(&repository.search("BUY").await));
+        assert!(repository.search("laundry").await.is_empty());
+        assert_eq!(vec![1, 2, 3], ids(&repository.search("").await));
+    }
+
+    #[tokio::test]
+    async fn text_up_to_the_max_len_is_kept() {
+        let repository = TodoRepositoryForMemory::new().with_max_text_len(6, OverlongText::Reject);
+
+        let todo = repository.create("héllo".into()).await.unwrap();
+        assert_eq!("héllo", todo.text);
+    }
+
+    #[tokio::test]
+    async fn overlong_text_is_rejected_in_reject_mode() {
+        let repository = TodoRepositoryForMemory::new().with_max_text_len(6, OverlongText::Reject);
+
+        let err = repository.create("too long".into()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+
+        repository.create("short".into()).await.unwrap();
+        let err = repository
+            .update(
+                1,
+                UpdateTodo {
+                    text: Some("too long".to_string()),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+        assert_eq!("short", repository.find(1).await.unwrap().text);
+    }
+
+    #[tokio::test]
+    async fn overlong_text_can_be_truncated() {
+        let repository =
+            TodoRepositoryForMemory::new().with_max_text_len(5, OverlongText::Truncate);
+
+        let todo = repository.create("ünïcode text".into()).await.unwrap();
+        assert_eq!("ünïco", todo.text);
+
+        let todo = repository
+            .update(
+                1,
+                UpdateTodo {
+                    text: Some("much longer".to_string()),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!("much ", todo.text);
+    }
+
+    #[test]
+    fn default_text_limit_is_generous() {
+        let mut text = "a".repeat(10_000);
+        assert!(TextLimit::default().apply(&mut text).is_ok());
+
+        text.push('a');
+        assert!(TextLimit::default().apply(&mut text).is_err());
+    }
+
+    #[tokio::test]
+    async fn create_rejects_blank_text() {
+        let repository = TodoRepositoryForMemory::new();
+
+        for text in ["", "   \t\n"] {
+            let res = repository.create(CreateTodo::new(text.to_string())).await;
+            assert!(res.is_err());
+        }
+        assert!(repository.all().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn overdue_returns_incomplete_past_due_todos() {
+        let now = Utc::now();
+        let repository = TodoRepositoryForMemory::new();
+        for (text, due_date) in [
+            ("past due", Some(now - chrono::Duration::days(1))),
+            ("not yet due", Some(now + chrono::Duration::days(1))),
+            ("completed past due", Some(now - chrono::Duration::days(1))),
+            ("no due date", None),
+        ] {
+            repository
+                .create(CreateTodo {
+                    due_date,
+                    ..CreateTodo::new(text.to_string())
+                })
+                .await
+                .unwrap();
+        }
+        complete(&repository, 3).await;
+
+        assert_eq!(vec![1], ids(&repository.overdue(now).await));
+    }
+
+    #[tokio::test]
+    async fn agenda_puts_overdue_then_upcoming_then_undated() {
+        let now = Utc::now();
+        let days = chrono::Duration::days;
+        let repository = TodoRepositoryForMemory::new();
+        for (text, due_date) in [
+            ("no due date", None),
+            ("in two days", Some(now + days(2))),
+            ("a day overdue", Some(now - days(1))),
+            ("tomorrow", Some(now + days(1))),
+            ("a week overdue", Some(now - days(7))),
+            ("also no due date", None),
+            ("completed and overdue", Some(now - days(3))),
+            ("due right now", Some(now)),
+        ] {
+            repository
+                .create(CreateTodo {
+                    due_date,
+                    ..CreateTodo::new(text.to_string())
+                })
+                .await
+                .unwrap();
+        }
+        complete(&repository, 7).await;
+
+        let texts: Vec<String> = repository
+            .agenda(now)
+            .await
+            .into_iter()
+            .map(|todo| todo.text)
+            .collect();
+        assert_eq!(
+            vec![
+                "a week overdue",
+                "a day overdue",
+                "due right now",
+                "tomorrow",
+                "in two days",
+                "no due date",
+                "also no due date",
+            ],
+            texts
+        );
+    }
+
+    #[tokio::test]
+    async fn due_within_returns_upcoming_todos_soonest_first() {
+        let now = Utc::now();
+        let hours = chrono::Duration::hours;
+        let repository = TodoRepositoryForMemory::new();
+        for (text, due_date) in [
+            ("in three hours", Some(now + hours(3))),
+            ("in one hour", Some(now + hours(1))),
+            ("just past the window", Some(now + hours(4))),
+            ("overdue", Some(now - hours(1))),
+            ("completed in two hours", Some(now + hours(2))),
+            ("due right now", Some(now)),
+            ("no due date", None),
+        ] {
+            repository
+                .create(CreateTodo {
+                    due_date,
+                    ..CreateTodo::new(text.to_string())
+                })
+                .await
+                .unwrap();
+        }
+        complete(&repository, 5).await;
+
+        assert_eq!(
+            vec![6, 2, 1],
+            ids(&repository.due_within(now, hours(4)).await)
+        );
+    }
+
+    #[tokio::test]
+    async fn priority_defaults_to_medium_and_is_filterable() {
+        let payload: CreateTodo = serde_json::from_str(r#"{"text": "no priority"}"#).unwrap();
+        assert_eq!(Priority::Medium, payload.priority);
+
+        let repository = TodoRepositoryForMemory::new();
+        let todo = repository.create(payload).await.unwrap();
+        assert_eq!(Priority::Medium, todo.priority);
+        for priority in [Priority::High, Priority::Low, Priority::High] {
+            repository
+                .create(CreateTodo {
+                    priority,
+                    ..CreateTodo::new("with priority".to_string())
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            vec![2, 4],
+            ids(&repository.find_by_priority(Priority::High).await)
+        );
+        assert_eq!(
+            vec![3],
+            ids(&repository.find_by_priority(Priority::Low).await)
+        );
+        assert_eq!(
+            vec![1],
+            ids(&repository.find_by_priority(Priority::Medium).await)
+        );
+    }
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn find_by_tag_matches_any_of_a_todos_tags() {
+        let repository = TodoRepositoryForMemory::new();
+        for todo_tags in [
+            tags(&["home", "urgent"]),
+            tags(&["work"]),
+            tags(&["urgent", "work", "urgent"]),
+        ] {
+            repository
+                .create(CreateTodo {
+                    tags: todo_tags,
+                    ..CreateTodo::new("tagged".to_string())
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            tags(&["urgent", "work"]),
+            repository.find(3).await.unwrap().tags
+        );
+        assert_eq!(vec![1, 3], ids(&repository.find_by_tag("urgent").await));
+        assert_eq!(vec![1], ids(&repository.find_by_tag("home").await));
+        assert!(repository.find_by_tag("garden").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tag_index_follows_tag_changes() {
+        let repository = TodoRepositoryForMemory::new();
+        for todo_tags in [tags(&["home"]), tags(&["home", "urgent"])] {
+            repository
+                .create(CreateTodo {
+                    tags: todo_tags,
+                    ..CreateTodo::new("tagged".to_string())
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(vec![1, 2], repository.tag_index().ids("home"));
+
+        repository
+            .update(
+                1,
+                UpdateTodo {
+                    tags: Some(tags(&["work"])),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(vec![2], repository.tag_index().ids("home"));
+        assert_eq!(vec![1], repository.tag_index().ids("work"));
+        assert_eq!(vec![1], ids(&repository.find_by_tag("work").await));
+        assert_eq!(vec![2], ids(&repository.find_by_tag("home").await));
+
+        let snapshot = repository.snapshot();
+        repository.delete(2).await.unwrap();
+        assert!(repository.tag_index().ids("home").is_empty());
+        assert!(repository.tag_index().ids("urgent").is_empty());
+        assert!(repository.find_by_tag("home").await.is_empty());
+
+        repository.restore_snapshot(snapshot);
+        assert_eq!(vec![2], repository.tag_index().ids("urgent"));
+        repository.soft_delete(2).await.unwrap();
+        assert_eq!(vec![2], repository.tag_index().ids("urgent"));
+        assert!(repository.find_by_tag("urgent").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tag_counts_tally_each_tag_across_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        for todo_tags in [
+            tags(&["home", "urgent"]),
+            tags(&["work", "urgent"]),
+            tags(&["urgent", "work", "urgent"]),
+            tags(&["garden"]),
+            tags(&[]),
+        ] {
+            repository
+                .create(CreateTodo {
+                    tags: todo_tags,
+                    ..CreateTodo::new("tagged".to_string())
+                })
+                .await
+                .unwrap();
+        }
+        repository.soft_delete(4).await.unwrap();
+
+        assert_eq!(
+            HashMap::from([
+                ("home".to_string(), 1),
+                ("urgent".to_string(), 3),
+                ("work".to_string(), 2),
+            ]),
+            repository.tag_counts().await
+        );
+    }
+
+    #[tokio::test]
+    async fn all_tags_is_the_sorted_union_of_every_todos_tags() {
+        let repository = TodoRepositoryForMemory::new();
+        for todo_tags in [
+            tags(&["work", "urgent"]),
+            tags(&["home", "urgent"]),
+            tags(&["work"]),
+            tags(&["garden"]),
+        ] {
+            repository
+                .create(CreateTodo {
+                    tags: todo_tags,
+                    ..CreateTodo::new("tagged".to_string())
+                })
+                .await
+                .unwrap();
+        }
+        repository.soft_delete(4).await.unwrap();
+
+        assert_eq!(
+            tags(&["home", "urgent", "work"]),
+            repository.all_tags().await
+        );
+    }
+
+    #[tokio::test]
+    async fn count_and_count_completed() {
+        let repository = TodoRepositoryForMemory::new();
+        assert_eq!(0, repository.count().await);
+
+        create_todos(&repository, 3).await;
+        complete(&repository, 2).await;
+        assert_eq!(3, repository.count().await);
+        assert_eq!(1, repository.count_completed().await);
+    }
+
+    #[tokio::test]
+    async fn ordered_storage_returns_all_by_id() {
+        let repository = TodoRepositoryForMemory::new().with_ordered_storage();
+        for id in [50, 3, 17, 8, 41, 1] {
+            repository
+                .upsert(id, CreateTodo::new(format!("todo {}", id)))
+                .unwrap();
+        }
+        create_todos(&repository, 2).await;
+        repository.reassign_id(17, 60).unwrap();
+        repository.delete(8).await.unwrap();
+
+        assert_eq!(vec![1, 3, 41, 50, 51, 52, 60], ids(&repository.all().await));
+        repository.restore_snapshot(repository.snapshot());
+        repository.clear_all().await.unwrap();
+        create_todos(&repository, 3).await;
+        repository
+            .upsert(2, CreateTodo::new("two".to_string()))
+            .unwrap();
+        assert_eq!(vec![2, 61, 62, 63], ids(&repository.all().await));
+    }
+
+    #[tokio::test]
+    async fn create_fails_cleanly_once_ids_run_out() {
+        let repository = TodoRepositoryForMemory::seeded(vec![Todo::new(
+            i32::MAX - 1,
+            "nearly the last".to_string(),
+        )]);
+        let is_exhausted = |e: &anyhow::Error| {
+            matches!(
+                e.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::IdSpaceExhausted)
+            )
+        };
+
+        let payloads = vec![
+            CreateTodo::new("one".to_string()),
+            CreateTodo::new("two".to_string()),
+        ];
+        assert!(is_exhausted(
+            &repository.bulk_create(payloads).await.unwrap_err()
+        ));
+        let last = repository.create("the last".into()).await.unwrap();
+        assert_eq!(i32::MAX, last.id);
+
+        let err = repository.create("one too many".into()).await.unwrap_err();
+        assert!(is_exhausted(&err));
+        assert!(is_exhausted(
+            &repository.find_or_create("one too many").await.unwrap_err()
+        ));
+        assert_eq!(2, repository.count().await);
+
+        let payload = CreateTodo::builder().id(5).text("own id").build().unwrap();
+        assert_eq!(5, repository.create(payload).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn check_invariants_passes_after_regular_use() {
+        let repository = TodoRepositoryForMemory::new();
+        repository.check_invariants().unwrap();
+
+        let payload = CreateTodo::builder()
+            .text("tagged")
+            .tag("home")
+            .build()
+            .unwrap();
+        repository.create(payload).await.unwrap();
+        create_todos(&repository, 3).await;
+        repository.delete(2).await.unwrap();
+        repository.soft_delete(3).await.unwrap();
+        repository.reassign_id(4, 9).unwrap();
+        repository.reindex();
+
+        repository.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_invariants_finds_a_corrupted_store() {
+        let message = |repository: &TodoRepositoryForMemory| {
+            let err = repository.check_invariants().unwrap_err();
+            match err.downcast_ref::<RepositoryError>() {
+                Some(RepositoryError::Inconsistent(problem)) => problem.clone(),
+                _ => panic!("unexpected error: {}", err),
+            }
+        };
+
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+        let todo = repository.write_store_ref().remove(&2).unwrap();
+        repository.write_store_ref().insert(7, todo);
+        assert_eq!("todo 2 is stored under 7", message(&repository));
+
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+        let mut todo = repository.write_store_ref().remove(&2).unwrap();
+        todo.id = 5;
+        repository.write_store_ref().insert(5, todo);
+        assert_eq!(
+            "todo 5 is past the last id handed out, 2",
+            message(&repository)
+        );
+
+        let repository = TodoRepositoryForMemory::new();
+        for id in [1, 2] {
+            let payload = CreateTodo::builder()
+                .id(id)
+                .text("tagged")
+                .tag("home")
+                .build()
+                .unwrap();
+            repository.create(payload).await.unwrap();
+        }
+        let todo = repository.find(1).await.unwrap();
+        repository.tag_index().remove(&todo);
+        assert_eq!(
+            "tag home is indexed for [2] but held by [1, 2]",
+            message(&repository)
+        );
+    }
+
+    #[tokio::test]
+    async fn completing_a_recurring_todo_fails_cleanly_once_ids_run_out() {
+        let recurring = Todo {
+            recurrence: Some(Recurrence::Daily),
+            due_date: Some(Utc::now()),
+            ..Todo::new(i32::MAX, "water the plants".to_string())
+        };
+        let repository = TodoRepositoryForMemory::seeded(vec![recurring.clone()]);
+        let is_exhausted = |e: &anyhow::Error| {
+            matches!(
+                e.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::IdSpaceExhausted)
+            )
+        };
+
+        let err = repository
+            .update(i32::MAX, UpdateTodo::completed(true))
+            .await
+            .unwrap_err();
+        assert!(is_exhausted(&err));
+        assert!(is_exhausted(
+            &repository.toggle_completed(i32::MAX).await.unwrap_err()
+        ));
+        assert!(is_exhausted(&repository.complete_all().await.unwrap_err()));
+        let payload = UpdateTodo::completed(true);
+        let err = repository
+            .update_where(TodoFilter::default(), payload)
+            .await
+            .unwrap_err();
+        assert!(is_exhausted(&err));
+        assert_eq!(vec![recurring], repository.all().await);
+
+        let payload = CreateTodo::builder().id(1).text("parent").build().unwrap();
+        repository.create(payload).await.unwrap();
+        let child = CreateTodo {
+            parent_id: Some(1),
+            recurrence: Some(Recurrence::Daily),
+            ..CreateTodo::new("water the plants".to_string())
+        };
+        repository.upsert(i32::MAX, child).unwrap();
+        let repository = repository.with_cascade_mode(CascadeMode::CompleteChildren);
+        let err = repository
+            .update(1, UpdateTodo::completed(true))
+            .await
+            .unwrap_err();
+        assert!(is_exhausted(&err));
+        assert!(!repository.find(1).await.unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn is_empty_goes_by_what_all_returns() {
+        let repository = TodoRepositoryForMemory::new();
+        assert!(repository.is_empty().await);
+
+        create_todos(&repository, 1).await;
+        assert!(!repository.is_empty().await);
+        repository.delete(1).await.unwrap();
+        assert!(repository.is_empty().await);
+
+        create_todos(&repository, 2).await;
+        repository.soft_delete(2).await.unwrap();
+        complete(&repository, 3).await;
+        repository.archive(3).await.unwrap();
+        assert!(repository.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn max_id_is_the_highest_live_id() {
+        let repository = TodoRepositoryForMemory::new();
+        assert_eq!(None, repository.max_id().await);
+
+        create_todos(&repository, 4).await;
+        repository.delete(2).await.unwrap();
+        assert_eq!(Some(4), repository.max_id().await);
+
+        repository.delete(4).await.unwrap();
+        assert_eq!(Some(3), repository.max_id().await);
+        repository.soft_delete(3).await.unwrap();
+        assert_eq!(Some(1), repository.max_id().await);
+
+        complete(&repository, 1).await;
+        repository.archive(1).await.unwrap();
+        assert_eq!(Some(1), repository.max_id().await);
+        repository.delete(1).await.unwrap();
+        assert_eq!(None, repository.max_id().await);
+    }
+
+    #[tokio::test]
+    async fn update_bumps_updated_at() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository
+            .create(CreateTodo::new("todo text".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(created.created_at, created.updated_at);
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let updated = repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    due_date: None,
+                    priority: None,
+                    tags: None,
+                    description: None,
+                    assignee: None,
+                    color: None,
+                    blocked_by: None,
+                    estimate_minutes: None,
+                    attachments: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(created.created_at, updated.created_at);
+        assert!(updated.updated_at > created.updated_at);
+    }
+
+    #[tokio::test]
+    async fn bulk_create_assigns_contiguous_ids() {
+        let repository = TodoRepositoryForMemory::new();
+        repository
+            .create(CreateTodo::new("existing".to_string()))
+            .await
+            .unwrap();
+
+        let payloads = (1..=100)
+            .map(|i| CreateTodo::new(format!("todo {}", i)))
+            .collect();
+        let todos = repository.bulk_create(payloads).await.unwrap();
+
+        assert_eq!((2..=101).collect::<Vec<i32>>(), ids(&todos));
+        assert_eq!(101, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn bulk_create_never_generates_a_client_id_from_the_same_batch() {
+        let repository = TodoRepositoryForMemory::new().with_events(16);
+        let mut events = repository.subscribe();
+
+        let payloads = vec![
+            CreateTodo::new("generated".to_string()),
+            CreateTodo {
+                id: Some(1),
+                ..CreateTodo::new("client".to_string())
+            },
+        ];
+        let todos = repository.bulk_create(payloads).await.unwrap();
+
+        assert_eq!(vec![2, 1], ids(&todos));
+        let texts: Vec<String> = sorted_by_id(repository.all().await)
+            .into_iter()
+            .map(|todo| todo.text)
+            .collect();
+        assert_eq!(vec!["client", "generated"], texts);
+        for todo in todos {
+            assert_eq!(TodoEvent::Created(todo), events.recv().await.unwrap());
+        }
+        assert_eq!(3, repository.create("next".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn bulk_create_rejects_the_whole_batch_on_invalid_payload() {
+        let repository = TodoRepositoryForMemory::new();
+
+        let payloads = vec![
+            CreateTodo::new("valid".to_string()),
+            CreateTodo::new(" ".to_string()),
+        ];
+        assert!(repository.bulk_create(payloads).await.is_err());
+        assert_eq!(0, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn bulk_delete_reports_per_id_results() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 3).await;
+
+        let report = repository.bulk_delete(vec![1, 4, 3, 1]).await.unwrap();
+        assert_eq!(vec![1, 3], report.deleted);
+        assert_eq!(vec![4, 1], report.not_found);
+        assert_eq!(vec![2], ids(&repository.all().await));
+    }
+
+    #[tokio::test]
+    async fn toggle_completed_flips_back_and_forth() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+
+        let todo = repository.toggle_completed(1).await.unwrap();
+        assert!(todo.completed);
+        let todo = repository.toggle_completed(1).await.unwrap();
+        assert!(!todo.completed);
+        assert_eq!(Some(todo), repository.find(1).await);
+
+        let err = repository.toggle_completed(2).await.unwrap_err();
+        assert!(is_not_found(&err));
+    }
+
+    #[tokio::test]
+    async fn all_ids_lists_the_ids_all_returns_in_order() {
+        let repository = TodoRepositoryForMemory::new();
+        assert!(repository.all_ids().is_empty());
+
+        create_todos(&repository, 5).await;
+        repository.delete(2).await.unwrap();
+        repository.soft_delete(4).await.unwrap();
+
+        assert_eq!(vec![1, 3, 5], repository.all_ids());
+        assert_eq!(
+            ids(&sorted_by_id(repository.all().await)),
+            repository.all_ids()
+        );
+    }
+
+    #[tokio::test]
+    async fn all_json_is_an_array_of_todo_objects() {
+        let repository = TodoRepositoryForMemory::new();
+        assert_eq!(serde_json::json!([]), repository.all_json());
+
+        create_todos(&repository, 3).await;
+        repository.archive(2).await.unwrap();
+        let value = repository.all_json();
+        let todos = value.as_array().unwrap();
+        assert_eq!(2, todos.len());
+        for (todo, id) in todos.iter().zip([1, 3]) {
+            let todo = todo.as_object().unwrap();
+            assert_eq!(Some(&serde_json::json!(id)), todo.get("id"));
+            for key in ["text", "completed", "created_at", "tags", "priority"] {
+                assert!(todo.contains_key(key), "{} is missing", key);
+            }
+        }
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&repository.export_json().await).unwrap(),
+            value
+        );
+    }
+
+    #[tokio::test]
+    async fn with_todo_lends_the_todo_to_the_closure() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+        repository.soft_delete(2).await.unwrap();
+
+        let text = repository.with_todo(1, |todo| todo.text.clone());
+        assert_eq!(Some("todo 1".to_string()), text);
+        assert_eq!(None, repository.with_todo(2, |todo| todo.id));
+        assert_eq!(None, repository.with_todo(3, |todo| todo.id));
+    }
+
+    #[tokio::test]
+    async fn filter_applies_any_predicate() {
+        let repository = TodoRepositoryForMemory::new();
+        for text in ["a", "bbbb", "cc", "dddddd", "eeeee"] {
+            repository.create(text.into()).await.unwrap();
+        }
+        for id in [2, 3, 5] {
+            complete(&repository, id).await;
+        }
+        repository.archive(5).await.unwrap();
+
+        let todos = repository.filter(|todo| todo.completed && todo.text.len() > 1);
+        assert_eq!(vec![2, 3], ids(&todos));
+        let todos = repository.filter(|todo| !todo.completed && todo.text.len() > 3);
+        assert_eq!(vec![4], ids(&todos));
+        assert!(repository.filter(|_| false).is_empty());
+    }
+
+    fn chunk_ids(chunks: &[Vec<Todo>]) -> Vec<Vec<i32>> {
+        chunks.iter().map(|chunk| ids(chunk)).collect()
+    }
+
+    #[tokio::test]
+    async fn chunks_split_todos_in_id_order() {
+        let repository = TodoRepositoryForMemory::new();
+        assert!(repository.chunks(2).is_empty());
+        assert!(repository.chunks(0).is_empty());
+
+        create_todos(&repository, 6).await;
+        assert_eq!(
+            vec![vec![1, 2, 3], vec![4, 5, 6]],
+            chunk_ids(&repository.chunks(3))
+        );
+
+        repository.delete(6).await.unwrap();
+        assert_eq!(
+            vec![vec![1, 2], vec![3, 4], vec![5]],
+            chunk_ids(&repository.chunks(2))
+        );
+        assert_eq!(vec![vec![1, 2, 3, 4, 5]], chunk_ids(&repository.chunks(0)));
+    }
+
+    #[tokio::test]
+    async fn bump_priority_moves_one_level_and_clamps() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+        let bump = |id, up| {
+            let repository = repository.clone();
+            async move { repository.bump_priority(id, up).await.unwrap().priority }
+        };
+
+        assert_eq!(Priority::High, bump(1, true).await);
+        assert_eq!(Priority::High, bump(1, true).await);
+        assert_eq!(Priority::Medium, bump(1, false).await);
+        assert_eq!(Priority::Low, bump(2, false).await);
+        assert_eq!(Priority::Low, bump(2, false).await);
+        assert_eq!(Priority::Medium, bump(2, true).await);
+
+        let err = repository.bump_priority(3, true).await.unwrap_err();
+        assert!(is_not_found(&err));
+    }
+
+    #[tokio::test]
+    async fn toggle_many_reports_each_id() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 3).await;
+        complete(&repository, 2).await;
+        repository.soft_delete(3).await.unwrap();
+
+        let results = repository.toggle_many(vec![1, 2, 3, 7]);
+        assert_eq!(
+            vec![1, 2, 3, 7],
+            results.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+        );
+        assert!(results[0].1.as_ref().unwrap().completed);
+        assert!(!results[1].1.as_ref().unwrap().completed);
+        assert!(is_not_found(results[2].1.as_ref().unwrap_err()));
+        assert!(is_not_found(results[3].1.as_ref().unwrap_err()));
+
+        assert!(repository.find(1).await.unwrap().completed);
+        assert!(!repository.find(2).await.unwrap().completed);
+        assert!(repository.toggle_many(Vec::new()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn apply_runs_each_op_and_reports_on_it() {
+        let repository = TodoRepositoryForMemory::new().with_events(16);
+        let mut events = repository.subscribe();
+        create_todos(&repository, 2).await;
+
+        let results = repository.apply(
+            vec![
+                TodoOp::Create(CreateTodo::new("todo 3".to_string())),
+                TodoOp::Update {
+                    id: 1,
+                    payload: UpdateTodo::completed(true),
+                },
+                TodoOp::Delete(2),
+                TodoOp::Delete(7),
+            ],
+            false,
+        );
+        assert_eq!(4, results.len());
+        assert!(matches!(&results[0], OpResult::Created(todo) if todo.id == 3));
+        assert!(matches!(&results[1], OpResult::Updated(todo) if todo.completed));
+        assert!(matches!(results[2], OpResult::Deleted(2)));
+        assert!(matches!(&results[3], OpResult::Failed(e) if is_not_found(e)));
+
+        assert_eq!(vec![1, 3], ids(&sorted_by_id(repository.all().await)));
+        assert!(repository.find(1).await.unwrap().completed);
+        for _ in 0..2 {
+            events.recv().await.unwrap();
+        }
+        assert!(matches!(events.recv().await.unwrap(), TodoEvent::Created(todo) if todo.id == 3));
+        assert_eq!(
+            TodoEvent::Updated(repository.find(1).await.unwrap()),
+            events.recv().await.unwrap()
+        );
+        assert_eq!(TodoEvent::Deleted(2), events.recv().await.unwrap());
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn apply_all_or_nothing_rolls_back_on_a_failed_op() {
+        let repository = TodoRepositoryForMemory::new().with_events(16);
+        create_todos(&repository, 2).await;
+        let mut events = repository.subscribe();
+        let before = repository.snapshot();
+
+        let results = repository.apply(
+            vec![
+                TodoOp::Create(CreateTodo::new("todo 3".to_string())),
+                TodoOp::Delete(1),
+                TodoOp::Update {
+                    id: 7,
+                    payload: UpdateTodo::completed(true),
+                },
+                TodoOp::Delete(2),
+            ],
+            true,
+        );
+        assert_eq!(4, results.len());
+        assert!(matches!(results[0], OpResult::RolledBack));
+        assert!(matches!(results[1], OpResult::RolledBack));
+        assert!(matches!(&results[2], OpResult::Failed(e) if is_not_found(e)));
+        assert!(matches!(results[3], OpResult::RolledBack));
+
+        assert_eq!(before, repository.snapshot());
+        assert!(events.try_recv().is_err());
+        assert_eq!(3, repository.create("todo 3".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn duplicates_copy_the_content_but_start_over() {
+        let repository = TodoRepositoryForMemory::new();
+        let due_date = Utc::now() + chrono::Duration::days(3);
+        let payload = CreateTodo::builder()
+            .text("water the plants")
+            .tag("home")
+            .priority(Priority::High)
+            .due_date(due_date)
+            .build()
+            .unwrap();
+        let source = repository.create(payload).await.unwrap();
+        let source = repository
+            .update(source.id, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+
+        let copy = repository.duplicate(source.id).await.unwrap();
+        assert_ne!(source.id, copy.id);
+        assert!(!copy.completed);
+        assert_eq!(source.text, copy.text);
+        assert_eq!(source.tags, copy.tags);
+        assert_eq!(source.priority, copy.priority);
+        assert_eq!(source.due_date, copy.due_date);
+        assert!(copy.created_at >= source.updated_at);
+        assert_eq!(Some(source), repository.find(1).await);
+
+        assert!(is_not_found(&repository.duplicate(9).await.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn merge_folds_tags_and_description_into_the_kept_todo() {
+        let repository = TodoRepositoryForMemory::new();
+        for (text, tags, description) in [
+            ("buy milk", ["shop", "home"], "semi-skimmed"),
+            ("Buy milk", ["home", "urgent"], "two bottles"),
+        ] {
+            let payload = CreateTodo::builder()
+                .text(text)
+                .tag(tags[0])
+                .tag(tags[1])
+                .description(description)
+                .build()
+                .unwrap();
+            repository.create(payload).await.unwrap();
+        }
+
+        let todo = repository.merge(1, 2).await.unwrap();
+        assert_eq!("buy milk", todo.text);
+        assert_eq!(tags(&["shop", "home", "urgent"]), todo.tags);
+        assert_eq!(
+            Some("semi-skimmed\n\ntwo bottles".to_string()),
+            todo.description
+        );
+        assert_eq!(Some(todo), repository.find(1).await);
+        assert_eq!(None, repository.find(2).await);
+    }
+
+    #[tokio::test]
+    async fn merge_needs_two_distinct_existing_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+
+        let err = repository.merge(1, 2).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(2))
+        ));
+        let err = repository.merge(1, 1).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+        assert!(repository.find(1).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn soft_deleted_todos_are_hidden_until_restored() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+
+        repository.soft_delete(1).await.unwrap();
+        assert_eq!(vec![2], ids(&repository.all().await));
+        assert_eq!(None, repository.find(1).await);
+        assert_eq!(1, repository.count().await);
+        assert!(is_not_found(
+            &repository
+                .update(1, UpdateTodo::completed(true))
+                .await
+                .unwrap_err()
+        ));
+        assert!(is_not_found(&repository.soft_delete(1).await.unwrap_err()));
+
+        let restored = repository.restore(1).await.unwrap();
+        assert_eq!(None, restored.deleted_at);
+        assert_eq!(Some(restored), repository.find(1).await);
+        assert_eq!(vec![1, 2], ids(&sorted_by_id(repository.all().await)));
+    }
+
+    #[tokio::test]
+    async fn find_many_preserves_input_order_and_skips_missing() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 4).await;
+        repository.soft_delete(2).await.unwrap();
+
+        let todos = repository.find_many(&[3, 9, 1, 2, 4]).await;
+        assert_eq!(vec![3, 1, 4], ids(&todos));
+        assert!(repository.find_many(&[]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn find_map_keys_the_todos_that_exist_by_id() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 3).await;
+        repository.soft_delete(2).await.unwrap();
+
+        let todos = repository.find_map(&[3, 9, 1, 2, 3]).await;
+        let mut keys: Vec<i32> = todos.keys().copied().collect();
+        keys.sort();
+        assert_eq!(vec![1, 3], keys);
+        assert_eq!(repository.find(3).await.as_ref(), todos.get(&3));
+    }
+
+    #[tokio::test]
+    async fn validate_rejects_what_create_rejects() {
+        let repository = TodoRepositoryForMemory::new().with_max_text_len(6, OverlongText::Reject);
+        create_todos(&repository, 1).await;
+        let before = repository.export_json().await;
+
+        let invalid = [
+            CreateTodo::new(" ".to_string()),
+            CreateTodo::new("too long".to_string()),
+            CreateTodo {
+                color: Some("red".to_string()),
+                ..CreateTodo::new("paint".to_string())
+            },
+            CreateTodo {
+                parent_id: Some(9),
+                ..CreateTodo::new("child".to_string())
+            },
+            CreateTodo {
+                blocked_by: vec![9],
+                ..CreateTodo::new("later".to_string())
+            },
+        ];
+        for payload in invalid {
+            let err = repository.validate(&payload).unwrap_err();
+            let create_err = repository.create(payload).await.unwrap_err();
+            assert_eq!(err.to_string(), create_err.to_string());
+        }
+
+        let valid = CreateTodo {
+            parent_id: Some(1),
+            ..CreateTodo::new("child".to_string())
+        };
+        assert!(repository.validate(&valid).is_ok());
+        assert_eq!(before, repository.export_json().await);
+        assert!(repository.create(valid).await.is_ok());
+    }
+
+    fn changed_fields(diff: &TodoDiff) -> Vec<&str> {
+        diff.changes
+            .iter()
+            .map(|change| change.field.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn diff_lists_a_single_changed_field() {
+        let old = Todo::new(1, "buy milk".to_string());
+        let new = Todo {
+            text: "buy oat milk".to_string(),
+            ..old.clone()
+        };
+
+        assert_eq!(
+            TodoDiff {
+                changes: vec![FieldChange {
+                    field: "text".to_string(),
+                    before: serde_json::json!("buy milk"),
+                    after: serde_json::json!("buy oat milk"),
+                }],
+            },
+            diff(&old, &new)
+        );
+    }
+
+    #[test]
+    fn diff_lists_every_changed_field() {
+        let mut old = Todo::new(1, "buy milk".to_string());
+        old.updated_at -= chrono::Duration::seconds(1);
+        let new = old.updated(UpdateTodo {
+            completed: Some(true),
+            tags: Some(tags(&["errand"])),
+            estimate_minutes: Some(Some(15)),
+            ..UpdateTodo::default()
+        });
+
+        let diff = diff(&old, &new);
+        assert_eq!(
+            vec![
+                "completed",
+                "estimate_minutes",
+                "tags",
+                "updated_at",
+                "version"
+            ],
+            changed_fields(&diff)
+        );
+        assert_eq!(serde_json::json!(false), diff.changes[0].before);
+        assert_eq!(serde_json::json!(true), diff.changes[0].after);
+    }
+
+    #[test]
+    fn diff_of_identical_todos_is_empty() {
+        let todo = Todo::new(1, "buy milk".to_string());
+
+        assert!(diff(&todo, &todo.clone()).is_empty());
+    }
+
+    #[test]
+    fn minimal_update_sets_a_single_changed_field() {
+        let current = Todo::new(1, "buy milk".to_string());
+        let desired = Todo {
+            priority: Priority::High,
+            ..current.clone()
+        };
+
+        let payload = minimal_update(&current, &desired);
+        assert_eq!(
+            UpdateTodo {
+                priority: Some(Priority::High),
+                ..UpdateTodo::default()
+            },
+            payload
+        );
+        let updated = current.updated_as_of(payload, current.updated_at);
+        assert_eq!(
+            desired,
+            Todo {
+                version: desired.version,
+                ..updated
+            }
+        );
+    }
+
+    #[test]
+    fn minimal_update_of_identical_todos_sets_nothing() {
+        let todo = Todo::new(1, "buy milk".to_string());
+
+        assert_eq!(UpdateTodo::default(), minimal_update(&todo, &todo.clone()));
+    }
+
+    #[test]
+    fn minimal_update_sets_every_changed_field() {
+        let current = Todo {
+            description: Some("the usual".to_string()),
+            ..Todo::new(1, "buy milk".to_string())
+        };
+        let desired = Todo {
+            text: "buy oat milk".to_string(),
+            completed: true,
+            due_date: Some(Utc::now() + chrono::Duration::days(1)),
+            priority: Priority::Low,
+            tags: tags(&["errand"]),
+            description: None,
+            assignee: Some("sam".to_string()),
+            color: Some("#00ff00".to_string()),
+            blocked_by: vec![2],
+            estimate_minutes: Some(15),
+            attachments: vec![Attachment {
+                name: "list.txt".to_string(),
+                url: "https://example.com/list.txt".to_string(),
+                size_bytes: 12,
+            }],
+            ..current.clone()
+        };
+
+        let payload = minimal_update(&current, &desired);
+        assert_eq!(
+            UpdateTodo {
+                text: Some(desired.text.clone()),
+                completed: Some(true),
+                due_date: desired.due_date,
+                priority: Some(Priority::Low),
+                tags: Some(desired.tags.clone()),
+                description: Some(None),
+                assignee: Some(desired.assignee.clone()),
+                color: Some(desired.color.clone()),
+                blocked_by: Some(vec![2]),
+                estimate_minutes: Some(Some(15)),
+                attachments: Some(desired.attachments.clone()),
+            },
+            payload
+        );
+    }
+
+    #[tokio::test]
+    async fn reset_ids_restarts_numbering_once_the_store_is_empty() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 3).await;
+        repository.clear_all().await.unwrap();
+
+        repository.reset_ids().unwrap();
+        assert_eq!(1, repository.create("first".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn reset_ids_is_refused_while_todos_remain() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+        repository.delete(1).await.unwrap();
+        repository.soft_delete(2).await.unwrap();
+
+        let err = repository.reset_ids().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+        assert_eq!(3, repository.create("next".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn transaction_commits_every_change_on_ok() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+
+        let count = repository
+            .transaction(|store| {
+                store.get_mut(&1).unwrap().completed = true;
+                store.remove(&2);
+                store.insert(5, Todo::new(5, "five".to_string()));
+                Ok(store.len())
+            })
+            .unwrap();
+        assert_eq!(2, count);
+        assert!(repository.find(1).await.unwrap().completed);
+        assert_eq!(None, repository.find(2).await);
+        assert_eq!("five", repository.find(5).await.unwrap().text);
+        assert_eq!(6, repository.create("next".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn transaction_rolls_back_on_err() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+        let before = repository.snapshot();
+
+        let err = repository
+            .transaction(|store| -> anyhow::Result<()> {
+                store.get_mut(&1).unwrap().completed = true;
+                store.remove(&2);
+                store.insert(5, Todo::new(5, "five".to_string()));
+                Err(RepositoryError::Conflict(2).into())
+            })
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(2))
+        ));
+        assert_eq!(before, repository.snapshot());
+        assert_eq!(3, repository.create("next".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn reassign_id_moves_the_todo_and_what_points_at_it() {
+        let repository = TodoRepositoryForMemory::new().with_events(16);
+        create_todos(&repository, 1).await;
+        let payload = CreateTodo::builder()
+            .text("child")
+            .parent_id(1)
+            .blocked_by(1)
+            .build()
+            .unwrap();
+        repository.create(payload).await.unwrap();
+
+        let mut events = repository.subscribe();
+
+        let todo = repository.reassign_id(1, 10).unwrap();
+        assert_eq!(10, todo.id);
+        assert_eq!("todo 1", todo.text);
+        assert_eq!(None, repository.find(1).await);
+        assert_eq!(Some(todo), repository.find(10).await);
+        let child = repository.find(2).await.unwrap();
+        assert_eq!(Some(10), child.parent_id);
+        assert_eq!(vec![10], child.blocked_by);
+        assert_eq!(2, child.version);
+        assert_eq!(TodoEvent::Deleted(1), events.recv().await.unwrap());
+        assert!(matches!(events.recv().await.unwrap(), TodoEvent::Created(todo) if todo.id == 10));
+        assert_eq!(TodoEvent::Updated(child), events.recv().await.unwrap());
+
+        let next = repository.create("next".into()).await.unwrap();
+        assert_eq!(11, next.id);
+    }
+
+    #[tokio::test]
+    async fn reindex_closes_the_gaps_left_by_deletes() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 12).await;
+        for id in [2, 3, 4, 6, 7, 8, 9, 10, 11] {
+            repository.delete(id).await.unwrap();
+        }
+        let payload = CreateTodo::builder()
+            .text("child")
+            .parent_id(5)
+            .blocked_by(12)
+            .build()
+            .unwrap();
+        repository.create(payload).await.unwrap();
+        repository.soft_delete(12).await.unwrap();
+
+        assert_eq!(3, repository.reindex());
+        let todos = sorted_by_id(repository.all().await);
+        assert_eq!(vec![1, 2, 4], ids(&todos));
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(vec!["todo 1", "todo 5", "child"], texts);
+        assert_eq!(Some(2), todos[2].parent_id);
+        assert_eq!(vec![3], todos[2].blocked_by);
+        assert_eq!("todo 12", repository.restore(3).await.unwrap().text);
+
+        assert_eq!(0, repository.reindex());
+        assert_eq!(5, repository.create("next".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn reassign_id_rejects_missing_sources_and_taken_targets() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 3).await;
+        repository.soft_delete(3).await.unwrap();
+
+        assert!(is_not_found(&repository.reassign_id(9, 20).unwrap_err()));
+        assert!(is_not_found(&repository.reassign_id(3, 20).unwrap_err()));
+        for invalid in [0, -1] {
+            let err = repository.reassign_id(1, invalid).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::Validation(_))
+            ));
+        }
+        for taken in [2, 3] {
+            let err = repository.reassign_id(1, taken).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::Conflict(id)) if *id == taken
+            ));
+        }
+        assert_eq!("todo 1", repository.find(1).await.unwrap().text);
+    }
+
+    #[tokio::test]
+    async fn next_task_prefers_priority_then_due_date_then_id() {
+        let repository = TodoRepositoryForMemory::new();
+        let due = |days| Utc::now() + chrono::Duration::days(days);
+        let payloads = [
+            CreateTodo::builder().text("low").priority(Priority::Low),
+            CreateTodo::builder().text("undated"),
+            CreateTodo::builder().text("later").due_date(due(2)),
+            CreateTodo::builder().text("sooner").due_date(due(1)),
+            CreateTodo::builder()
+                .text("urgent")
+                .priority(Priority::High),
+        ];
+        for payload in payloads {
+            repository.create(payload.build().unwrap()).await.unwrap();
+        }
+
+        let mut order = Vec::new();
+        while let Some(todo) = repository.next_task().await {
+            order.push(todo.text.clone());
+            complete(&repository, todo.id).await;
+        }
+        assert_eq!(vec!["urgent", "sooner", "later", "undated", "low"], order);
+    }
+
+    #[tokio::test]
+    async fn next_task_is_none_when_nothing_is_left_to_do() {
+        let repository = TodoRepositoryForMemory::new();
+        assert_eq!(None, repository.next_task().await);
+
+        create_todos(&repository, 2).await;
+        repository.complete_all().await.unwrap();
+        assert_eq!(None, repository.next_task().await);
+    }
+
+    #[tokio::test]
+    async fn todos_can_be_created_already_completed() {
+        let repository = TodoRepositoryForMemory::new();
+        let payload = CreateTodo::builder()
+            .text("fixed the sink")
+            .completed(true)
+            .build()
+            .unwrap();
+
+        let done = repository.create(payload).await.unwrap();
+        assert!(done.completed);
+        assert_eq!(Some(done), repository.find(1).await);
+
+        let payload: CreateTodo = serde_json::from_str(r#"{"text": "buy milk"}"#).unwrap();
+        assert!(!repository.create(payload).await.unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn log_time_adds_up_across_calls() {
+        let repository = TodoRepositoryForMemory::new();
+        let payload = CreateTodo::builder()
+            .text("write report")
+            .estimate_minutes(90)
+            .build()
+            .unwrap();
+        let todo = repository.create(payload).await.unwrap();
+        assert_eq!(Some(90), todo.estimate_minutes);
+        assert_eq!(0, todo.spent_minutes);
+
+        repository.log_time(todo.id, 25).await.unwrap();
+        let todo = repository.log_time(todo.id, 40).await.unwrap();
+        assert_eq!(65, todo.spent_minutes);
+        assert_eq!(3, todo.version);
+        assert_eq!(Some(todo), repository.find(1).await);
+        assert!(is_not_found(&repository.log_time(9, 5).await.unwrap_err()));
+
+        let todo = repository
+            .update(
+                1,
+                UpdateTodo {
+                    estimate_minutes: Some(None),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(None, todo.estimate_minutes);
+        assert_eq!(65, todo.spent_minutes);
+    }
+
+    #[tokio::test]
+    async fn total_spent_sums_every_listed_todo() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 3).await;
+        assert_eq!(0, repository.total_spent().await);
+
+        repository.log_time(1, 30).await.unwrap();
+        repository.log_time(2, 15).await.unwrap();
+        repository.log_time(3, 10).await.unwrap();
+        repository.soft_delete(3).await.unwrap();
+        assert_eq!(45, repository.total_spent().await);
+    }
+
+    #[tokio::test]
+    async fn clear_all_empties_the_store_without_reusing_ids() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 3).await;
+        repository.soft_delete(3).await.unwrap();
+
+        repository.clear_all().await.unwrap();
+        assert!(repository.all().await.is_empty());
+        assert_eq!(0, repository.count().await);
+        assert!(is_not_found(&repository.restore(3).await.unwrap_err()));
+
+        let todo = repository
+            .create(CreateTodo::new("after clear".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(4, todo.id);
+    }
+
+    #[tokio::test]
+    async fn missing_id_errors_downcast_to_not_found() {
+        let repository = TodoRepositoryForMemory::new();
+
+        let err = repository
+            .update(1, UpdateTodo::completed(true))
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<RepositoryError>() {
+            Some(RepositoryError::NotFound(id)) => assert_eq!(1, *id),
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+
+        let err = repository.delete(1).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(1))
+        ));
+    }
+
+    fn positions(todos: &[Todo]) -> Vec<(i32, i32)> {
+        todos.iter().map(|todo| (todo.id, todo.position)).collect()
+    }
+
+    #[tokio::test]
+    async fn reorder_keeps_positions_contiguous() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 4).await;
+        assert_eq!(
+            vec![(1, 0), (2, 1), (3, 2), (4, 3)],
+            positions(&repository.all_by_position().await)
+        );
+
+        repository.reorder(4, 0).await.unwrap();
+        assert_eq!(
+            vec![(4, 0), (1, 1), (2, 2), (3, 3)],
+            positions(&repository.all_by_position().await)
+        );
+
+        repository.reorder(4, 2).await.unwrap();
+        assert_eq!(
+            vec![(1, 0), (2, 1), (4, 2), (3, 3)],
+            positions(&repository.all_by_position().await)
+        );
+
+        assert!(repository.reorder(1, 4).await.is_err());
+        assert!(is_not_found(&repository.reorder(5, 0).await.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn todos_move_to_either_end() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 5).await;
+
+        repository.move_to_top(3).await.unwrap();
+        assert_eq!(
+            vec![(3, 0), (1, 1), (2, 2), (4, 3), (5, 4)],
+            positions(&repository.all_by_position().await)
+        );
+
+        repository.move_to_bottom(2).await.unwrap();
+        assert_eq!(
+            vec![(3, 0), (1, 1), (4, 2), (5, 3), (2, 4)],
+            positions(&repository.all_by_position().await)
+        );
+
+        complete(&repository, 1).await;
+        repository.archive(1).await.unwrap();
+        repository.move_to_bottom(3).await.unwrap();
+        assert_eq!(
+            vec![(4, 1), (5, 2), (2, 3), (3, 4)],
+            positions(&repository.all_by_position().await)
+        );
+        assert!(is_not_found(&repository.move_to_top(6).await.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn stats_summarises_the_store() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 4).await;
+        repository
+            .create(CreateTodo {
+                due_date: Some(Utc::now() - chrono::Duration::days(1)),
+                ..CreateTodo::new("overdue".to_string())
+            })
+            .await
+            .unwrap();
+        complete(&repository, 1).await;
+        complete(&repository, 2).await;
+        repository.soft_delete(3).await.unwrap();
+
+        assert_eq!(
+            TodoStats {
+                total: 4,
+                completed: 2,
+                incomplete: 2,
+                overdue: 1,
+            },
+            repository.stats().await
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn concurrent_creates_get_unique_ids() {
+        let repository = TodoRepositoryForMemory::new();
+
+        let handles: Vec<_> = (0..16)
+            .map(|task| {
+                let repository = repository.clone();
+                tokio::spawn(async move {
+                    for i in 0..50 {
+                        repository
+                            .create(CreateTodo::new(format!("task {} todo {}", task, i)))
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let mut ids = ids(&repository.all().await);
+        ids.sort();
+        assert_eq!((1..=800).collect::<Vec<i32>>(), ids);
+        assert_eq!(800, repository.count().await);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn concurrent_changes_to_different_todos_all_land() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 16).await;
+
+        let mut handles: Vec<_> = (1..=16)
+            .map(|id| {
+                let repository = repository.clone();
+                tokio::spawn(async move {
+                    for i in 0..50 {
+                        let payload = UpdateTodo {
+                            text: Some(format!("todo {} edit {}", id, i)),
+                            tags: Some(vec![format!("tag {}", i % 3)]),
+                            ..UpdateTodo::default()
+                        };
+                        repository.update(id, payload).await.unwrap();
+                        repository.log_time(id, 1).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        // Calls on the whole store go on alongside the ones on single todos.
+        let whole = repository.clone();
+        handles.push(tokio::spawn(async move {
+            for i in 0..50 {
+                whole
+                    .create(CreateTodo::new(format!("extra {}", i)))
+                    .await
+                    .unwrap();
+                assert!(whole.all().await.len() >= 16);
+            }
+        }));
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        for id in 1..=16 {
+            let todo = repository.find(id).await.unwrap();
+            assert_eq!(format!("todo {} edit 49", id), todo.text);
+            assert_eq!(50, todo.spent_minutes);
+            assert_eq!(101, todo.version);
+        }
+        assert_eq!(66, repository.count().await);
+        repository.check_invariants().unwrap();
+    }
+
+    #[tokio::test]
+    async fn completions_by_day_counts_every_day_in_the_range() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 5).await;
+        for id in 1..=4 {
+            complete(&repository, id).await;
+        }
+        let day = |d| NaiveDate::from_ymd_opt(2026, 10, d).unwrap();
+        let at = |d, h| day(d).and_hms_opt(h, 0, 0).unwrap().and_utc();
+        {
+            let mut store = repository.write_store_ref();
+            store.get_mut(&1).unwrap().updated_at = at(1, 9);
+            store.get_mut(&2).unwrap().updated_at = at(1, 17);
+            store.get_mut(&3).unwrap().updated_at = at(4, 12);
+            store.get_mut(&4).unwrap().updated_at = at(9, 12);
+            store.get_mut(&5).unwrap().updated_at = at(2, 12);
+        }
+
+        let counts = repository.completions_by_day(at(1, 0), at(5, 0)).await;
+        assert_eq!(
+            BTreeMap::from([
+                (day(1), 2),
+                (day(2), 0),
+                (day(3), 0),
+                (day(4), 1),
+                (day(5), 0)
+            ]),
+            counts
+        );
+    }
+
+    #[tokio::test]
+    async fn completed_between_orders_by_completion_time() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 6).await;
+        for id in 1..=5 {
+            complete(&repository, id).await;
+        }
+        let from = Utc::now() - chrono::Duration::days(7);
+        let to = Utc::now();
+        {
+            let mut store = repository.write_store_ref();
+            store.get_mut(&1).unwrap().updated_at = from - chrono::Duration::seconds(1);
+            store.get_mut(&2).unwrap().updated_at = to;
+            store.get_mut(&3).unwrap().updated_at = from;
+            store.get_mut(&4).unwrap().updated_at = to + chrono::Duration::seconds(1);
+            store.get_mut(&5).unwrap().updated_at = from + chrono::Duration::days(1);
+            store.get_mut(&6).unwrap().updated_at = from + chrono::Duration::days(1);
+        }
+
+        assert_eq!(
+            vec![3, 5, 2],
+            ids(&repository.completed_between(from, to).await)
+        );
+    }
+
+    #[tokio::test]
+    async fn created_between_is_inclusive() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 4).await;
+        let start = Utc::now() - chrono::Duration::days(7);
+        let end = Utc::now();
+        {
+            let mut store = repository.write_store_ref();
+            store.get_mut(&1).unwrap().created_at = start - chrono::Duration::seconds(1);
+            store.get_mut(&2).unwrap().created_at = start;
+            store.get_mut(&3).unwrap().created_at = start + chrono::Duration::days(1);
+            store.get_mut(&4).unwrap().created_at = end;
+        }
+
+        assert_eq!(
+            vec![2, 3, 4],
+            ids(&repository.created_between(start, end).await)
+        );
+        assert!(repository.created_between(end, start).await.is_empty());
+    }
+
+    async fn create_child(
+        repository: &TodoRepositoryForMemory,
+        parent_id: i32,
+    ) -> anyhow::Result<Todo> {
+        repository
+            .create(CreateTodo {
+                parent_id: Some(parent_id),
+                ..CreateTodo::new(format!("child of {}", parent_id))
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn children_require_an_existing_parent() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+
+        let child = create_child(&repository, 1).await.unwrap();
+        assert_eq!(Some(1), child.parent_id);
+        assert_eq!(vec![child], repository.children(1).await);
+
+        let err = create_child(&repository, 9).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(9))
+        ));
+        assert_eq!(2, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_parent_orphans_children_by_default() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+        create_child(&repository, 1).await.unwrap();
+
+        repository.delete(1).await.unwrap();
+        assert_eq!(None, repository.find(2).await.unwrap().parent_id);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_parent_can_cascade() {
+        let repository =
+            TodoRepositoryForMemory::new().with_on_parent_delete(OnParentDelete::Cascade);
+        create_todos(&repository, 2).await;
+        create_child(&repository, 1).await.unwrap();
+        create_child(&repository, 3).await.unwrap();
+
+        repository.delete(1).await.unwrap();
+        assert_eq!(vec![2], ids(&repository.all().await));
+    }
+
+    #[tokio::test]
+    async fn a_todo_cannot_be_merged_into_its_descendants() {
+        let repository =
+            TodoRepositoryForMemory::new().with_on_parent_delete(OnParentDelete::Cascade);
+        create_todos(&repository, 2).await;
+        create_child(&repository, 1).await.unwrap();
+        create_child(&repository, 3).await.unwrap();
+
+        for keep_id in [3, 4] {
+            let err = repository.merge(keep_id, 1).await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::Validation(_))
+            ));
+        }
+        assert_eq!(vec![1, 2, 3, 4], ids(&sorted_by_id(repository.all().await)));
+
+        let todo = repository.merge(2, 1).await.unwrap();
+        assert_eq!(Some(todo), repository.find(2).await);
+        assert_eq!(vec![2], ids(&repository.all().await));
+    }
+
+    // Todo 1 with two open children, 2 and 3, and a grandchild, 4, under 3.
+    async fn create_family(cascade_mode: CascadeMode) -> TodoRepositoryForMemory {
+        let repository = TodoRepositoryForMemory::new().with_cascade_mode(cascade_mode);
+        create_todos(&repository, 1).await;
+        create_child(&repository, 1).await.unwrap();
+        create_child(&repository, 1).await.unwrap();
+        create_child(&repository, 3).await.unwrap();
+
+        repository
+    }
+
+    async fn completed_ids(repository: &TodoRepositoryForMemory) -> Vec<i32> {
+        ids(&repository.find_by_completed(true).await)
+    }
+
+    #[tokio::test]
+    async fn completing_a_parent_ignores_children_by_default() {
+        let repository = create_family(CascadeMode::default()).await;
+
+        complete(&repository, 1).await;
+        assert_eq!(vec![1], completed_ids(&repository).await);
+    }
+
+    #[tokio::test]
+    async fn completing_a_parent_can_complete_its_children() {
+        let repository = create_family(CascadeMode::CompleteChildren).await;
+
+        complete(&repository, 1).await;
+        assert_eq!(vec![1, 2, 3, 4], completed_ids(&repository).await);
+
+        repository
+            .update(1, UpdateTodo::completed(false))
+            .await
+            .unwrap();
+        assert_eq!(vec![2, 3, 4], completed_ids(&repository).await);
+    }
+
+    #[tokio::test]
+    async fn completing_a_parent_can_be_blocked_by_open_children() {
+        let repository = create_family(CascadeMode::BlockIfChildrenOpen).await;
+
+        let blocked_by_2 = |err: anyhow::Error| {
+            matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::Conflict(2))
+            )
+        };
+        let err = repository
+            .update(1, UpdateTodo::completed(true))
+            .await
+            .unwrap_err();
+        assert!(blocked_by_2(err));
+        assert!(blocked_by_2(
+            repository.toggle_completed(1).await.unwrap_err()
+        ));
+        assert!(blocked_by_2(repository.complete(1).await.unwrap_err()));
+        assert!(completed_ids(&repository).await.is_empty());
+
+        complete(&repository, 2).await;
+        let err = repository.toggle_completed(3).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(4))
+        ));
+        let results = repository.toggle_many(vec![1, 4, 3, 1]);
+        assert!(results[0].1.is_err());
+        assert!(results[1..].iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(vec![1, 2, 3, 4], completed_ids(&repository).await);
+    }
+
+    #[tokio::test]
+    async fn batch_completions_follow_the_cascade_mode() {
+        let parents = || TodoFilter {
+            text_contains: Some("todo 1".to_string()),
+            ..TodoFilter::default()
+        };
+
+        let repository = create_family(CascadeMode::BlockIfChildrenOpen).await;
+        let err = repository.complete_where(parents()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(2))
+        ));
+        let err = repository
+            .update_where(parents(), UpdateTodo::completed(true))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(2))
+        ));
+        assert!(completed_ids(&repository).await.is_empty());
+        assert_eq!(4, repository.complete_all().await.unwrap());
+        assert_eq!(vec![1, 2, 3, 4], completed_ids(&repository).await);
+
+        let repository = create_family(CascadeMode::CompleteChildren).await;
+        let completed = repository.complete_where(parents()).await.unwrap();
+        assert_eq!(vec![1], ids(&completed));
+        assert_eq!(vec![1, 2, 3, 4], completed_ids(&repository).await);
+        assert!(repository.all().await.iter().all(|todo| todo.version == 2));
+    }
+
+    #[tokio::test]
+    async fn export_csv_round_trips_tricky_text() {
+        let repository = TodoRepositoryForMemory::new();
+        let texts = [
+            "plain",
+            "milk, eggs, bread",
+            "say \"hi\"",
+            "first line\nsecond line",
+        ];
+        for text in texts {
+            repository
+                .create(CreateTodo {
+                    tags: tags(&["home", "errand"]),
+                    ..CreateTodo::new(text.to_string())
+                })
+                .await
+                .unwrap();
+        }
+
+        let csv = repository.export_csv().await;
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(
+            CSV_HEADER.to_vec(),
+            reader.headers().unwrap().iter().collect::<Vec<_>>()
+        );
+        let records: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(texts.len(), records.len());
+        for (record, todo) in records.iter().zip(repository.all_by_position().await) {
+            assert_eq!(todo.id.to_string(), record[0]);
+            assert_eq!(todo.text, record[1]);
+            assert_eq!("false", &record[2]);
+            assert_eq!("", &record[5]);
+            assert_eq!("Medium", &record[6]);
+            assert_eq!("home;errand", &record[7]);
+        }
+    }
+
+    fn backup() -> Vec<Todo> {
+        vec![
+            Todo::new(2, "backup 2".to_string()),
+            Todo::new(5, "backup 5".to_string()),
+        ]
+    }
+
+    #[tokio::test]
+    async fn import_skips_taken_ids_by_default() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+
+        let report = repository
+            .import(backup(), ConflictPolicy::default())
+            .unwrap();
+        assert_eq!(
+            ImportReport {
+                imported: vec![5],
+                skipped: vec![2],
+                overwritten: vec![],
+            },
+            report
+        );
+        assert_eq!("todo 2", repository.find(2).await.unwrap().text);
+        assert_eq!("backup 5", repository.find(5).await.unwrap().text);
+        assert_eq!(6, repository.create("next".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn import_can_overwrite_taken_ids() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+        repository.soft_delete(2).await.unwrap();
+
+        let report = repository
+            .import(backup(), ConflictPolicy::Overwrite)
+            .unwrap();
+        assert_eq!(
+            ImportReport {
+                imported: vec![5],
+                skipped: vec![],
+                overwritten: vec![2],
+            },
+            report
+        );
+        assert_eq!("backup 2", repository.find(2).await.unwrap().text);
+        assert_eq!(vec![1, 2, 5], ids(&sorted_by_id(repository.all().await)));
+    }
+
+    #[tokio::test]
+    async fn import_fails_without_importing_anything_on_a_taken_id() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+
+        let err = repository
+            .import(backup(), ConflictPolicy::Fail)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(2))
+        ));
+        assert_eq!(None, repository.find(5).await);
+        assert_eq!("todo 2", repository.find(2).await.unwrap().text);
+        assert_eq!(3, repository.create("next".into()).await.unwrap().id);
+
+        let empty = TodoRepositoryForMemory::new();
+        let report = empty.import(backup(), ConflictPolicy::Fail).unwrap();
+        assert_eq!(vec![2, 5], report.imported);
+    }
+
+    // Exports a few varied todos in `format` and imports them into an empty
+    // repository, which must end up with the same todos apart from the
+    // timestamps and versions.
+    async fn assert_round_trip(format: SerializationFormat) {
+        let source = TodoRepositoryForMemory::new();
+        create_todos(&source, 2).await;
+        let payload = CreateTodo::builder()
+            .text("child")
+            .parent_id(1)
+            .tag("work")
+            .priority(Priority::High)
+            .due_date(Utc::now() + chrono::Duration::days(1))
+            .description("with \"quotes\"\nand a newline")
+            .attachment(Attachment {
+                name: "notes.txt".to_string(),
+                url: "https://example.com/notes.txt".to_string(),
+                size_bytes: 42,
+            })
+            .build()
+            .unwrap();
+        source.create(payload).await.unwrap();
+        complete(&source, 2).await;
+
+        let data = source.export(format).await.unwrap();
+        let destination = TodoRepositoryForMemory::new();
+        assert_eq!(3, destination.import_from(format, &data).await.unwrap());
+
+        let content = |todos: Vec<Todo>| {
+            sorted_by_id(todos)
+                .into_iter()
+                .map(|todo| Todo {
+                    created_at: DateTime::<Utc>::MIN_UTC,
+                    updated_at: DateTime::<Utc>::MIN_UTC,
+                    version: 1,
+                    ..todo
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            content(source.all().await),
+            content(destination.all().await)
+        );
+        assert!(destination
+            .import_from(format, b"\x01 not todos")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn json_exports_round_trip() {
+        assert_round_trip(SerializationFormat::Json).await;
+    }
+
+    #[cfg(feature = "toml")]
+    #[tokio::test]
+    async fn toml_exports_round_trip() {
+        assert_round_trip(SerializationFormat::Toml).await;
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[tokio::test]
+    async fn message_pack_exports_round_trip() {
+        assert_round_trip(SerializationFormat::MessagePack).await;
+    }
+
+    #[tokio::test]
+    async fn share_tokens_decode_to_the_same_todo() {
+        let repository = TodoRepositoryForMemory::new();
+        let payload = CreateTodo::builder()
+            .text("plan the trip / \"summer\" edition")
+            .tag("travel")
+            .priority(Priority::High)
+            .description("flights, hotel & car")
+            .build()
+            .unwrap();
+        let todo = repository.create(payload).await.unwrap();
+
+        let token = repository.share_token(todo.id).await.unwrap();
+        assert!(token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(todo, Todo::from_share_token(&token).unwrap());
+        assert!(is_not_found(&repository.share_token(7).await.unwrap_err()));
+    }
+
+    #[test]
+    fn malformed_share_tokens_are_rejected() {
+        let todo = Todo::new(1, "todo 1".to_string());
+        let token = todo.share_token();
+        let not_a_todo = URL_SAFE_NO_PAD.encode(br#"{"text":"todo 1"}"#);
+
+        for token in ["not base64!", &token[..token.len() / 2], &not_a_todo] {
+            let err = Todo::from_share_token(token).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::Validation(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn import_json_reassigns_ids() {
+        let source = TodoRepositoryForMemory::new();
+        create_todos(&source, 3).await;
+        source
+            .create(CreateTodo {
+                priority: Priority::High,
+                tags: tags(&["work"]),
+                parent_id: Some(3),
+                ..CreateTodo::new("child".to_string())
+            })
+            .await
+            .unwrap();
+        complete(&source, 2).await;
+        source.delete(1).await.unwrap();
+
+        let destination = TodoRepositoryForMemory::new();
+        let imported = destination.import_json(&source.export_json().await).await;
+        assert_eq!(3, imported.unwrap());
+
+        let todos = sorted_by_id(destination.all().await);
+        assert_eq!(vec![1, 2, 3], ids(&todos));
+        let summary: Vec<(&str, bool, Priority, Option<i32>)> = todos
+            .iter()
+            .map(|todo| {
+                (
+                    todo.text.as_str(),
+                    todo.completed,
+                    todo.priority,
+                    todo.parent_id,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("todo 2", true, Priority::Medium, None),
+                ("todo 3", false, Priority::Medium, None),
+                ("child", false, Priority::High, Some(2)),
+            ],
+            summary
+        );
+        assert_eq!(tags(&["work"]), todos[2].tags);
+        assert!(destination.import_json("not json").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn import_json_keeps_completed_recurring_todos_as_they_are() {
+        let source = TodoRepositoryForMemory::new();
+        let payload = CreateTodo::builder()
+            .text("water the plants")
+            .recurrence(Recurrence::Daily)
+            .due_date(Utc::now())
+            .build()
+            .unwrap();
+        source.create(payload).await.unwrap();
+        complete(&source, 1).await;
+        assert_eq!(2, source.count().await);
+
+        let destination = TodoRepositoryForMemory::new().with_events(16);
+        let mut events = destination.subscribe();
+        let imported = destination.import_json(&source.export_json().await).await;
+        assert_eq!(2, imported.unwrap());
+
+        let todos = sorted_by_id(destination.all().await);
+        assert_eq!(
+            vec![true, false],
+            todos.iter().map(|todo| todo.completed).collect::<Vec<_>>()
+        );
+        assert!(todos.iter().all(|todo| todo.version == 1));
+        for _ in 0..2 {
+            assert!(matches!(
+                events.recv().await.unwrap(),
+                TodoEvent::Created(_)
+            ));
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn timestamps_come_from_the_injected_clock() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap()
+            .and_utc();
+        let clock = Arc::new(clock::FixedClock::new(start));
+        let repository = TodoRepositoryForMemory::new().with_clock(clock.clone());
+
+        let todo = repository.create("buy milk".into()).await.unwrap();
+        assert_eq!(start, todo.created_at);
+        assert_eq!(start, todo.updated_at);
+
+        clock.advance(chrono::Duration::minutes(5));
+        let todo = repository
+            .update(todo.id, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+        assert_eq!(start, todo.created_at);
+        assert_eq!(start + chrono::Duration::minutes(5), todo.updated_at);
+
+        let later = start + chrono::Duration::days(31);
+        clock.set(later);
+        repository.soft_delete(todo.id).await.unwrap();
+        let deleted = &repository.snapshot().todos[&todo.id];
+        assert_eq!(Some(later), deleted.deleted_at);
+    }
+
+    #[tokio::test]
+    async fn stale_returns_incomplete_todos_untouched_for_long_oldest_first() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap()
+            .and_utc();
+        let clock = Arc::new(clock::FixedClock::new(start + chrono::Duration::days(1)));
+        let repository = TodoRepositoryForMemory::new().with_clock(clock.clone());
+        repository.create("old".into()).await.unwrap();
+        clock.set(start);
+        repository.create("older".into()).await.unwrap();
+        let payload = CreateTodo::builder()
+            .text("old but completed")
+            .completed(true)
+            .build()
+            .unwrap();
+        repository.create(payload).await.unwrap();
+        repository.create("old but touched".into()).await.unwrap();
+
+        clock.set(start + chrono::Duration::days(29));
+        repository
+            .update(
+                4,
+                UpdateTodo {
+                    text: Some("recently touched".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let now = start + chrono::Duration::days(30);
+        let stale = repository.stale(now, chrono::Duration::days(7)).await;
+        assert_eq!(vec![2, 1], ids(&stale));
+        assert!(repository
+            .stale(now, chrono::Duration::days(30))
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_if_version_applies_when_the_version_matches() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+
+        let todo = repository.find(1).await.unwrap();
+        assert_eq!(1, todo.version);
+        let todo = repository
+            .update_if_version(1, todo.version, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+        assert!(todo.completed);
+        assert_eq!(2, todo.version);
+        assert_eq!(Some(todo), repository.find(1).await);
+    }
+
+    #[tokio::test]
+    async fn update_if_version_rejects_stale_versions() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+        let stale = repository.find(1).await.unwrap();
+        complete(&repository, 1).await;
+
+        let err = repository
+            .update_if_version(1, stale.version, UpdateTodo::completed(false))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(1))
+        ));
+        let todo = repository.find(1).await.unwrap();
+        assert!(todo.completed);
+        assert_eq!(2, todo.version);
+    }
+
+    #[tokio::test]
+    async fn update_from_detects_updates_made_since_the_base_was_read() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+        let first = repository.find(1).await.unwrap();
+        let second = repository.find(1).await.unwrap();
+
+        let rename = |text: &str| UpdateTodo {
+            text: Some(text.to_string()),
+            ..UpdateTodo::default()
+        };
+        let todo = repository
+            .update_from(&first, rename("from the first"))
+            .await
+            .unwrap();
+        let err = repository
+            .update_from(&second, rename("from the second"))
+            .await
+            .unwrap_err();
+        assert!(is_conflict(&err));
+        assert_eq!(Some(todo.clone()), repository.find(1).await);
+
+        let todo = repository
+            .update_from(&todo, rename("from the second"))
+            .await
+            .unwrap();
+        assert_eq!("from the second", todo.text);
+    }
+
+    #[tokio::test]
+    async fn update_from_detects_changes_made_outside_update() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+        let payload = || UpdateTodo::completed(true);
+
+        let base = repository.find(1).await.unwrap();
+        repository.log_time(1, 30).await.unwrap();
+        assert!(is_conflict(
+            &repository.update_from(&base, payload()).await.unwrap_err()
+        ));
+
+        let base = repository.find(1).await.unwrap();
+        repository.archive(1).await.unwrap();
+        assert!(is_conflict(
+            &repository.update_from(&base, payload()).await.unwrap_err()
+        ));
+
+        let base = repository.find(1).await.unwrap();
+        repository.soft_delete(1).await.unwrap();
+        let todo = repository.restore(1).await.unwrap();
+        assert!(is_conflict(
+            &repository.update_from(&base, payload()).await.unwrap_err()
+        ));
+        assert!(!repository.find(1).await.unwrap().completed);
+
+        assert!(
+            repository
+                .update_from(&todo, payload())
+                .await
+                .unwrap()
+                .completed
+        );
+    }
+
+    #[tokio::test]
+    async fn replace_returns_the_todo_before_and_after() {
+        let repository = TodoRepositoryForMemory::new();
+        let created = repository.create("draft".into()).await.unwrap();
+
+        let (previous, updated) = repository
+            .replace(
+                1,
+                UpdateTodo {
+                    text: Some("final".to_string()),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(created, previous);
+        assert_eq!("final", updated.text);
+        assert_eq!(2, updated.version);
+        assert_eq!(Some(updated), repository.find(1).await);
+
+        let err = repository
+            .replace(2, UpdateTodo::completed(true))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(2))
+        ));
+    }
+
+    #[test]
+    fn builder_builds_a_minimal_todo() {
+        let payload = CreateTodo::builder().text("minimal").build().unwrap();
+        assert_eq!(CreateTodo::new("minimal".to_string()), payload);
+    }
+
+    #[test]
+    fn builder_sets_every_field() {
+        let due_date = Utc::now();
+        let payload = CreateTodo::builder()
+            .text("full")
+            .due_date(due_date)
+            .priority(Priority::High)
+            .tag("home")
+            .tag("errand")
+            .parent_id(1)
+            .build()
+            .unwrap();
+        assert_eq!(
+            CreateTodo {
+                id: None,
+                text: "full".to_string(),
+                due_date: Some(due_date),
+                priority: Priority::High,
+                tags: tags(&["home", "errand"]),
+                parent_id: Some(1),
+                recurrence: None,
+                description: None,
+                assignee: None,
+                color: None,
+                blocked_by: vec![],
+                estimate_minutes: None,
+                completed: None,
+                attachments: vec![],
+            },
+            payload
+        );
+    }
+
+    #[test]
+    fn builder_requires_text() {
+        let err = CreateTodo::builder()
+            .priority(Priority::Low)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, RepositoryError::Validation(_)));
+    }
+
+    async fn query_ids(repository: &TodoRepositoryForMemory, filter: TodoFilter) -> Vec<i32> {
+        ids(&repository.query(filter).await)
+    }
+
+    async fn create_query_fixture() -> TodoRepositoryForMemory {
+        let repository = TodoRepositoryForMemory::new();
+        let now = Utc::now();
+        let payloads = [
+            (
+                "Buy milk",
+                Some(now - chrono::Duration::days(1)),
+                &["home"][..],
+            ),
+            (
+                "buy bread",
+                Some(now + chrono::Duration::days(1)),
+                &["home"],
+            ),
+            (
+                "Write report",
+                Some(now - chrono::Duration::days(2)),
+                &["work"],
+            ),
+            ("Buy a gift", None, &["home", "errand"]),
+        ];
+        for (text, due_date, todo_tags) in payloads {
+            repository
+                .create(CreateTodo {
+                    due_date,
+                    tags: tags(todo_tags),
+                    ..CreateTodo::new(text.to_string())
+                })
+                .await
+                .unwrap();
+        }
+        complete(&repository, 3).await;
+
+        repository
+    }
+
+    #[tokio::test]
+    async fn query_with_no_criteria_returns_everything() {
+        let repository = create_query_fixture().await;
+        assert_eq!(
+            vec![1, 2, 3, 4],
+            query_ids(&repository, TodoFilter::default()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn query_filters_by_each_criterion() {
+        let repository = create_query_fixture().await;
+
+        let filter = TodoFilter {
+            completed: Some(false),
+            ..TodoFilter::default()
+        };
+        assert_eq!(vec![1, 2, 4], query_ids(&repository, filter).await);
+
+        let filter = TodoFilter {
+            tag: Some("errand".to_string()),
+            ..TodoFilter::default()
+        };
+        assert_eq!(vec![4], query_ids(&repository, filter).await);
+
+        let filter = TodoFilter {
+            due_before: Some(Utc::now()),
+            ..TodoFilter::default()
+        };
+        assert_eq!(vec![1, 3], query_ids(&repository, filter).await);
+
+        let filter = TodoFilter {
+            text_contains: Some("BUY".to_string()),
+            ..TodoFilter::default()
+        };
+        assert_eq!(vec![1, 2, 4], query_ids(&repository, filter).await);
+    }
+
+    #[tokio::test]
+    async fn query_combines_criteria_with_and() {
+        let repository = create_query_fixture().await;
+
+        let filter = TodoFilter {
+            completed: Some(false),
+            tag: Some("home".to_string()),
+            due_before: Some(Utc::now()),
+            text_contains: Some("buy".to_string()),
+        };
+        assert_eq!(vec![1], query_ids(&repository, filter).await);
+    }
+
+    #[test]
+    fn query_strings_parse_into_filters() {
+        let parse = |query| TodoFilter::parse(query).unwrap();
+        assert_eq!(TodoFilter::default(), parse("  "));
+        assert_eq!(Some(false), parse("completed:false").completed);
+        assert_eq!(Some(true), parse("completed:true").completed);
+        assert_eq!(Some("work".to_string()), parse("tag:work").tag);
+        assert_eq!(Some("milk".to_string()), parse("text:milk").text_contains);
+        assert_eq!(
+            Some("buy some milk".to_string()),
+            parse("buy  some milk").text_contains
+        );
+
+        let midnight = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc();
+        assert_eq!(Some(midnight), parse("due<2024-01-01").due_before);
+        assert_eq!(
+            Some(midnight + chrono::Duration::hours(3)),
+            parse("due<2024-01-01T12:00:00+09:00").due_before
+        );
+
+        assert_eq!(
+            TodoFilter {
+                completed: Some(false),
+                tag: Some("work".to_string()),
+                due_before: Some(midnight),
+                text_contains: Some("report".to_string()),
+            },
+            parse("completed:false report tag:work due<2024-01-01")
+        );
+    }
+
+    #[test]
+    fn malformed_query_strings_are_rejected() {
+        for query in [
+            "completed:maybe",
+            "due<tomorrow",
+            "priority:high",
+            "tag:",
+            "tag:work tag:home",
+            "text:milk bread",
+            "due>2024-01-01",
+        ] {
+            let err = TodoFilter::parse(query).unwrap_err();
+            assert!(
+                matches!(err, RepositoryError::Validation(_)),
+                "{} should be rejected",
+                query
+            );
+        }
+
+        let err = TodoFilter::parse("completed:maybe").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("completed:true or completed:false"));
+    }
+
+    #[tokio::test]
+    async fn query_str_parses_then_queries() {
+        let repository = create_query_fixture().await;
+
+        let todos = repository
+            .query_str("completed:false tag:home buy")
+            .await
+            .unwrap();
+        assert_eq!(vec![1, 2, 4], ids(&todos));
+
+        let err = repository.query_str("due<someday").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn find_or_create_returns_the_existing_todo() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+
+        let first = repository.find_or_create("buy milk").await.unwrap();
+        let second = repository.find_or_create("buy milk").await.unwrap();
+        assert_eq!(3, first.id);
+        assert_eq!(first, second);
+        assert_eq!(2, repository.find_or_create("todo 2").await.unwrap().id);
+        assert_eq!(3, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn create_todo_converts_from_strings() {
+        assert_eq!(
+            CreateTodo::new("buy milk".to_string()),
+            CreateTodo::from("buy milk")
+        );
+        assert_eq!(
+            CreateTodo::new("buy milk".to_string()),
+            CreateTodo::from("buy milk".to_string())
+        );
+
+        let repository = TodoRepositoryForMemory::new();
+        let todo = repository.create("buy milk".into()).await.unwrap();
+        assert_eq!("buy milk", todo.text);
+    }
+
+    #[tokio::test]
+    async fn update_todo_defaults_to_changing_nothing() {
+        assert_eq!(
+            UpdateTodo {
+                text: None,
+                completed: None,
+                due_date: None,
+                priority: None,
+                tags: None,
+                description: None,
+                assignee: None,
+                color: None,
+                blocked_by: None,
+                estimate_minutes: None,
+                attachments: None,
+            },
+            UpdateTodo::default()
+        );
+
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+        let todo = repository
+            .update(
+                1,
+                UpdateTodo {
+                    completed: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(todo.completed);
+        assert_eq!("todo 1", todo.text);
+    }
+
+    #[tokio::test]
+    async fn archived_todos_leave_all_until_unarchived() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+        complete(&repository, 1).await;
+
+        let archived = repository.archive(1).await.unwrap();
+        assert!(archived.archived);
+        assert!(archived.completed);
+        assert_eq!(vec![2], ids(&repository.all().await));
+        assert_eq!(vec![archived.clone()], repository.archived().await);
+        assert_eq!(Some(archived), repository.find(1).await);
+        assert_eq!(1, repository.count().await);
+        assert_eq!(0, repository.count_completed().await);
+
+        let unarchived = repository.unarchive(1).await.unwrap();
+        assert!(!unarchived.archived);
+        assert!(unarchived.completed);
+        assert!(repository.archived().await.is_empty());
+        assert_eq!(vec![1, 2], ids(&sorted_by_id(repository.all().await)));
+        assert!(is_not_found(&repository.archive(3).await.unwrap_err()));
+    }
+
+    async fn create_patch_fixture() -> (TodoRepositoryForMemory, Todo) {
+        let repository = TodoRepositoryForMemory::new();
+        let todo = repository
+            .create(CreateTodo {
+                due_date: Some(Utc::now()),
+                priority: Priority::High,
+                tags: tags(&["home"]),
+                ..CreateTodo::new("original".to_string())
+            })
+            .await
+            .unwrap();
+        let todo = repository
+            .update(todo.id, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+
+        (repository, todo)
+    }
+
+    #[tokio::test]
+    async fn updating_text_keeps_every_other_field() {
+        let (repository, before) = create_patch_fixture().await;
+
+        let after = repository
+            .update(
+                before.id,
+                UpdateTodo {
+                    text: Some("renamed".to_string()),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!("renamed", after.text);
+        assert_eq!(
+            Todo {
+                text: before.text.clone(),
+                updated_at: before.updated_at,
+                version: before.version,
+                ..after
+            },
+            before
+        );
+    }
+
+    #[tokio::test]
+    async fn updating_completed_keeps_every_other_field() {
+        let (repository, before) = create_patch_fixture().await;
+
+        let after = repository
+            .update(before.id, UpdateTodo::completed(false))
+            .await
+            .unwrap();
+        assert!(!after.completed);
+        assert_eq!(
+            Todo {
+                completed: before.completed,
+                updated_at: before.updated_at,
+                version: before.version,
+                ..after
+            },
+            before
+        );
+    }
+
+    #[tokio::test]
+    async fn an_empty_update_changes_nothing_but_bookkeeping() {
+        let (repository, before) = create_patch_fixture().await;
+
+        let after = repository
+            .update(before.id, UpdateTodo::default())
+            .await
+            .unwrap();
+        assert_eq!(before.version + 1, after.version);
+        assert_eq!(
+            Todo {
+                updated_at: before.updated_at,
+                version: before.version,
+                ..after
+            },
+            before
+        );
+    }
+
+    #[tokio::test]
+    async fn seeded_keeps_ids_and_numbers_new_todos_after_them() {
+        let repository = TodoRepositoryForMemory::seeded(vec![
+            Todo::new(5, "five".to_string()),
+            Todo::new(6, "six".to_string()),
+            Todo::new(7, "seven".to_string()),
+        ]);
+        assert_eq!(vec![5, 6, 7], ids(&sorted_by_id(repository.all().await)));
+        assert_eq!("six", repository.find(6).await.unwrap().text);
+
+        let todo = repository.create("eight".into()).await.unwrap();
+        assert_eq!(8, todo.id);
+    }
+
+    #[tokio::test]
+    async fn store_hash_ignores_insertion_order_but_not_content() {
+        let todos: Vec<Todo> = (1..=4)
+            .map(|id| Todo::new(id, format!("todo {}", id)))
+            .collect();
+        let forwards = TodoRepositoryForMemory::new();
+        forwards
+            .import(todos.clone(), ConflictPolicy::Fail)
+            .unwrap();
+        let backwards = TodoRepositoryForMemory::new();
+        backwards
+            .import(todos.into_iter().rev().collect(), ConflictPolicy::Fail)
+            .unwrap();
+        assert_eq!(forwards.store_hash(), backwards.store_hash());
+        assert_ne!(0, forwards.store_hash());
+
+        let mut renamed = forwards.find(2).await.unwrap();
+        renamed.text = "changed".to_string();
+        backwards
+            .import(vec![renamed], ConflictPolicy::Overwrite)
+            .unwrap();
+        assert_ne!(forwards.store_hash(), backwards.store_hash());
+    }
+
+    #[tokio::test]
+    async fn approx_memory_bytes_grows_with_content() {
+        let short = TodoRepositoryForMemory::new();
+        let long = TodoRepositoryForMemory::new();
+        let empty = short.approx_memory_bytes();
+        for _ in 0..10 {
+            short.create("milk".into()).await.unwrap();
+            long.create("milk ".repeat(200).into()).await.unwrap();
+        }
+
+        assert!(empty < short.approx_memory_bytes());
+        assert!(short.approx_memory_bytes() + 10 * 900 < long.approx_memory_bytes());
+    }
+
+    #[tokio::test]
+    async fn ndjson_round_trips_todos() {
+        let source = TodoRepositoryForMemory::new();
+        create_todos(&source, 3).await;
+        complete(&source, 2).await;
+        source.log_time(3, 15).await.unwrap();
+
+        let mut buffer = Vec::new();
+        source.export_ndjson(&mut buffer).unwrap();
+        let text = String::from_utf8(buffer.clone()).unwrap();
+        assert_eq!(3, text.lines().count());
+        assert!(text.lines().all(|line| line.starts_with('{')));
+
+        let target = TodoRepositoryForMemory::new();
+        let report = target
+            .import_ndjson(buffer.as_slice(), ConflictPolicy::Fail)
+            .unwrap();
+        assert_eq!(vec![1, 2, 3], report.imported);
+        assert_eq!(
+            sorted_by_id(source.all().await),
+            sorted_by_id(target.all().await)
+        );
+
+        let err = target
+            .import_ndjson("\n{}\n".as_bytes(), ConflictPolicy::Skip)
+            .unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[tokio::test]
+    async fn subscribers_see_mutations_in_order() {
+        let repository = TodoRepositoryForMemory::new().with_events(16);
+        let mut events = repository.subscribe();
+
+        let created = repository.create("buy milk".into()).await.unwrap();
+        let updated = repository
+            .update(created.id, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+        repository.delete(created.id).await.unwrap();
+
+        assert_eq!(TodoEvent::Created(created), events.recv().await.unwrap());
+        assert_eq!(TodoEvent::Updated(updated), events.recv().await.unwrap());
+        assert_eq!(TodoEvent::Deleted(1), events.recv().await.unwrap());
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn change_log_records_before_and_after_each_mutation() {
+        let repository = TodoRepositoryForMemory::new().with_change_log(16);
+        let start = Utc::now();
+
+        let created = repository.create("buy milk".into()).await.unwrap();
+        let updated = repository
+            .update(created.id, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+        repository.delete(created.id).await.unwrap();
+
+        let log = repository.change_log();
+        let shapes: Vec<(ChangeKind, i32, Option<Todo>, Option<Todo>)> = log
+            .iter()
+            .map(|entry| {
+                (
+                    entry.kind,
+                    entry.id,
+                    entry.before.clone(),
+                    entry.after.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (ChangeKind::Created, 1, None, Some(created.clone())),
+                (ChangeKind::Updated, 1, Some(created), Some(updated.clone())),
+                (ChangeKind::Deleted, 1, Some(updated), None),
+            ],
+            shapes
+        );
+        assert!(log
+            .windows(2)
+            .all(|pair| start <= pair[0].timestamp && pair[0].timestamp <= pair[1].timestamp));
+    }
+
+    #[tokio::test]
+    async fn changes_since_returns_only_later_entries() {
+        let repository = TodoRepositoryForMemory::new().with_change_log(16);
+        create_todos(&repository, 2).await;
+        let (version, _) = repository.changes_since(0);
+        assert_eq!(2, version);
+
+        complete(&repository, 1).await;
+        repository.delete(2).await.unwrap();
+        repository.create("third".into()).await.unwrap();
+
+        let (latest, entries) = repository.changes_since(version);
+        assert_eq!(5, latest);
+        let changes: Vec<(u64, ChangeKind, i32)> = entries
+            .iter()
+            .map(|entry| (entry.version, entry.kind, entry.id))
+            .collect();
+        assert_eq!(
+            vec![
+                (3, ChangeKind::Updated, 1),
+                (4, ChangeKind::Deleted, 2),
+                (5, ChangeKind::Created, 3),
+            ],
+            changes
+        );
+        assert!(repository.changes_since(latest).1.is_empty());
+    }
+
+    #[tokio::test]
+    async fn version_counts_mutations_without_a_change_log() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+        repository.delete(1).await.unwrap();
+
+        assert_eq!(3, repository.version());
+        assert_eq!((3, vec![]), repository.changes_since(0));
+    }
+
+    #[tokio::test]
+    async fn change_log_keeps_only_the_newest_entries() {
+        let repository = TodoRepositoryForMemory::new().with_change_log(2);
+        create_todos(&repository, 3).await;
+
+        let ids: Vec<i32> = repository
+            .change_log()
+            .iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert_eq!(vec![2, 3], ids);
+        assert!(TodoRepositoryForMemory::new().change_log().is_empty());
+    }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-pub struct Todo {
-    id: i32,
-    text: String,
-    completed: bool,
-}
+    #[tokio::test]
+    async fn replaying_the_change_log_rebuilds_the_store() {
+        let repository = TodoRepositoryForMemory::new().with_change_log(100);
+        create_todos(&repository, 4).await;
+        create_child(&repository, 1).await.unwrap();
+        complete(&repository, 2).await;
+        repository.log_time(3, 20).await.unwrap();
+        repository.soft_delete(4).await.unwrap();
+        repository.soft_delete(2).await.unwrap();
+        repository.restore(2).await.unwrap();
+        repository.delete(1).await.unwrap();
+        repository.reassign_id(3, 9).unwrap();
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-pub struct CreateTodo {
-    text: String,
-}
+        let replayed = TodoRepositoryForMemory::from_change_log(repository.change_log());
+        let (mut original, rebuilt) = (repository.snapshot(), replayed.snapshot());
+        original.todos.retain(|_, todo| !todo.is_deleted());
+        assert_eq!(original.todos, rebuilt.todos);
+        assert_eq!(9, rebuilt.last_id);
+        assert_eq!(10, replayed.create("next".into()).await.unwrap().id);
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-pub struct UpdateTodo {
-    text: Option<String>,
-    completed: Option<bool>,
-}
+        let mut entries = repository.change_log();
+        entries.drain(..4);
+        let partial = TodoRepositoryForMemory::from_change_log(entries);
+        assert_eq!(vec![5, 9], ids(&sorted_by_id(partial.all().await)));
+    }
 
-impl Todo {
-    pub fn new(id: i32, text: String) -> Self {
-        Self {
-            id,
-            text,
-            completed: false,
+    #[tokio::test]
+    async fn subscribing_without_events_gives_a_closed_receiver() {
+        let repository = TodoRepositoryForMemory::new();
+        let mut events = repository.subscribe();
+
+        repository.create("buy milk".into()).await.unwrap();
+        assert_eq!(
+            Err(broadcast::error::RecvError::Closed),
+            events.recv().await
+        );
+    }
+
+    #[tokio::test]
+    async fn exists_is_false_once_deleted() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 2).await;
+
+        assert!(repository.exists(1).await);
+        repository.delete(1).await.unwrap();
+        assert!(!repository.exists(1).await);
+        repository.soft_delete(2).await.unwrap();
+        assert!(!repository.exists(2).await);
+        assert!(!repository.exists(3).await);
+    }
+
+    #[tokio::test]
+    async fn completing_a_daily_todo_schedules_the_next_one() {
+        let repository = TodoRepositoryForMemory::new();
+        let due_date = Utc::now();
+        let todo = repository
+            .create(
+                CreateTodo::builder()
+                    .text("water plants")
+                    .due_date(due_date)
+                    .recurrence(Recurrence::Daily)
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        complete(&repository, todo.id).await;
+        let todos = sorted_by_id(repository.all().await);
+        assert_eq!(vec![1, 2], ids(&todos));
+        assert!(todos[0].completed);
+        assert!(!todos[1].completed);
+        assert_eq!("water plants", todos[1].text);
+        assert_eq!(
+            Some(due_date + chrono::Duration::days(1)),
+            todos[1].due_date
+        );
+        assert_eq!(Some(Recurrence::Daily), todos[1].recurrence);
+
+        // Updating an already completed todo does not schedule another one.
+        complete(&repository, todo.id).await;
+        assert_eq!(2, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn completing_a_one_off_todo_schedules_nothing() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+
+        repository.toggle_completed(1).await.unwrap();
+        assert_eq!(vec![1], ids(&repository.all().await));
+    }
+
+    fn assert_conflict(err: anyhow::Error, id: i32) {
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(conflict)) if *conflict == id
+        ));
+    }
+
+    #[tokio::test]
+    async fn create_unique_rejects_exact_duplicates() {
+        let repository = TodoRepositoryForMemory::new();
+        repository.create_unique("buy milk".into()).await.unwrap();
+
+        let err = repository
+            .create_unique("buy milk".into())
+            .await
+            .unwrap_err();
+        assert_conflict(err, 1);
+        assert_eq!(1, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn create_unique_ignores_case() {
+        let repository = TodoRepositoryForMemory::new();
+        repository.create_unique("Buy Milk".into()).await.unwrap();
+
+        let err = repository
+            .create_unique("buy milk".into())
+            .await
+            .unwrap_err();
+        assert_conflict(err, 1);
+    }
+
+    #[tokio::test]
+    async fn create_unique_accepts_new_text() {
+        let repository = TodoRepositoryForMemory::new();
+        repository.create_unique("buy milk".into()).await.unwrap();
+
+        let todo = repository.create_unique("buy bread".into()).await.unwrap();
+        assert_eq!(2, todo.id);
+        assert_eq!(2, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn complete_all_counts_only_incomplete_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 3).await;
+        complete(&repository, 1).await;
+
+        assert_eq!(2, repository.complete_all().await.unwrap());
+        assert_eq!(3, repository.count_completed().await);
+        assert_eq!(0, repository.complete_all().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn complete_where_completes_only_matching_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        for (text, tag) in [("a", "sprint-3"), ("b", "sprint-4"), ("c", "sprint-3")] {
+            let payload = CreateTodo::builder().text(text).tag(tag).build().unwrap();
+            repository.create(payload).await.unwrap();
         }
+        create_todos(&repository, 1).await;
+
+        let sprint = TodoFilter {
+            tag: Some("sprint-3".to_string()),
+            ..TodoFilter::default()
+        };
+        let completed = repository.complete_where(sprint.clone()).await.unwrap();
+        assert_eq!(vec![1, 3], ids(&completed));
+        assert!(completed.iter().all(|todo| todo.completed));
+        assert_eq!(vec![2, 4], ids(&repository.find_by_completed(false).await));
+
+        assert!(repository.complete_where(sprint).await.unwrap().is_empty());
     }
-}
 
-type TodoDates = HashMap<i32, Todo>;
+    #[tokio::test]
+    async fn update_where_changes_only_matching_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        for (text, tag) in [("a", "urgent"), ("b", "later"), ("c", "urgent")] {
+            let payload = CreateTodo::builder().text(text).tag(tag).build().unwrap();
+            repository.create(payload).await.unwrap();
+        }
+        let untouched = repository.find(2).await.unwrap();
 
-#[derive(Debug, Clone)]
-pub struct TodoRepositoryForMemory {
-    store: Arc<RwLock<TodoDates>>,
-}
+        let urgent = TodoFilter {
+            tag: Some("urgent".to_string()),
+            ..TodoFilter::default()
+        };
+        let high = UpdateTodo {
+            priority: Some(Priority::High),
+            ..UpdateTodo::default()
+        };
+        let updated = repository.update_where(urgent.clone(), high).await.unwrap();
+        assert_eq!(vec![1, 3], ids(&updated));
+        assert_eq!(
+            vec![1, 3],
+            ids(&repository.find_by_priority(Priority::High).await)
+        );
+        assert_eq!(Some(untouched), repository.find(2).await);
 
-impl TodoRepositoryForMemory {
-    pub fn new() -> Self {
-        Self {
-            store: Arc::default(),
+        let blank = UpdateTodo {
+            text: Some(" ".to_string()),
+            ..UpdateTodo::default()
+        };
+        assert!(repository.update_where(urgent, blank).await.is_err());
+        assert_eq!("a", repository.find(1).await.unwrap().text);
+    }
+
+    #[tokio::test]
+    async fn clear_completed_deletes_only_completed_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 3).await;
+        complete(&repository, 1).await;
+        complete(&repository, 3).await;
+
+        assert_eq!(2, repository.clear_completed().await.unwrap());
+        assert_eq!(vec![2], ids(&repository.all().await));
+        assert_eq!(0, repository.clear_completed().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn max_completed_evicts_the_oldest_completed_todos() {
+        let repository = TodoRepositoryForMemory::new().with_max_completed(2);
+        create_todos(&repository, 6).await;
+        for id in [4, 1, 5, 2] {
+            complete(&repository, id).await;
         }
+        assert_eq!(vec![3, 4, 5, 6], ids(&sorted_by_id(repository.all().await)));
+
+        repository
+            .create(
+                CreateTodo::builder()
+                    .text("done")
+                    .completed(true)
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let todos = sorted_by_id(repository.all().await);
+        assert_eq!(vec![3, 5, 6, 7], ids(&todos));
+        assert_eq!(
+            vec![5, 7],
+            ids(&sorted_by_id(repository.find_by_completed(true).await))
+        );
+        assert_eq!(None, repository.find(4).await);
     }
 
-    fn write_store_ref(&self) -> RwLockWriteGuard<TodoDates> {
-        self.store.write().unwrap()
+    fn is_capacity_exceeded(err: &anyhow::Error) -> bool {
+        matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::CapacityExceeded(3))
+        )
     }
 
-    fn read_store_ref(&self) -> RwLockReadGuard<TodoDates> {
-        self.store.read().unwrap()
+    #[tokio::test]
+    async fn max_todos_rejects_creates_once_full() {
+        let repository = TodoRepositoryForMemory::new().with_max_todos(3, CountedTodos::All);
+        create_todos(&repository, 3).await;
+        complete(&repository, 1).await;
+        repository.archive(2).await.unwrap();
+
+        let err = repository.create("fourth".into()).await.unwrap_err();
+        assert!(is_capacity_exceeded(&err));
+        let err = repository.find_or_create("fourth").await.unwrap_err();
+        assert!(is_capacity_exceeded(&err));
+        let err = repository
+            .bulk_create(vec!["fourth".into()])
+            .await
+            .unwrap_err();
+        assert!(is_capacity_exceeded(&err));
+        assert_eq!(3, repository.snapshot().todos.len());
+
+        repository.delete(3).await.unwrap();
+        let todo = repository.create("fourth".into()).await.unwrap();
+        assert_eq!(4, todo.id);
     }
-}
 
-impl TodoRepository for TodoRepositoryForMemory {
-    fn create(&self, payload: CreateTodo) -> Todo {
-        let mut store = self.write_store_ref();
+    #[tokio::test]
+    async fn max_todos_can_leave_out_finished_todos() {
+        let repository = TodoRepositoryForMemory::new().with_max_todos(3, CountedTodos::Open);
+        create_todos(&repository, 3).await;
+        complete(&repository, 1).await;
+        repository.archive(2).await.unwrap();
+
+        let payloads = vec!["fourth".into(), "fifth".into()];
+        repository.bulk_create(payloads).await.unwrap();
+        let err = repository.create("sixth".into()).await.unwrap_err();
+        assert!(is_capacity_exceeded(&err));
+    }
 
-        let id = (store.len() + 1) as i32;
-        let todo = Todo::new(id, payload.text);
-        store.insert(id, todo.clone());
+    #[tokio::test]
+    async fn restore_snapshot_rolls_back_every_change() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 3).await;
+        complete(&repository, 2).await;
+        let snapshot = repository.snapshot();
 
-        todo
+        create_todos(&repository, 2).await;
+        repository.delete(1).await.unwrap();
+        repository.soft_delete(2).await.unwrap();
+        repository
+            .update(
+                3,
+                UpdateTodo {
+                    text: Some("changed".to_string()),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_ne!(snapshot, repository.snapshot());
+
+        repository.restore_snapshot(snapshot.clone());
+        assert_eq!(snapshot, repository.snapshot());
+        assert_eq!(4, repository.create("after".into()).await.unwrap().id);
     }
 
-    fn find(&self, id: i32) -> Option<Todo> {
-        let store = self.read_store_ref();
+    async fn create_sort_fixture() -> TodoRepositoryForMemory {
+        let repository = TodoRepositoryForMemory::new();
+        for text in ["banana", "apple", "cherry", "apple"] {
+            repository.create(text.into()).await.unwrap();
+        }
+        complete(&repository, 3).await;
+        complete(&repository, 4).await;
 
-        store.get(&id).cloned()
+        repository
     }
 
-    fn all(&self) -> Vec<Todo> {
-        let store = self.read_store_ref();
+    #[tokio::test]
+    async fn all_sorted_by_id() {
+        let repository = create_sort_fixture().await;
 
-        store.values().cloned().collect()
+        assert_eq!(
+            vec![1, 2, 3, 4],
+            ids(&repository.all_sorted(SortOrder::IdAsc).await)
+        );
+        assert_eq!(
+            vec![4, 3, 2, 1],
+            ids(&repository.all_sorted(SortOrder::IdDesc).await)
+        );
     }
 
-    fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
-        let mut store = self.write_store_ref();
+    #[tokio::test]
+    async fn all_sorted_by_text_breaks_ties_by_id() {
+        let repository = create_sort_fixture().await;
 
-        let todo = store.get(&id).context(RepositoryError::NotFound(id))?;
-        let todo = Todo {
-            id,
-            text: payload.text.unwrap_or(todo.text.clone()),
-            completed: payload.completed.unwrap_or(todo.completed),
-        };
-        store.insert(id, todo.clone());
+        assert_eq!(
+            vec![2, 4, 1, 3],
+            ids(&repository.all_sorted(SortOrder::TextAsc).await)
+        );
+    }
 
-        Ok(todo)
+    #[tokio::test]
+    async fn all_sorted_with_completed_first() {
+        let repository = create_sort_fixture().await;
+
+        assert_eq!(
+            vec![3, 4, 1, 2],
+            ids(&repository.all_sorted(SortOrder::CompletedFirst).await)
+        );
     }
 
-    fn delete(&self, id: i32) -> anyhow::Result<()> {
-        let mut store = self.write_store_ref();
-        store.remove(&id).context(RepositoryError::NotFound(id))?;
+    #[tokio::test]
+    async fn health_check_fails_once_the_lock_is_poisoned() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+        repository.health_check().await.unwrap();
 
-        Ok(())
+        let poisoner = repository.clone();
+        let result = std::thread::spawn(move || {
+            let _store = poisoner.write_store_ref();
+            panic!("poison the store lock");
+        })
+        .join();
+        assert!(result.is_err());
+
+        let err = repository.health_check().await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::StorageUnavailable)
+        ));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn a_poisoned_lock_does_not_break_later_calls() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+
+        let poisoner = repository.clone();
+        let result = std::thread::spawn(move || {
+            let _store = poisoner.write_store_ref();
+            panic!("poison the store lock");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(repository.store.is_poisoned());
+
+        assert_eq!(vec![1], ids(&repository.all().await));
+        assert_eq!(2, repository.create("after".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn poisoned_tag_index_and_change_log_locks_do_not_break_later_calls() {
+        let repository = TodoRepositoryForMemory::new().with_change_log(8);
+        create_todos(&repository, 1).await;
+
+        let poisoner = repository.clone();
+        let result = std::thread::spawn(move || {
+            let _tag_index = poisoner.tag_index();
+            let _changes = poisoner.changes.as_ref().unwrap().lock();
+            panic!("poison the tag index and change log locks");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(repository.tag_index.is_poisoned());
+
+        let payload = CreateTodo {
+            tags: tags(&["home"]),
+            ..CreateTodo::new("after".to_string())
+        };
+        repository.create(payload).await.unwrap();
+        assert_eq!(vec![2], ids(&repository.find_by_tag("home").await));
+        assert_eq!(2, repository.change_log().len());
+        assert_eq!(1, repository.changes_since(1).1.len());
+    }
+
+    #[tokio::test]
+    async fn recent_returns_the_newest_todos_first() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 4).await;
+        {
+            // Make creation order disagree with id order.
+            let mut store = repository.write_store_ref();
+            store.get_mut(&2).unwrap().created_at = Utc::now() + chrono::Duration::hours(1);
+        }
+
+        assert_eq!(vec![2, 4], ids(&repository.recent(2).await));
+        assert_eq!(vec![2, 4, 3, 1], ids(&repository.recent(4).await));
+        assert_eq!(vec![2, 4, 3, 1], ids(&repository.recent(10).await));
+        assert!(repository.recent(0).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn description_can_be_set_updated_and_cleared() {
+        let repository = TodoRepositoryForMemory::new();
+        let payload = CreateTodo::builder()
+            .text("trip")
+            .description("pack the tent")
+            .build()
+            .unwrap();
+        let todo = repository.create(payload).await.unwrap();
+        assert_eq!(Some("pack the tent".to_string()), todo.description);
+
+        let todo = repository
+            .update(1, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+        assert_eq!(Some("pack the tent".to_string()), todo.description);
+
+        let todo = repository
+            .update(
+                1,
+                UpdateTodo {
+                    description: Some(Some("pack the tent and stove".to_string())),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("pack the tent and stove".to_string()),
+            todo.description
+        );
+
+        let todo = repository
+            .update(
+                1,
+                UpdateTodo {
+                    description: Some(None),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(None, todo.description);
+    }
 
     #[test]
-    fn todo_crud_scenario() {
-        let text = "todo  text";
-        let id = 1;
-        let expected = Todo::new(id, text.to_string());
+    fn description_null_and_missing_mean_different_things() {
+        let missing: UpdateTodo = serde_json::from_str(r#"{"completed": true}"#).unwrap();
+        assert_eq!(None, missing.description);
 
-        let reopsitory = TodoRepositoryForMemory::new();
-        let todo = reopsitory.create(CreateTodo {
-            text: text.to_string(),
-        });
-        assert_eq!(expected, todo);
+        let null: UpdateTodo = serde_json::from_str(r#"{"description": null}"#).unwrap();
+        assert_eq!(Some(None), null.description);
 
-        let todo = reopsitory.find(id).unwrap();
-        assert_eq!(expected, todo);
+        let set: UpdateTodo = serde_json::from_str(r#"{"description": "notes"}"#).unwrap();
+        assert_eq!(Some(Some("notes".to_string())), set.description);
+    }
 
-        let todos = reopsitory.all();
-        assert_eq!(vec![expected], todos);
+    #[tokio::test]
+    async fn untagged_leaves_out_tagged_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        for todo_tags in [&["home"][..], &[], &["work", "urgent"], &[], &[]] {
+            repository
+                .create(CreateTodo {
+                    tags: tags(todo_tags),
+                    ..CreateTodo::new("todo".to_string())
+                })
+                .await
+                .unwrap();
+        }
+        repository.soft_delete(5).await.unwrap();
 
-        let text = "update todo text";
-        let todo = reopsitory
+        assert_eq!(vec![2, 4], ids(&repository.untagged().await));
+        let payload = UpdateTodo {
+            tags: Some(tags(&["home"])),
+            ..UpdateTodo::default()
+        };
+        repository.update(2, payload).await.unwrap();
+        assert_eq!(vec![4], ids(&repository.untagged().await));
+    }
+
+    #[tokio::test]
+    async fn todos_can_be_assigned_and_reassigned() {
+        let repository = TodoRepositoryForMemory::new();
+        let payload = CreateTodo::builder()
+            .text("fix the sink")
+            .assignee("alex")
+            .build()
+            .unwrap();
+        repository.create(payload).await.unwrap();
+        create_todos(&repository, 1).await;
+
+        assert_eq!(vec![1], ids(&repository.find_by_assignee("alex").await));
+        assert_eq!(vec![2], ids(&repository.unassigned().await));
+
+        let reassign = |assignee: Option<&str>| UpdateTodo {
+            assignee: Some(assignee.map(str::to_string)),
+            ..UpdateTodo::default()
+        };
+        repository.update(1, reassign(Some("sam"))).await.unwrap();
+        assert!(repository.find_by_assignee("alex").await.is_empty());
+        assert_eq!(vec![1], ids(&repository.find_by_assignee("sam").await));
+
+        repository.update(1, reassign(None)).await.unwrap();
+        assert_eq!(vec![1, 2], ids(&repository.unassigned().await));
+    }
+
+    #[tokio::test]
+    async fn reassign_all_moves_every_todo_of_an_assignee() {
+        let repository = TodoRepositoryForMemory::new();
+        for assignee in ["alex", "sam", "alex", "alex", "alex"] {
+            let payload = CreateTodo::builder()
+                .text("chore")
+                .assignee(assignee)
+                .build()
+                .unwrap();
+            repository.create(payload).await.unwrap();
+        }
+        create_todos(&repository, 1).await;
+        complete(&repository, 3).await;
+        repository.archive(3).await.unwrap();
+        repository.soft_delete(5).await.unwrap();
+
+        assert_eq!(3, repository.reassign_all("alex", "robin"));
+        assert!(repository.find_by_assignee("alex").await.is_empty());
+        assert_eq!(vec![1, 4], ids(&repository.find_by_assignee("robin").await));
+        assert_eq!(
+            Some("robin"),
+            repository.archived().await[0].assignee.as_deref()
+        );
+        assert_eq!(2, repository.find(1).await.unwrap().version);
+        assert_eq!(vec![2], ids(&repository.find_by_assignee("sam").await));
+        assert_eq!(vec![6], ids(&repository.unassigned().await));
+
+        assert_eq!(0, repository.reassign_all("alex", "robin"));
+        assert_eq!(0, repository.reassign_all("nobody", "robin"));
+        assert_eq!(0, repository.reassign_all("robin", "robin"));
+    }
+
+    #[tokio::test]
+    async fn blocked_todos_become_ready_once_their_blockers_complete() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+        let payload = CreateTodo::builder()
+            .text("after the first")
+            .blocked_by(1)
+            .build()
+            .unwrap();
+        repository.create(payload).await.unwrap();
+
+        assert_eq!(vec![1], ids(&repository.ready().await));
+
+        complete(&repository, 1).await;
+        assert_eq!(vec![2], ids(&repository.ready().await));
+    }
+
+    fn attachment(name: &str, url: &str) -> Attachment {
+        Attachment {
+            name: name.to_string(),
+            url: url.to_string(),
+            size_bytes: 1024,
+        }
+    }
+
+    #[tokio::test]
+    async fn attachments_are_added_and_removed_by_name() {
+        let repository = TodoRepositoryForMemory::new();
+        let payload = CreateTodo::builder()
+            .text("send the report")
+            .attachment(attachment("draft.pdf", "https://example.com/draft.pdf"))
+            .build()
+            .unwrap();
+        let todo = repository.create(payload).await.unwrap();
+        assert_eq!(1, todo.attachments.len());
+
+        let slides = attachment("slides.key", "https://example.com/slides.key");
+        let todo = repository
+            .add_attachment(todo.id, slides.clone())
+            .await
+            .unwrap();
+        let names: Vec<&str> = todo.attachments.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(vec!["draft.pdf", "slides.key"], names);
+        assert_eq!(Some(todo.clone()), repository.find(todo.id).await);
+
+        let todo = repository
+            .remove_attachment(todo.id, "draft.pdf")
+            .await
+            .unwrap();
+        assert_eq!(vec![slides], todo.attachments);
+
+        let err = repository
+            .remove_attachment(todo.id, "draft.pdf")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+        let err = repository
+            .add_attachment(9, attachment("a", "https://example.com/a"))
+            .await
+            .unwrap_err();
+        assert!(is_not_found(&err));
+    }
+
+    #[tokio::test]
+    async fn attachments_need_a_url_and_a_unique_name() {
+        let repository = TodoRepositoryForMemory::new();
+        let payload = CreateTodo::builder()
+            .text("no url")
+            .attachment(attachment("empty", " "))
+            .build()
+            .unwrap();
+        assert!(repository.create(payload).await.is_err());
+
+        create_todos(&repository, 1).await;
+        repository
+            .add_attachment(1, attachment("notes", "https://example.com/1"))
+            .await
+            .unwrap();
+        let err = repository
+            .add_attachment(1, attachment("notes", "https://example.com/2"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+        assert_eq!(1, repository.find(1).await.unwrap().attachments.len());
+    }
+
+    #[tokio::test]
+    async fn blockers_must_exist_and_cannot_be_the_todo_itself() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+
+        let payload = CreateTodo::builder()
+            .text("blocked by nothing")
+            .blocked_by(9)
+            .build()
+            .unwrap();
+        let err = repository.create(payload).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(9))
+        ));
+
+        let err = repository
             .update(
-                id,
+                1,
                 UpdateTodo {
-                    text: Some(text.to_string()),
-                    completed: Some(true),
+                    blocked_by: Some(vec![1]),
+                    ..UpdateTodo::default()
                 },
             )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+        assert!(repository.find(1).await.unwrap().blocked_by.is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_reports_every_invalid_field() {
+        let repository = TodoRepositoryForMemory::new();
+        create_todos(&repository, 1).await;
+
+        let payload = UpdateTodo {
+            text: Some("  ".to_string()),
+            color: Some(Some("red".to_string())),
+            due_date: Some(DateTime::UNIX_EPOCH - chrono::Duration::days(1)),
+            ..UpdateTodo::default()
+        };
+        let err = repository.update(1, payload).await.unwrap_err();
+        let Some(RepositoryError::Validation(problems)) = err.downcast_ref::<RepositoryError>()
+        else {
+            panic!("expected a validation error, got {:?}", err);
+        };
+        assert_eq!(3, problems.len(), "{:?}", problems);
+        for (problem, expected) in problems.iter().zip([
+            "text must not be empty",
+            "color must look like #RRGGBB",
+            "due date must not be before 1970-01-01",
+        ]) {
+            assert!(
+                problem.starts_with(expected),
+                "{:?} should start with {:?}",
+                problem,
+                expected
+            );
+        }
+        assert_eq!("todo 1", repository.find(1).await.unwrap().text);
+    }
+
+    const MESSY_TEXT: &str = "  buy \t milk\n\nand   eggs ";
+
+    #[tokio::test]
+    async fn text_is_normalized_when_enabled() {
+        let repository = TodoRepositoryForMemory::new().with_normalized_text(true);
+
+        let todo = repository.create(MESSY_TEXT.into()).await.unwrap();
+        assert_eq!("buy milk and eggs", todo.text);
+        let payload = UpdateTodo {
+            text: Some(format!("{} again", MESSY_TEXT)),
+            ..UpdateTodo::default()
+        };
+        let todo = repository.update(todo.id, payload).await.unwrap();
+        assert_eq!("buy milk and eggs again", todo.text);
+        let found = repository.find_or_create(" buy  milk and eggs again").await;
+        assert_eq!(todo.id, found.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn text_is_kept_as_given_by_default() {
+        let repository = TodoRepositoryForMemory::new();
+
+        let todo = repository.create(MESSY_TEXT.into()).await.unwrap();
+        assert_eq!(MESSY_TEXT, todo.text);
+        let payload = UpdateTodo {
+            text: Some(format!("{} again", MESSY_TEXT)),
+            ..UpdateTodo::default()
+        };
+        let todo = repository.update(todo.id, payload).await.unwrap();
+        assert_eq!(format!("{} again", MESSY_TEXT), todo.text);
+    }
+
+    #[tokio::test]
+    async fn colors_are_validated_on_create_and_update() {
+        let repository = TodoRepositoryForMemory::new();
+        let payload = CreateTodo::builder()
+            .text("paint the fence")
+            .color("#1a2B3c")
+            .build()
             .unwrap();
+        let todo = repository.create(payload).await.unwrap();
+        assert_eq!(Some("#1a2B3c".to_string()), todo.color);
+
+        let plain = repository.create("no color".into()).await.unwrap();
+        assert_eq!(None, plain.color);
+
+        for color in ["1a2b3c", "#1a2b3", "#1a2b3cd", "#12345g", "red"] {
+            let payload = CreateTodo::builder()
+                .text("bad color")
+                .color(color)
+                .build()
+                .unwrap();
+            let err = repository.create(payload).await.unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<RepositoryError>(),
+                    Some(RepositoryError::Validation(_))
+                ),
+                "{} should be rejected",
+                color
+            );
+        }
+
+        let recolor = |color: Option<&str>| UpdateTodo {
+            color: Some(color.map(str::to_string)),
+            ..UpdateTodo::default()
+        };
+        let err = repository
+            .update(1, recolor(Some("#zzzzzz")))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
         assert_eq!(
-            Todo {
-                id,
-                text: text.to_string(),
-                completed: true
-            },
-            todo
+            Some("#1a2B3c".to_string()),
+            repository.find(1).await.unwrap().color
         );
 
-        let res = reopsitory.delete(id);
-        assert!(res.is_ok());
+        let todo = repository.update(1, recolor(None)).await.unwrap();
+        assert_eq!(None, todo.color);
+    }
+
+    #[tokio::test]
+    async fn for_each_visits_listed_todos() {
+        let repository = TodoRepositoryForMemory::new();
+        for text in ["a", "bb", "ccc", "dddd"] {
+            repository.create(text.into()).await.unwrap();
+        }
+        repository.soft_delete(4).await.unwrap();
+
+        let mut total_len = 0;
+        repository.for_each(|todo| total_len += todo.text.len());
+        assert_eq!(6, total_len);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn calls_are_wrapped_in_named_spans() {
+        use tracing::{
+            field::{Field, Visit},
+            span::{Attributes, Id},
+            Subscriber,
+        };
+        use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+        type Spans = Arc<Mutex<Vec<(String, Option<i64>)>>>;
+
+        #[derive(Default)]
+        struct SpanNames(Spans);
+
+        struct IdField(Option<i64>);
+
+        impl Visit for IdField {
+            fn record_i64(&mut self, field: &Field, value: i64) {
+                if field.name() == "id" {
+                    self.0 = Some(value);
+                }
+            }
+
+            fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+        }
+
+        impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanNames {
+            fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+                let mut id = IdField(None);
+                attrs.record(&mut id);
+                let name = attrs.metadata().name().to_string();
+                self.0.lock().unwrap().push((name, id.0));
+            }
+        }
+
+        let spans = SpanNames::default();
+        let names = Arc::clone(&spans.0);
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(spans));
+
+        let repository = TodoRepositoryForMemory::new();
+        repository.create("buy milk".into()).await.unwrap();
+        repository.find(1).await.unwrap();
+
+        let names = names.lock().unwrap();
+        assert!(names.contains(&("create".to_string(), None)));
+        assert!(names.contains(&("find".to_string(), Some(1))));
     }
 }
@@ -0,0 +1,537 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::{
+    BulkDeleteReport, CompleteOutcome, CreateTodo, SerializationFormat, Todo, TodoFilter,
+    TodoRepository, TodoStats, UpdateTodo,
+};
+
+const DEFAULT_CAPACITY: usize = 256;
+
+// The most recently used todos by id. Once full, storing another one evicts
+// the todo that was used longest ago.
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    entries: HashMap<i32, (Todo, u64)>,
+    clock: u64,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn get(&mut self, id: i32) -> Option<Todo> {
+        let now = self.tick();
+        let (todo, used) = self.entries.get_mut(&id)?;
+        *used = now;
+
+        Some(todo.clone())
+    }
+
+    fn insert(&mut self, todo: Todo) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&todo.id) && self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(id, _)| *id);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        let now = self.tick();
+        self.entries.insert(todo.id, (todo, now));
+    }
+
+    fn remove(&mut self, id: i32) {
+        self.entries.remove(&id);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+// Answers `find` from a bounded cache in front of a slower repository. Writes
+// go to the inner repository and drop the cached copies they touch. The cache
+// doesn't know which todos are whose children, so writes that can cascade to
+// them, like the ones that can touch any todo, empty the whole cache.
+// Everything else, `all` included, goes straight to the inner repository.
+// Clones share the cache.
+#[derive(Debug)]
+pub struct CachedRepository<R> {
+    inner: R,
+    cache: Arc<Mutex<Lru>>,
+}
+
+impl<R: Clone> Clone for CachedRepository<R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            cache: Arc::clone(&self.cache),
+        }
+    }
+}
+
+impl<R: TodoRepository> CachedRepository<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_capacity(inner, DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(inner: R, capacity: usize) -> Self {
+        Self {
+            inner,
+            cache: Arc::new(Mutex::new(Lru::new(capacity))),
+        }
+    }
+
+    pub fn cached_len(&self) -> usize {
+        self.cache().entries.len()
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Lru> {
+        self.cache.lock().unwrap()
+    }
+
+    fn cached(&self, result: anyhow::Result<Todo>) -> anyhow::Result<Todo> {
+        if let Ok(todo) = &result {
+            self.cache().insert(todo.clone());
+        }
+        result
+    }
+
+    // The cached copy is dropped even when the write fails, so a failure that
+    // left the row changed can't leave a stale copy behind.
+    fn invalidated<T>(&self, id: i32, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        self.cache().remove(id);
+        result
+    }
+
+    fn cleared<T>(&self, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        self.cache().clear();
+        result
+    }
+}
+
+#[async_trait]
+impl<R: TodoRepository> TodoRepository for CachedRepository<R> {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        self.cached(self.inner.create(payload).await)
+    }
+
+    async fn find_or_create(&self, text: &str) -> anyhow::Result<Todo> {
+        self.cached(self.inner.find_or_create(text).await)
+    }
+
+    async fn create_unique(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        self.cached(self.inner.create_unique(payload).await)
+    }
+
+    async fn find(&self, id: i32) -> Option<Todo> {
+        if let Some(todo) = self.cache().get(id) {
+            return Some(todo);
+        }
+
+        let todo = self.inner.find(id).await?;
+        self.cache().insert(todo.clone());
+        Some(todo)
+    }
+
+    async fn all(&self) -> Vec<Todo> {
+        self.inner.all().await
+    }
+
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        self.cleared(self.inner.update(id, payload).await)
+    }
+
+    async fn update_if_version(
+        &self,
+        id: i32,
+        expected_version: u32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo> {
+        let result = self
+            .inner
+            .update_if_version(id, expected_version, payload)
+            .await;
+        self.cleared(result)
+    }
+
+    async fn replace(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<(Todo, Todo)> {
+        self.cleared(self.inner.replace(id, payload).await)
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        self.cleared(self.inner.delete(id).await)
+    }
+
+    async fn count(&self) -> usize {
+        self.inner.count().await
+    }
+
+    async fn count_completed(&self) -> usize {
+        self.inner.count_completed().await
+    }
+
+    async fn bulk_create(&self, payloads: Vec<CreateTodo>) -> anyhow::Result<Vec<Todo>> {
+        let todos = self.inner.bulk_create(payloads).await?;
+        let mut cache = self.cache();
+        for todo in todos.iter() {
+            cache.insert(todo.clone());
+        }
+        drop(cache);
+
+        Ok(todos)
+    }
+
+    async fn bulk_delete(&self, ids: Vec<i32>) -> anyhow::Result<BulkDeleteReport> {
+        self.cleared(self.inner.bulk_delete(ids).await)
+    }
+
+    async fn toggle_completed(&self, id: i32) -> anyhow::Result<Todo> {
+        self.cleared(self.inner.toggle_completed(id).await)
+    }
+
+    async fn complete(&self, id: i32) -> anyhow::Result<CompleteOutcome> {
+        self.cleared(self.inner.complete(id).await)
+    }
+
+    async fn merge(&self, keep_id: i32, merge_id: i32) -> anyhow::Result<Todo> {
+        self.cleared(self.inner.merge(keep_id, merge_id).await)
+    }
+
+    async fn soft_delete(&self, id: i32) -> anyhow::Result<()> {
+        self.invalidated(id, self.inner.soft_delete(id).await)
+    }
+
+    async fn restore(&self, id: i32) -> anyhow::Result<Todo> {
+        self.invalidated(id, self.inner.restore(id).await)
+    }
+
+    async fn find_many(&self, ids: &[i32]) -> Vec<Todo> {
+        self.inner.find_many(ids).await
+    }
+
+    async fn clear_all(&self) -> anyhow::Result<()> {
+        self.cleared(self.inner.clear_all().await)
+    }
+
+    async fn reorder(&self, id: i32, new_position: i32) -> anyhow::Result<()> {
+        self.cleared(self.inner.reorder(id, new_position).await)
+    }
+
+    async fn stats(&self) -> TodoStats {
+        self.inner.stats().await
+    }
+
+    async fn tag_counts(&self) -> HashMap<String, usize> {
+        self.inner.tag_counts().await
+    }
+
+    async fn complete_all(&self) -> anyhow::Result<usize> {
+        self.cleared(self.inner.complete_all().await)
+    }
+
+    async fn complete_where(&self, filter: TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        self.cleared(self.inner.complete_where(filter).await)
+    }
+
+    async fn update_where(
+        &self,
+        filter: TodoFilter,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Vec<Todo>> {
+        self.cleared(self.inner.update_where(filter, payload).await)
+    }
+
+    async fn clear_completed(&self) -> anyhow::Result<usize> {
+        self.cleared(self.inner.clear_completed().await)
+    }
+
+    async fn archive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.invalidated(id, self.inner.archive(id).await)
+    }
+
+    async fn unarchive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.invalidated(id, self.inner.unarchive(id).await)
+    }
+
+    async fn archived(&self) -> Vec<Todo> {
+        self.inner.archived().await
+    }
+
+    async fn log_time(&self, id: i32, minutes: u32) -> anyhow::Result<Todo> {
+        self.invalidated(id, self.inner.log_time(id, minutes).await)
+    }
+
+    async fn import_from(&self, format: SerializationFormat, data: &[u8]) -> anyhow::Result<usize> {
+        self.cleared(self.inner.import_from(format, data).await)
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        self.inner.health_check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::{
+        test_utils::run_conformance, CascadeMode, OnParentDelete, TodoRepositoryForMemory,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts the `find` calls that reach the wrapped repository.
+    #[derive(Debug, Clone)]
+    struct CountingRepository {
+        inner: TodoRepositoryForMemory,
+        finds: Arc<AtomicUsize>,
+    }
+
+    impl CountingRepository {
+        fn new() -> Self {
+            Self {
+                inner: TodoRepositoryForMemory::new(),
+                finds: Arc::default(),
+            }
+        }
+
+        fn finds(&self) -> usize {
+            self.finds.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl TodoRepository for CountingRepository {
+        async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+            self.inner.create(payload).await
+        }
+
+        async fn find_or_create(&self, text: &str) -> anyhow::Result<Todo> {
+            self.inner.find_or_create(text).await
+        }
+
+        async fn create_unique(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+            self.inner.create_unique(payload).await
+        }
+
+        async fn find(&self, id: i32) -> Option<Todo> {
+            self.finds.fetch_add(1, Ordering::SeqCst);
+            self.inner.find(id).await
+        }
+
+        async fn all(&self) -> Vec<Todo> {
+            self.inner.all().await
+        }
+
+        async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+            self.inner.update(id, payload).await
+        }
+
+        async fn update_if_version(
+            &self,
+            id: i32,
+            expected_version: u32,
+            payload: UpdateTodo,
+        ) -> anyhow::Result<Todo> {
+            self.inner
+                .update_if_version(id, expected_version, payload)
+                .await
+        }
+
+        async fn replace(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<(Todo, Todo)> {
+            self.inner.replace(id, payload).await
+        }
+
+        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+            self.inner.delete(id).await
+        }
+
+        async fn soft_delete(&self, id: i32) -> anyhow::Result<()> {
+            self.inner.soft_delete(id).await
+        }
+
+        async fn restore(&self, id: i32) -> anyhow::Result<Todo> {
+            self.inner.restore(id).await
+        }
+
+        async fn clear_all(&self) -> anyhow::Result<()> {
+            self.inner.clear_all().await
+        }
+
+        async fn reorder(&self, id: i32, new_position: i32) -> anyhow::Result<()> {
+            self.inner.reorder(id, new_position).await
+        }
+
+        async fn complete_all(&self) -> anyhow::Result<usize> {
+            self.inner.complete_all().await
+        }
+
+        async fn clear_completed(&self) -> anyhow::Result<usize> {
+            self.inner.clear_completed().await
+        }
+
+        async fn archive(&self, id: i32) -> anyhow::Result<Todo> {
+            self.inner.archive(id).await
+        }
+
+        async fn unarchive(&self, id: i32) -> anyhow::Result<Todo> {
+            self.inner.unarchive(id).await
+        }
+
+        async fn archived(&self) -> Vec<Todo> {
+            self.inner.archived().await
+        }
+
+        async fn log_time(&self, id: i32, minutes: u32) -> anyhow::Result<Todo> {
+            self.inner.log_time(id, minutes).await
+        }
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        run_conformance(CachedRepository::new(TodoRepositoryForMemory::new())).await;
+    }
+
+    #[tokio::test]
+    async fn repeated_finds_are_served_from_the_cache() {
+        let counting = CountingRepository::new();
+        let repository = CachedRepository::new(counting.clone());
+        counting.create("not cached yet".into()).await.unwrap();
+
+        let first = repository.find(1).await;
+        let second = repository.find(1).await;
+        assert_eq!(first, second);
+        assert_eq!(1, counting.finds());
+
+        let created = repository.create("cached on create".into()).await.unwrap();
+        assert_eq!(Some(created), repository.find(2).await);
+        assert_eq!(1, counting.finds());
+    }
+
+    #[tokio::test]
+    async fn updates_and_deletes_invalidate_the_cached_todo() {
+        let counting = CountingRepository::new();
+        let repository = CachedRepository::new(counting.clone());
+        repository.create("first".into()).await.unwrap();
+
+        let updated = repository
+            .update(1, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+        assert_eq!(Some(updated), repository.find(1).await);
+        assert_eq!(1, counting.finds());
+
+        repository.delete(1).await.unwrap();
+        assert_eq!(None, repository.find(1).await);
+        assert_eq!(2, counting.finds());
+    }
+
+    #[tokio::test]
+    async fn deleting_a_parent_invalidates_its_children() {
+        let repository = CachedRepository::new(TodoRepositoryForMemory::new());
+        let parent = repository.create("parent".into()).await.unwrap();
+        let payload = CreateTodo::builder()
+            .text("child")
+            .parent_id(parent.id)
+            .build()
+            .unwrap();
+        let child = repository.create(payload).await.unwrap();
+
+        repository.delete(parent.id).await.unwrap();
+        assert_eq!(None, repository.find(child.id).await.unwrap().parent_id);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_grandparent_invalidates_grandchildren_whose_parent_was_evicted() {
+        let repository = CachedRepository::with_capacity(
+            TodoRepositoryForMemory::new().with_on_parent_delete(OnParentDelete::Cascade),
+            2,
+        );
+        let grandparent = repository.create("grandparent".into()).await.unwrap();
+        let payload = CreateTodo::builder()
+            .text("parent")
+            .parent_id(grandparent.id)
+            .build()
+            .unwrap();
+        let parent = repository.create(payload).await.unwrap();
+        let payload = CreateTodo::builder()
+            .text("grandchild")
+            .parent_id(parent.id)
+            .build()
+            .unwrap();
+        let grandchild = repository.create(payload).await.unwrap();
+
+        // Leaves the grandparent and the grandchild cached, but not the parent.
+        repository.find(grandparent.id).await.unwrap();
+        repository.find(grandchild.id).await.unwrap();
+        repository.delete(grandparent.id).await.unwrap();
+        assert_eq!(None, repository.find(grandchild.id).await);
+    }
+
+    #[tokio::test]
+    async fn completing_a_parent_invalidates_its_cascaded_children() {
+        let repository = CachedRepository::new(
+            TodoRepositoryForMemory::new().with_cascade_mode(CascadeMode::CompleteChildren),
+        );
+        let parent = repository.create("parent".into()).await.unwrap();
+        let payload = CreateTodo::builder()
+            .text("child")
+            .parent_id(parent.id)
+            .build()
+            .unwrap();
+        let child = repository.create(payload).await.unwrap();
+        assert!(!repository.find(child.id).await.unwrap().completed);
+
+        repository.toggle_completed(parent.id).await.unwrap();
+        assert!(repository.find(child.id).await.unwrap().completed);
+
+        repository.toggle_completed(parent.id).await.unwrap();
+        let payload = CreateTodo::builder()
+            .text("second child")
+            .parent_id(parent.id)
+            .build()
+            .unwrap();
+        let second = repository.create(payload).await.unwrap();
+        assert!(!repository.find(second.id).await.unwrap().completed);
+
+        repository.complete(parent.id).await.unwrap();
+        assert!(repository.find(second.id).await.unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn the_least_recently_used_todo_is_evicted() {
+        let counting = CountingRepository::new();
+        let repository = CachedRepository::with_capacity(counting.clone(), 2);
+        for text in ["first", "second"] {
+            repository.create(text.into()).await.unwrap();
+        }
+        repository.find(1).await.unwrap();
+        repository.create("third".into()).await.unwrap();
+        assert_eq!(2, repository.cached_len());
+
+        repository.find(1).await.unwrap();
+        repository.find(3).await.unwrap();
+        assert_eq!(0, counting.finds());
+        repository.find(2).await.unwrap();
+        assert_eq!(1, counting.finds());
+    }
+}
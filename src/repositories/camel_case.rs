@@ -0,0 +1,131 @@
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+// Reads and writes the wrapped value with camelCase keys, e.g. `createdAt`
+// for `created_at`, while the types themselves stay snake_case for existing
+// consumers. Keys of nested objects are converted too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CamelCase<T>(pub T);
+
+impl<T: Serialize> Serialize for CamelCase<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = serde_json::to_value(&self.0).map_err(serde::ser::Error::custom)?;
+        rename_keys(value, &to_camel_case).serialize(serializer)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for CamelCase<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = rename_keys(Value::deserialize(deserializer)?, &to_snake_case);
+        serde_json::from_value(value)
+            .map(CamelCase)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn rename_keys(value: Value, rename: &dyn Fn(&str) -> String) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (rename(&key), rename_keys(value, rename)))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| rename_keys(value, rename))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn to_camel_case(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+fn to_snake_case(key: &str) -> String {
+    let mut snake = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_uppercase() {
+            snake.push('_');
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::{CreateTodo, Todo, UpdateTodo};
+
+    fn keys(value: &Value) -> Vec<String> {
+        let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn todo_keys_are_snake_case_unless_wrapped() {
+        let todo = Todo::new(1, "buy milk".to_string());
+
+        let snake = serde_json::to_value(&todo).unwrap();
+        assert!(snake.get("created_at").is_some());
+        assert!(snake.get("createdAt").is_none());
+
+        let camel = serde_json::to_value(CamelCase(&todo)).unwrap();
+        assert!(camel.get("createdAt").is_some());
+        assert!(camel.get("estimateMinutes").is_some());
+        assert!(keys(&camel).iter().all(|key| !key.contains('_')));
+        assert_eq!(keys(&snake).len(), keys(&camel).len());
+
+        let CamelCase(back) = serde_json::from_value::<CamelCase<Todo>>(camel).unwrap();
+        assert_eq!(todo, back);
+    }
+
+    #[test]
+    fn payloads_round_trip_through_camel_case() {
+        let create = CreateTodo::builder()
+            .text("child")
+            .parent_id(1)
+            .estimate_minutes(30)
+            .build()
+            .unwrap();
+        let camel = serde_json::to_value(CamelCase(&create)).unwrap();
+        assert_eq!(Some(&Value::from(1)), camel.get("parentId"));
+        assert_eq!(Some(&Value::from(30)), camel.get("estimateMinutes"));
+        let CamelCase(back) = serde_json::from_value::<CamelCase<CreateTodo>>(camel).unwrap();
+        assert_eq!(create, back);
+
+        let CamelCase(update): CamelCase<UpdateTodo> =
+            serde_json::from_str(r#"{"estimateMinutes": null, "completed": true}"#).unwrap();
+        assert_eq!(
+            UpdateTodo {
+                estimate_minutes: Some(None),
+                ..UpdateTodo::completed(true)
+            },
+            update
+        );
+        let camel = serde_json::to_value(CamelCase(&update)).unwrap();
+        assert_eq!(Some(&Value::Null), camel.get("estimateMinutes"));
+        assert_eq!(Some(&Value::Null), camel.get("dueDate"));
+        let CamelCase(back) = serde_json::from_value::<CamelCase<UpdateTodo>>(camel).unwrap();
+        assert_eq!(update, back);
+    }
+}
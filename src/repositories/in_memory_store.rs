@@ -0,0 +1,826 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::{btree_map, hash_map, BTreeMap, HashMap},
+    fmt, iter,
+    ops::{Deref, DerefMut, Index},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+};
+
+// Hands out the ids of new entities. It must never hand out the same id
+// twice, or an entity would be overwritten.
+pub trait IdGenerator: fmt::Debug + Send + Sync {
+    fn next_id(&self) -> i32;
+
+    // Called when the store's contents are replaced, e.g. by a snapshot, so
+    // generators that count can carry on after `last_id`.
+    fn restart_after(&self, _last_id: i32) {}
+}
+
+// 1, 2, 3, ... carrying on after the largest id handed out so far.
+#[derive(Debug, Default)]
+pub struct SequentialIdGenerator {
+    last_id: AtomicI32,
+}
+
+impl SequentialIdGenerator {
+    pub fn starting_after(last_id: i32) -> Self {
+        Self {
+            last_id: AtomicI32::new(last_id),
+        }
+    }
+}
+
+impl IdGenerator for SequentialIdGenerator {
+    // Callers check that ids are left first, so running out is a bug.
+    fn next_id(&self) -> i32 {
+        let last_id = self
+            .last_id
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |id| id.checked_add(1))
+            .expect("no ids left after i32::MAX");
+
+        last_id + 1
+    }
+
+    fn restart_after(&self, last_id: i32) {
+        self.last_id.store(last_id, Ordering::SeqCst);
+    }
+}
+
+// The entities of a store by id. `Ordered` goes through them by id, so
+// callers need not sort, at the cost of O(log n) lookups.
+#[derive(Debug, Clone)]
+pub enum Entries<T> {
+    Hashed(HashMap<i32, T>),
+    Ordered(BTreeMap<i32, T>),
+}
+
+impl<T> Default for Entries<T> {
+    fn default() -> Self {
+        Self::Hashed(HashMap::new())
+    }
+}
+
+impl<T> From<HashMap<i32, T>> for Entries<T> {
+    fn from(entries: HashMap<i32, T>) -> Self {
+        Self::Hashed(entries)
+    }
+}
+
+impl<T> From<BTreeMap<i32, T>> for Entries<T> {
+    fn from(entries: BTreeMap<i32, T>) -> Self {
+        Self::Ordered(entries)
+    }
+}
+
+impl<T> FromIterator<(i32, T)> for Entries<T> {
+    fn from_iter<I: IntoIterator<Item = (i32, T)>>(iter: I) -> Self {
+        Self::Hashed(iter.into_iter().collect())
+    }
+}
+
+// Equal when they hold the same entities, however they keep them.
+impl<T: PartialEq> PartialEq for Entries<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(id, entity)| other.get(id) == Some(entity))
+    }
+}
+
+impl<T: Eq> Eq for Entries<T> {}
+
+impl<T> Index<&i32> for Entries<T> {
+    type Output = T;
+
+    fn index(&self, id: &i32) -> &T {
+        self.get(id).expect("no entry for id")
+    }
+}
+
+// Written as a plain map of id to entity. Reading one back gives `Hashed`.
+impl<T: Serialize> Serialize for Entries<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Entries<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HashMap::deserialize(deserializer).map(Self::Hashed)
+    }
+}
+
+impl<T> Entries<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn empty(ordered: bool) -> Self {
+        match ordered {
+            true => Self::Ordered(BTreeMap::new()),
+            false => Self::Hashed(HashMap::new()),
+        }
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        matches!(self, Self::Ordered(_))
+    }
+
+    pub fn into_ordered(self) -> Self {
+        match self {
+            Self::Hashed(entries) => Self::Ordered(entries.into_iter().collect()),
+            ordered => ordered,
+        }
+    }
+
+    pub fn get(&self, id: &i32) -> Option<&T> {
+        match self {
+            Self::Hashed(entries) => entries.get(id),
+            Self::Ordered(entries) => entries.get(id),
+        }
+    }
+
+    pub fn get_mut(&mut self, id: &i32) -> Option<&mut T> {
+        match self {
+            Self::Hashed(entries) => entries.get_mut(id),
+            Self::Ordered(entries) => entries.get_mut(id),
+        }
+    }
+
+    pub fn contains_key(&self, id: &i32) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn insert(&mut self, id: i32, entity: T) -> Option<T> {
+        match self {
+            Self::Hashed(entries) => entries.insert(id, entity),
+            Self::Ordered(entries) => entries.insert(id, entity),
+        }
+    }
+
+    pub fn remove(&mut self, id: &i32) -> Option<T> {
+        match self {
+            Self::Hashed(entries) => entries.remove(id),
+            Self::Ordered(entries) => entries.remove(id),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Hashed(entries) => entries.len(),
+            Self::Ordered(entries) => entries.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // How many entities fit before the storage grows. `Ordered` grows a node
+    // at a time, so it never has room to spare.
+    pub fn capacity(&self) -> usize {
+        match self {
+            Self::Hashed(entries) => entries.capacity(),
+            Self::Ordered(entries) => entries.len(),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        match self {
+            Self::Hashed(entries) => Iter::Hashed(entries.iter()),
+            Self::Ordered(entries) => Iter::Ordered(entries.iter()),
+        }
+    }
+
+    pub fn keys(&self) -> Keys<'_, T> {
+        let key: fn(_) -> _ = |(id, _)| id;
+        self.iter().map(key)
+    }
+
+    pub fn values(&self) -> Values<'_, T> {
+        let value: fn(_) -> _ = |(_, entity)| entity;
+        self.iter().map(value)
+    }
+
+    pub fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_> {
+        match self {
+            Self::Hashed(entries) => Box::new(entries.values_mut()),
+            Self::Ordered(entries) => Box::new(entries.values_mut()),
+        }
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&i32, &mut T) -> bool) {
+        match self {
+            Self::Hashed(entries) => entries.retain(|id, entity| keep(id, entity)),
+            Self::Ordered(entries) => entries.retain(|id, entity| keep(id, entity)),
+        }
+    }
+
+    // Empties the entries, keeping the way they are stored.
+    pub fn drain(&mut self) -> Box<dyn Iterator<Item = (i32, T)> + '_> {
+        match self {
+            Self::Hashed(entries) => Box::new(entries.drain()),
+            Self::Ordered(entries) => Box::new(std::mem::take(entries).into_iter()),
+        }
+    }
+}
+
+pub type Keys<'a, T> = iter::Map<Iter<'a, T>, fn((&'a i32, &'a T)) -> &'a i32>;
+pub type Values<'a, T> = iter::Map<Iter<'a, T>, fn((&'a i32, &'a T)) -> &'a T>;
+
+pub enum Iter<'a, T> {
+    Hashed(hash_map::Iter<'a, i32, T>),
+    Ordered(btree_map::Iter<'a, i32, T>),
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (&'a i32, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Hashed(iter) => iter.next(),
+            Self::Ordered(iter) => iter.next(),
+        }
+    }
+}
+
+// How many locks the entities of a hashed store are spread over.
+const SHARDS: usize = 16;
+
+// The entities spread over `SHARDS` locks by id, so calls on a single entity
+// lock only its shard and calls on entities in other shards go ahead at the
+// same time. Every such call read-locks `global` first; calls on the whole
+// store lock it too, then every shard in order. Ordered storage keeps
+// everything in the first shard, so going through it by id needs no merging,
+// at the cost of the sharding.
+#[derive(Debug)]
+struct Shards<T> {
+    global: RwLock<()>,
+    shards: Vec<RwLock<Entries<T>>>,
+    ordered: AtomicBool,
+}
+
+impl<T> Shards<T> {
+    fn new(mut entries: Entries<T>) -> Self {
+        let ordered = entries.is_ordered();
+        let mut shards: Vec<Entries<T>> = (0..SHARDS).map(|_| Entries::empty(ordered)).collect();
+        for (id, entity) in entries.drain() {
+            shards[shard_of(id, ordered)].insert(id, entity);
+        }
+
+        Self {
+            global: RwLock::new(()),
+            shards: shards.into_iter().map(RwLock::new).collect(),
+            ordered: AtomicBool::new(ordered),
+        }
+    }
+
+    // Only changes while `global` is write-locked.
+    fn ordered(&self) -> bool {
+        self.ordered.load(Ordering::SeqCst)
+    }
+}
+
+fn shard_of(id: i32, ordered: bool) -> usize {
+    match ordered {
+        true => 0,
+        false => id.rem_euclid(SHARDS as i32) as usize,
+    }
+}
+
+// A panic while a lock is held poisons it. Callers leave the entries
+// consistent before they can panic, so carry on with the data as is rather
+// than failing every later call.
+fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+enum GlobalGuard<'a> {
+    Read { _guard: RwLockReadGuard<'a, ()> },
+    Write { _guard: RwLockWriteGuard<'a, ()> },
+}
+
+enum ShardGuard<'a, T> {
+    Read(RwLockReadGuard<'a, Entries<T>>),
+    Write(RwLockWriteGuard<'a, Entries<T>>),
+}
+
+impl<T> Deref for ShardGuard<'_, T> {
+    type Target = Entries<T>;
+
+    fn deref(&self) -> &Entries<T> {
+        match self {
+            Self::Read(shard) => shard,
+            Self::Write(shard) => shard,
+        }
+    }
+}
+
+impl<T> DerefMut for ShardGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Entries<T> {
+        match self {
+            Self::Read(_) => panic!("changed a store locked with `read`"),
+            Self::Write(shard) => shard,
+        }
+    }
+}
+
+// The whole store, locked by `InMemoryStore::read` or `write`, with the same
+// calls as `Entries`. One from `read` panics when changed.
+pub struct Locked<'a, T> {
+    store: &'a Shards<T>,
+    shards: Vec<ShardGuard<'a, T>>,
+    _global: GlobalGuard<'a>,
+}
+
+impl<T> Locked<'_, T> {
+    fn shard(&self, id: i32) -> &Entries<T> {
+        &self.shards[shard_of(id, self.is_ordered())]
+    }
+
+    fn shard_mut(&mut self, id: i32) -> &mut Entries<T> {
+        let shard = shard_of(id, self.is_ordered());
+        &mut self.shards[shard]
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        self.store.ordered()
+    }
+
+    pub fn get(&self, id: &i32) -> Option<&T> {
+        self.shard(*id).get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &i32) -> Option<&mut T> {
+        self.shard_mut(*id).get_mut(id)
+    }
+
+    pub fn contains_key(&self, id: &i32) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn insert(&mut self, id: i32, entity: T) -> Option<T> {
+        self.shard_mut(id).insert(id, entity)
+    }
+
+    pub fn remove(&mut self, id: &i32) -> Option<T> {
+        self.shard_mut(*id).remove(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.is_empty())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shards.iter().map(|shard| shard.capacity()).sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&i32, &T)> + '_ {
+        self.shards.iter().flat_map(|shard| shard.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &i32> + '_ {
+        self.iter().map(|(id, _)| id)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> + '_ {
+        self.iter().map(|(_, entity)| entity)
+    }
+
+    pub fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_> {
+        Box::new(self.shards.iter_mut().flat_map(|shard| shard.values_mut()))
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&i32, &mut T) -> bool) {
+        for shard in self.shards.iter_mut() {
+            shard.retain(&mut keep);
+        }
+    }
+
+    // Empties the store, keeping the way it is stored.
+    pub fn drain(&mut self) -> impl Iterator<Item = (i32, T)> {
+        let drained: Vec<(i32, T)> = self
+            .shards
+            .iter_mut()
+            .flat_map(|shard| shard.drain())
+            .collect();
+
+        drained.into_iter()
+    }
+
+    // A copy of every entity, stored the same way.
+    pub fn to_entries(&self) -> Entries<T>
+    where
+        T: Clone,
+    {
+        let mut entries = Entries::empty(self.is_ordered());
+        for (id, entity) in self.iter() {
+            entries.insert(*id, entity.clone());
+        }
+
+        entries
+    }
+
+    // Swaps every entity for `entries`, stored the way `entries` are.
+    pub fn replace(&mut self, mut entries: Entries<T>) {
+        let ordered = entries.is_ordered();
+        for shard in self.shards.iter_mut() {
+            **shard = Entries::empty(ordered);
+        }
+        self.store.ordered.store(ordered, Ordering::SeqCst);
+        for (id, entity) in entries.drain() {
+            self.insert(id, entity);
+        }
+    }
+}
+
+impl<T> Index<&i32> for Locked<'_, T> {
+    type Output = T;
+
+    fn index(&self, id: &i32) -> &T {
+        self.get(id).expect("no entry for id")
+    }
+}
+
+// Entities keyed by an id the store hands out, locked by shard as `Shards`
+// describes. Clones share the same data.
+#[derive(Debug)]
+pub struct InMemoryStore<T> {
+    entries: Arc<Shards<T>>,
+    ids: Arc<dyn IdGenerator>,
+    last_id: Arc<AtomicI32>,
+}
+
+impl<T> Clone for InMemoryStore<T> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: Arc::clone(&self.entries),
+            ids: Arc::clone(&self.ids),
+            last_id: Arc::clone(&self.last_id),
+        }
+    }
+}
+
+impl<T> Default for InMemoryStore<T> {
+    fn default() -> Self {
+        Self::from_entries(HashMap::new(), 0)
+    }
+}
+
+impl<T> InMemoryStore<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `last_id` is the largest id handed out so far; the next one follows it.
+    pub fn from_entries(entries: impl Into<Entries<T>>, last_id: i32) -> Self {
+        Self {
+            entries: Arc::new(Shards::new(entries.into())),
+            ids: Arc::new(SequentialIdGenerator::starting_after(last_id)),
+            last_id: Arc::new(AtomicI32::new(last_id)),
+        }
+    }
+
+    pub fn with_id_generator(mut self, ids: impl IdGenerator + 'static) -> Self {
+        self.ids = Arc::new(ids);
+        self
+    }
+
+    // Ids are never reused, even after the entity holding one is removed.
+    pub fn next_id(&self) -> i32 {
+        let id = self.ids.next_id();
+        self.last_id.fetch_max(id, Ordering::SeqCst);
+        id
+    }
+
+    // The largest id handed out so far.
+    pub fn last_id(&self) -> i32 {
+        self.last_id.load(Ordering::SeqCst)
+    }
+
+    pub fn set_last_id(&self, last_id: i32) {
+        self.last_id.store(last_id, Ordering::SeqCst);
+        self.ids.restart_after(last_id);
+    }
+
+    // Locks the whole store, waiting for the calls on single entities to
+    // finish.
+    pub fn write(&self) -> Locked<'_, T> {
+        let global = write_lock(&self.entries.global);
+
+        Locked {
+            store: &self.entries,
+            shards: self
+                .entries
+                .shards
+                .iter()
+                .map(|shard| ShardGuard::Write(write_lock(shard)))
+                .collect(),
+            _global: GlobalGuard::Write { _guard: global },
+        }
+    }
+
+    pub fn read(&self) -> Locked<'_, T> {
+        let global = read_lock(&self.entries.global);
+
+        Locked {
+            store: &self.entries,
+            shards: self
+                .entries
+                .shards
+                .iter()
+                .map(|shard| ShardGuard::Read(read_lock(shard)))
+                .collect(),
+            _global: GlobalGuard::Read { _guard: global },
+        }
+    }
+
+    // Lends the entity under `id` to `f` with only its shard locked. `f` must
+    // not call back into the store.
+    pub fn with_entity<R>(&self, id: i32, f: impl FnOnce(Option<&T>) -> R) -> R {
+        let _global = read_lock(&self.entries.global);
+        let shard = read_lock(&self.entries.shards[shard_of(id, self.entries.ordered())]);
+
+        f(shard.get(&id))
+    }
+
+    // `with_entity` for changing the entity in place.
+    pub fn with_entity_mut<R>(&self, id: i32, f: impl FnOnce(Option<&mut T>) -> R) -> R {
+        let _global = read_lock(&self.entries.global);
+        let mut shard = write_lock(&self.entries.shards[shard_of(id, self.entries.ordered())]);
+
+        f(shard.get_mut(&id))
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.entries.global.is_poisoned()
+            || self.entries.shards.iter().any(|shard| shard.is_poisoned())
+    }
+
+    // Builds the entity from a fresh id and stores it, with the id's shard
+    // locked.
+    pub fn insert_with(&self, build: impl FnOnce(i32) -> T) -> T
+    where
+        T: Clone,
+    {
+        let _global = read_lock(&self.entries.global);
+
+        let id = self.next_id();
+        let entity = build(id);
+        write_lock(&self.entries.shards[shard_of(id, self.entries.ordered())])
+            .insert(id, entity.clone());
+
+        entity
+    }
+
+    pub fn get(&self, id: i32) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.with_entity(id, |entity| entity.cloned())
+    }
+
+    pub fn update(&self, id: i32, change: impl FnOnce(&mut T)) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.with_entity_mut(id, |entity| {
+            let entity = entity?;
+            change(entity);
+
+            Some(entity.clone())
+        })
+    }
+
+    // Moves the entities into id order storage, for this store and its
+    // clones.
+    pub fn make_ordered(&self) {
+        let mut entries = self.write();
+        let ordered: BTreeMap<i32, T> = entries.drain().collect();
+        entries.replace(ordered.into());
+    }
+
+    pub fn remove(&self, id: i32) -> Option<T> {
+        let _global = read_lock(&self.entries.global);
+
+        write_lock(&self.entries.shards[shard_of(id, self.entries.ordered())]).remove(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Note {
+        id: i32,
+        body: String,
+    }
+
+    fn note(store: &InMemoryStore<Note>, body: &str) -> Note {
+        store.insert_with(|id| Note {
+            id,
+            body: body.to_string(),
+        })
+    }
+
+    #[test]
+    fn insert_with_hands_out_increasing_ids() {
+        let store = InMemoryStore::new();
+
+        assert_eq!(1, note(&store, "first").id);
+        assert_eq!(2, note(&store, "second").id);
+        assert_eq!(2, store.len());
+        assert_eq!(2, store.last_id());
+    }
+
+    #[test]
+    fn get_update_and_remove() {
+        let store = InMemoryStore::new();
+        let first = note(&store, "first");
+
+        assert_eq!(Some(first.clone()), store.get(1));
+        let updated = store.update(1, |note| note.body = "edited".to_string());
+        assert_eq!("edited", updated.unwrap().body);
+        assert_eq!(None, store.update(2, |_| ()));
+
+        assert_eq!("edited", store.remove(1).unwrap().body);
+        assert_eq!(None, store.get(1));
+        assert!(store.is_empty());
+        assert_eq!(2, note(&store, "after remove").id);
+    }
+
+    #[test]
+    fn from_entries_continues_after_last_id() {
+        let store = InMemoryStore::from_entries(
+            HashMap::from([(
+                7,
+                Note {
+                    id: 7,
+                    body: "seeded".to_string(),
+                },
+            )]),
+            7,
+        );
+
+        assert_eq!("seeded", store.get(7).unwrap().body);
+        assert_eq!(8, note(&store, "next").id);
+    }
+
+    #[test]
+    fn ordered_entries_go_by_id() {
+        let mut hashed = Entries::new();
+        for id in [9, 2, 30, 4] {
+            hashed.insert(id, id * 10);
+        }
+        let mut ordered = hashed.clone().into_ordered();
+        assert!(ordered.is_ordered());
+        assert_eq!(
+            vec![2, 4, 9, 30],
+            ordered.keys().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(hashed, ordered);
+
+        ordered.remove(&9);
+        ordered.insert(1, 10);
+        assert_ne!(hashed, ordered);
+        assert_eq!(
+            vec![10, 20, 40, 300],
+            ordered.values().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(1, 10), (2, 20), (4, 40), (30, 300)],
+            ordered.drain().collect::<Vec<_>>()
+        );
+        assert!(ordered.is_empty() && ordered.is_ordered());
+    }
+
+    #[test]
+    fn make_ordered_applies_to_clones() {
+        let store = InMemoryStore::new();
+        let clone = store.clone();
+        note(&store, "first");
+
+        clone.make_ordered();
+        assert!(store.read().is_ordered());
+        assert_eq!(
+            Some("first".to_string()),
+            store.get(1).map(|note| note.body)
+        );
+    }
+
+    #[test]
+    fn entities_in_every_shard_make_up_the_store() {
+        let store = InMemoryStore::new();
+        for i in 0..40 {
+            note(&store, &format!("note {}", i));
+        }
+        store.update(40, |note| note.body = "edited".to_string());
+        store.remove(17);
+
+        let mut ids: Vec<i32> = store.read().keys().copied().collect();
+        ids.sort_unstable();
+        assert_eq!((1..=40).filter(|id| *id != 17).collect::<Vec<_>>(), ids);
+        assert_eq!("edited", store.read()[&40].body);
+
+        store.make_ordered();
+        let ordered = store.read();
+        assert!(ordered.is_ordered());
+        assert_eq!(ids, ordered.keys().copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn replace_swaps_the_entries_and_their_ordering() {
+        let store = InMemoryStore::new();
+        note(&store, "gone");
+
+        let mut entries = Entries::new();
+        for id in [5, 3, 21] {
+            entries.insert(
+                id,
+                Note {
+                    id,
+                    body: id.to_string(),
+                },
+            );
+        }
+        store.write().replace(entries.clone().into_ordered());
+
+        let replaced = store.read();
+        assert!(replaced.is_ordered());
+        assert_eq!(vec![3, 5, 21], replaced.keys().copied().collect::<Vec<_>>());
+        assert_eq!(entries, replaced.to_entries());
+    }
+
+    #[test]
+    fn calls_on_entities_in_other_shards_go_ahead() {
+        let store = InMemoryStore::new();
+        note(&store, "first");
+        note(&store, "second");
+
+        let (done, finished) = std::sync::mpsc::channel();
+        let other = store.clone();
+        // Holds the first note's shard until the second note has changed, which
+        // would never happen if both waited on the same lock.
+        let waited = store.with_entity_mut(1, |_| {
+            std::thread::spawn(move || {
+                other.update(2, |note| note.body = "edited".to_string());
+                done.send(()).unwrap();
+            });
+            finished.recv_timeout(std::time::Duration::from_secs(5))
+        });
+
+        assert!(waited.is_ok());
+        assert_eq!("edited", store.get(2).unwrap().body);
+    }
+
+    #[derive(Debug)]
+    struct Countdown(AtomicI32);
+
+    impl IdGenerator for Countdown {
+        fn next_id(&self) -> i32 {
+            self.0.fetch_sub(1, Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn ids_come_from_the_injected_generator() {
+        let store = InMemoryStore::new().with_id_generator(Countdown(AtomicI32::new(10)));
+
+        assert_eq!(10, note(&store, "first").id);
+        assert_eq!(9, note(&store, "second").id);
+        assert_eq!(10, store.last_id());
+    }
+
+    #[test]
+    fn clones_share_entries() {
+        let store = InMemoryStore::new();
+        let clone = store.clone();
+        note(&clone, "shared");
+
+        assert_eq!(
+            Some("shared".to_string()),
+            store.get(1).map(|note| note.body)
+        );
+    }
+}
@@ -0,0 +1,562 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+
+use super::{
+    clock::Clock, in_memory_store::InMemoryStore, metrics::Metrics, BulkDeleteReport,
+    CompleteOutcome, CreateTodo, OnParentDelete, OverlongText, Todo, TodoDates, TodoFilter,
+    TodoRepository, TodoRepositoryForMemory, UpdateTodo,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonFile {
+    last_id: i32,
+    todos: TodoDates,
+}
+
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForJsonFile {
+    inner: TodoRepositoryForMemory,
+    file: Arc<StoreFile>,
+}
+
+// Shared by every clone of a repository. When writes are batched, the last
+// clone to go flushes whatever is still pending.
+#[derive(Debug)]
+struct StoreFile {
+    path: PathBuf,
+    store: InMemoryStore<Todo>,
+    save_lock: Mutex<()>,
+    batched: bool,
+    dirty: AtomicBool,
+    writes: AtomicUsize,
+}
+
+impl StoreFile {
+    fn new(path: PathBuf, store: InMemoryStore<Todo>, batched: bool) -> Self {
+        Self {
+            path,
+            store,
+            save_lock: Mutex::new(()),
+            batched,
+            dirty: AtomicBool::new(false),
+            writes: AtomicUsize::new(0),
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if self.batched {
+            self.dirty.store(true, Ordering::SeqCst);
+            return Ok(());
+        }
+
+        self.write()
+    }
+
+    // Changes made while the file is written mark it dirty again, so the next
+    // flush picks them up.
+    fn flush(&self) -> io::Result<()> {
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        self.write()
+            .inspect_err(|_| self.dirty.store(true, Ordering::SeqCst))
+    }
+
+    // Writes the whole store to a sibling temp file and renames it over the
+    // target, so a crash mid-write never leaves a truncated file behind. The
+    // temp file is synced before the rename, and the directory after it, so
+    // the rename can't reach the disk ahead of the data or be lost.
+    fn write(&self) -> io::Result<()> {
+        let _guard = self.save_lock.lock().unwrap();
+
+        let bytes = {
+            let store = self.store.read();
+            serde_json::to_vec(&JsonFile {
+                last_id: self.store.last_id(),
+                todos: store.to_entries(),
+            })?
+        };
+        let tmp_path = tmp_path(&self.path);
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&bytes)?;
+        tmp.sync_all()?;
+        drop(tmp);
+        fs::rename(&tmp_path, &self.path)?;
+        sync_dir(&self.path)?;
+        self.writes.fetch_add(1, Ordering::SeqCst);
+
+        Ok(())
+    }
+}
+
+impl Drop for StoreFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            tracing::error!("failed to flush {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+async fn flush_every(file: Weak<StoreFile>, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        let Some(file) = file.upgrade() else {
+            return;
+        };
+        if let Err(e) = file.flush() {
+            tracing::error!("failed to flush {}: {}", file.path.display(), e);
+        }
+    }
+}
+
+impl TodoRepositoryForJsonFile {
+    pub fn new(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let inner = match fs::read(&path) {
+            Ok(bytes) => {
+                let file: JsonFile = serde_json::from_slice(&bytes)?;
+                TodoRepositoryForMemory::from_store(InMemoryStore::from_entries(
+                    file.todos,
+                    file.last_id,
+                ))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => TodoRepositoryForMemory::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let file = Arc::new(StoreFile::new(path, inner.store.clone(), false));
+        Ok(Self { inner, file })
+    }
+
+    // Mutations only mark the store as changed, and a background task writes
+    // it out at most once per `interval`. Call `flush` to write straight
+    // away; dropping the last clone of the repository flushes too. Needs a
+    // Tokio runtime to spawn the task on.
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        let file = Arc::new(StoreFile::new(
+            self.file.path.clone(),
+            self.inner.store.clone(),
+            true,
+        ));
+        tokio::spawn(flush_every(Arc::downgrade(&file), interval));
+        self.file = file;
+        self
+    }
+
+    // Writes any changes not yet on disk. Without `with_flush_interval`
+    // every change is written as it happens, so there is nothing to do.
+    pub fn flush(&self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    pub fn with_on_parent_delete(mut self, on_parent_delete: OnParentDelete) -> Self {
+        self.inner = self.inner.with_on_parent_delete(on_parent_delete);
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.inner = self.inner.with_metrics(metrics);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.inner = self.inner.with_clock(clock);
+        self
+    }
+
+    pub fn with_ordered_storage(mut self) -> Self {
+        self.inner = self.inner.with_ordered_storage();
+        self
+    }
+
+    pub fn with_normalized_text(mut self, normalize_text: bool) -> Self {
+        self.inner = self.inner.with_normalized_text(normalize_text);
+        self
+    }
+
+    pub fn with_max_text_len(mut self, max_text_len: usize, overlong: OverlongText) -> Self {
+        self.inner = self.inner.with_max_text_len(max_text_len, overlong);
+        self
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        Ok(self.file.save()?)
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+// Only Unix can open a directory to sync it.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[async_trait]
+impl TodoRepository for TodoRepositoryForJsonFile {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let todo = self.inner.create(payload).await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn find_or_create(&self, text: &str) -> anyhow::Result<Todo> {
+        let todo = self.inner.find_or_create(text).await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn create_unique(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let todo = self.inner.create_unique(payload).await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn find(&self, id: i32) -> Option<Todo> {
+        self.inner.find(id).await
+    }
+
+    async fn exists(&self, id: i32) -> bool {
+        self.inner.exists(id).await
+    }
+
+    async fn all(&self) -> Vec<Todo> {
+        self.inner.all().await
+    }
+
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        let todo = self.inner.update(id, payload).await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn update_if_version(
+        &self,
+        id: i32,
+        expected_version: u32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo> {
+        let todo = self
+            .inner
+            .update_if_version(id, expected_version, payload)
+            .await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn replace(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<(Todo, Todo)> {
+        let todos = self.inner.replace(id, payload).await?;
+        self.save()?;
+
+        Ok(todos)
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        self.inner.delete(id).await?;
+        self.save()?;
+
+        Ok(())
+    }
+
+    async fn count(&self) -> usize {
+        self.inner.count().await
+    }
+
+    async fn count_completed(&self) -> usize {
+        self.inner.count_completed().await
+    }
+
+    async fn bulk_create(&self, payloads: Vec<CreateTodo>) -> anyhow::Result<Vec<Todo>> {
+        let todos = self.inner.bulk_create(payloads).await?;
+        self.save()?;
+
+        Ok(todos)
+    }
+
+    async fn bulk_delete(&self, ids: Vec<i32>) -> anyhow::Result<BulkDeleteReport> {
+        let report = self.inner.bulk_delete(ids).await?;
+        self.save()?;
+
+        Ok(report)
+    }
+
+    async fn toggle_completed(&self, id: i32) -> anyhow::Result<Todo> {
+        let todo = self.inner.toggle_completed(id).await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn complete(&self, id: i32) -> anyhow::Result<CompleteOutcome> {
+        let outcome = self.inner.complete(id).await?;
+        if let CompleteOutcome::JustCompleted(_) = outcome {
+            self.save()?;
+        }
+
+        Ok(outcome)
+    }
+
+    async fn merge(&self, keep_id: i32, merge_id: i32) -> anyhow::Result<Todo> {
+        let todo = self.inner.merge(keep_id, merge_id).await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn soft_delete(&self, id: i32) -> anyhow::Result<()> {
+        self.inner.soft_delete(id).await?;
+        self.save()?;
+
+        Ok(())
+    }
+
+    async fn restore(&self, id: i32) -> anyhow::Result<Todo> {
+        let todo = self.inner.restore(id).await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn clear_all(&self) -> anyhow::Result<()> {
+        self.inner.clear_all().await?;
+        self.save()?;
+
+        Ok(())
+    }
+
+    async fn reorder(&self, id: i32, new_position: i32) -> anyhow::Result<()> {
+        self.inner.reorder(id, new_position).await?;
+        self.save()?;
+
+        Ok(())
+    }
+
+    async fn complete_all(&self) -> anyhow::Result<usize> {
+        let count = self.inner.complete_all().await?;
+        self.save()?;
+
+        Ok(count)
+    }
+
+    async fn complete_where(&self, filter: TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        let todos = self.inner.complete_where(filter).await?;
+        self.save()?;
+
+        Ok(todos)
+    }
+
+    async fn update_where(
+        &self,
+        filter: TodoFilter,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let todos = self.inner.update_where(filter, payload).await?;
+        self.save()?;
+
+        Ok(todos)
+    }
+
+    async fn clear_completed(&self) -> anyhow::Result<usize> {
+        let count = self.inner.clear_completed().await?;
+        self.save()?;
+
+        Ok(count)
+    }
+
+    async fn archive(&self, id: i32) -> anyhow::Result<Todo> {
+        let todo = self.inner.archive(id).await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn unarchive(&self, id: i32) -> anyhow::Result<Todo> {
+        let todo = self.inner.unarchive(id).await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn archived(&self) -> Vec<Todo> {
+        self.inner.archived().await
+    }
+
+    async fn log_time(&self, id: i32, minutes: u32) -> anyhow::Result<Todo> {
+        let todo = self.inner.log_time(id, minutes).await?;
+        self.save()?;
+
+        Ok(todo)
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        self.inner.health_check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::test_utils::run_conformance;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("my-todo-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let path = temp_file("conformance");
+
+        run_conformance(TodoRepositoryForJsonFile::new(&path).unwrap()).await;
+        let _ = fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn missing_file_is_an_empty_store() {
+        let path = temp_file("missing");
+
+        let repository = TodoRepositoryForJsonFile::new(&path).unwrap();
+        assert!(repository.all().await.is_empty());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn todos_survive_reopening() {
+        let path = temp_file("reopen");
+
+        let repository = TodoRepositoryForJsonFile::new(&path).unwrap();
+        let first = repository
+            .create(CreateTodo::new("first".to_string()))
+            .await
+            .unwrap();
+        let second = repository
+            .create(CreateTodo::new("second".to_string()))
+            .await
+            .unwrap();
+        let second = repository
+            .update(second.id, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+        repository.delete(first.id).await.unwrap();
+        drop(repository);
+
+        let repository = TodoRepositoryForJsonFile::new(&path).unwrap();
+        assert_eq!(vec![second], repository.all().await);
+        assert!(!tmp_path(&path).exists());
+
+        let third = repository
+            .create(CreateTodo::new("third".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(3, third.id);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn writes(repository: &TodoRepositoryForJsonFile) -> usize {
+        repository.file.writes.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn batched_writes_wait_for_flush() {
+        let path = temp_file("batched");
+
+        let repository = TodoRepositoryForJsonFile::new(&path)
+            .unwrap()
+            .with_flush_interval(Duration::from_secs(3600));
+        for text in ["first", "second", "third"] {
+            repository.create(text.into()).await.unwrap();
+        }
+        repository
+            .update(2, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+        assert_eq!(0, writes(&repository));
+        assert!(!path.exists());
+
+        repository.flush().unwrap();
+        repository.flush().unwrap();
+        assert_eq!(1, writes(&repository));
+        let reopened = TodoRepositoryForJsonFile::new(&path).unwrap();
+        assert_eq!(3, reopened.count().await);
+        assert!(reopened.find(2).await.unwrap().completed);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn batched_writes_coalesce_in_the_background() {
+        let path = temp_file("coalesced");
+
+        let repository = TodoRepositoryForJsonFile::new(&path)
+            .unwrap()
+            .with_flush_interval(Duration::from_millis(20));
+        for i in 0..20 {
+            repository
+                .create(format!("todo {}", i).into())
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!((1..20).contains(&writes(&repository)));
+        assert_eq!(
+            20,
+            TodoRepositoryForJsonFile::new(&path).unwrap().count().await
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropping_a_batched_repository_flushes_it() {
+        let path = temp_file("dropped");
+
+        let repository = TodoRepositoryForJsonFile::new(&path)
+            .unwrap()
+            .with_flush_interval(Duration::from_secs(3600));
+        let clone = repository.clone();
+        repository.create("kept".into()).await.unwrap();
+        drop(repository);
+        assert!(!path.exists());
+        drop(clone);
+
+        let repository = TodoRepositoryForJsonFile::new(&path).unwrap();
+        assert_eq!("kept", repository.find(1).await.unwrap().text);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
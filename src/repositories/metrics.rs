@@ -0,0 +1,17 @@
+use std::fmt;
+
+// Callbacks for counting repository usage, e.g. creates per minute. Each one
+// defaults to doing nothing, so implementations only override what they
+// record.
+pub trait Metrics: fmt::Debug + Send + Sync {
+    fn on_create(&self, _id: i32) {}
+    fn on_update(&self, _id: i32) {}
+    fn on_delete(&self, _id: i32) {}
+    // `find` was asked for an id that is missing or soft-deleted.
+    fn on_find_miss(&self, _id: i32) {}
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
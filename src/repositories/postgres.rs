@@ -0,0 +1,634 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::{types::Json, PgConnection, PgPool, Postgres};
+
+use super::{
+    reordered, CreateTodo, OnParentDelete, OverlongText, RepositoryError, TextLimit, Todo,
+    TodoRepository, UpdateTodo,
+};
+
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForDb {
+    pool: PgPool,
+    on_parent_delete: OnParentDelete,
+    text_limit: TextLimit,
+}
+
+impl TodoRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            on_parent_delete: OnParentDelete::default(),
+            text_limit: TextLimit::default(),
+        }
+    }
+
+    pub fn with_on_parent_delete(mut self, on_parent_delete: OnParentDelete) -> Self {
+        self.on_parent_delete = on_parent_delete;
+        self
+    }
+
+    // Defaults to 10,000 characters, rejecting anything longer.
+    pub fn with_max_text_len(mut self, max_text_len: usize, overlong: OverlongText) -> Self {
+        self.text_limit = TextLimit {
+            max_len: max_text_len,
+            overlong,
+        };
+        self
+    }
+
+    async fn set_archived(&self, id: i32, archived: bool) -> anyhow::Result<Todo> {
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+UPDATE todos SET archived = $1, updated_at = $2, version = version + 1
+WHERE id = $3 AND deleted_at IS NULL
+RETURNING *
+            "#,
+        )
+        .bind(archived)
+        .bind(chrono::Utc::now())
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .context(RepositoryError::NotFound(id))?;
+
+        Ok(todo)
+    }
+
+    async fn update_checked(
+        &self,
+        id: i32,
+        expected_version: Option<u32>,
+        mut payload: UpdateTodo,
+    ) -> anyhow::Result<(Todo, Todo)> {
+        payload.validate(id)?;
+        if let Some(text) = payload.text.as_mut() {
+            self.text_limit.apply(text)?;
+        }
+        if let Some(blocked_by) = &payload.blocked_by {
+            self.check_blockers(blocked_by).await?;
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        let before = sqlx::query_as::<_, Todo>(
+            "SELECT * FROM todos WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .context(RepositoryError::NotFound(id))?;
+        if expected_version.is_some_and(|version| version != before.version) {
+            return Err(RepositoryError::Conflict(id).into());
+        }
+        let todo = before.updated(payload);
+        sqlx::query(
+            r#"
+UPDATE todos
+SET text = $1, completed = $2, updated_at = $3, due_date = $4, priority = $5, tags = $6,
+    description = $7, assignee = $8, color = $9, blocked_by = $10, estimate_minutes = $11,
+    attachments = $12, version = $13
+WHERE id = $14
+            "#,
+        )
+        .bind(&todo.text)
+        .bind(todo.completed)
+        .bind(todo.updated_at)
+        .bind(todo.due_date)
+        .bind(todo.priority)
+        .bind(Json(&todo.tags))
+        .bind(&todo.description)
+        .bind(&todo.assignee)
+        .bind(&todo.color)
+        .bind(Json(&todo.blocked_by))
+        .bind(todo.estimate_minutes.map(i64::from))
+        .bind(Json(&todo.attachments))
+        .bind(todo.version as i64)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        if let Some(next) = before.next_occurrence(&todo) {
+            insert_todo(&mut *tx, &Todo::from_payload(0, next)).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok((before, todo))
+    }
+
+    async fn check_blockers(&self, blocked_by: &[i32]) -> anyhow::Result<()> {
+        for id in blocked_by.iter().copied() {
+            self.find(id).await.context(RepositoryError::NotFound(id))?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn migrate(&self) -> anyhow::Result<()> {
+        sqlx::migrate!("./migrations/postgres")
+            .run(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TodoRepository for TodoRepositoryForDb {
+    async fn create(&self, mut payload: CreateTodo) -> anyhow::Result<Todo> {
+        payload.validate()?;
+        payload.reject_client_id()?;
+        self.text_limit.apply(&mut payload.text)?;
+        if let Some(parent_id) = payload.parent_id {
+            self.find(parent_id)
+                .await
+                .context(RepositoryError::NotFound(parent_id))?;
+        }
+        self.check_blockers(&payload.blocked_by).await?;
+
+        // The id is assigned by the database.
+        let todo = Todo::from_payload(0, payload);
+        let todo = insert_todo(&self.pool, &todo).await?;
+
+        Ok(todo)
+    }
+
+    async fn find_or_create(&self, text: &str) -> anyhow::Result<Todo> {
+        let mut payload = CreateTodo::new(text.to_string());
+        payload.validate()?;
+        self.text_limit.apply(&mut payload.text)?;
+
+        let mut tx = self.pool.begin().await?;
+
+        // Serialises concurrent calls so two of them cannot both insert.
+        sqlx::query("LOCK TABLE todos IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+        let existing = sqlx::query_as::<_, Todo>(
+            "SELECT * FROM todos WHERE text = $1 AND deleted_at IS NULL ORDER BY id LIMIT 1",
+        )
+        .bind(&payload.text)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let todo = match existing {
+            Some(todo) => todo,
+            None => insert_todo(&mut *tx, &Todo::from_payload(0, payload)).await?,
+        };
+
+        tx.commit().await?;
+
+        Ok(todo)
+    }
+
+    async fn create_unique(&self, mut payload: CreateTodo) -> anyhow::Result<Todo> {
+        payload.validate()?;
+        payload.reject_client_id()?;
+        self.text_limit.apply(&mut payload.text)?;
+        if let Some(parent_id) = payload.parent_id {
+            self.find(parent_id)
+                .await
+                .context(RepositoryError::NotFound(parent_id))?;
+        }
+        self.check_blockers(&payload.blocked_by).await?;
+
+        let mut tx = self.pool.begin().await?;
+
+        // Serialises concurrent calls so two of them cannot both insert.
+        sqlx::query("LOCK TABLE todos IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+        let duplicate = sqlx::query_scalar::<_, i32>(
+            "SELECT id FROM todos WHERE lower(text) = lower($1) AND deleted_at IS NULL ORDER BY id LIMIT 1",
+        )
+        .bind(&payload.text)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(id) = duplicate {
+            return Err(RepositoryError::Conflict(id).into());
+        }
+        let todo = insert_todo(&mut *tx, &Todo::from_payload(0, payload)).await?;
+
+        tx.commit().await?;
+
+        Ok(todo)
+    }
+
+    async fn find(&self, id: i32) -> Option<Todo> {
+        sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE id = $1 AND deleted_at IS NULL")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("failed to find todo {}: {}", id, e);
+                None
+            })
+    }
+
+    async fn exists(&self, id: i32) -> bool {
+        sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM todos WHERE id = $1 AND deleted_at IS NULL)",
+        )
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("failed to check todo {}: {}", id, e);
+            false
+        })
+    }
+
+    async fn all(&self) -> Vec<Todo> {
+        sqlx::query_as::<_, Todo>(
+            "SELECT * FROM todos WHERE deleted_at IS NULL AND NOT archived ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("failed to fetch todos: {}", e);
+            Vec::new()
+        })
+    }
+
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        let (_, todo) = self.update_checked(id, None, payload).await?;
+
+        Ok(todo)
+    }
+
+    async fn update_if_version(
+        &self,
+        id: i32,
+        expected_version: u32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo> {
+        let (_, todo) = self
+            .update_checked(id, Some(expected_version), payload)
+            .await?;
+
+        Ok(todo)
+    }
+
+    async fn replace(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<(Todo, Todo)> {
+        self.update_checked(id, None, payload).await
+    }
+
+    // Removes the row for good, whether or not it was soft-deleted. Its
+    // children are orphaned or deleted depending on `on_parent_delete`.
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        if !delete_todo(&mut tx, id, self.on_parent_delete).await? {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn all_paginated(&self, offset: usize, limit: usize) -> Vec<Todo> {
+        sqlx::query_as::<_, Todo>(
+            "SELECT * FROM todos WHERE deleted_at IS NULL AND NOT archived ORDER BY id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("failed to fetch todos: {}", e);
+            Vec::new()
+        })
+    }
+
+    async fn count(&self) -> usize {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM todos WHERE deleted_at IS NULL AND NOT archived",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map(|count| count as usize)
+        .unwrap_or_else(|e| {
+            tracing::error!("failed to count todos: {}", e);
+            0
+        })
+    }
+
+    async fn soft_delete(&self, id: i32) -> anyhow::Result<()> {
+        let result = sqlx::query(
+            r#"
+UPDATE todos SET deleted_at = $1, version = version + 1
+WHERE id = $2 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(chrono::Utc::now())
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        Ok(())
+    }
+
+    async fn restore(&self, id: i32) -> anyhow::Result<Todo> {
+        let todo = sqlx::query_as::<_, Todo>(
+            "UPDATE todos SET deleted_at = NULL, version = version + 1 WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .context(RepositoryError::NotFound(id))?;
+
+        Ok(todo)
+    }
+
+    // Ids are not reused afterwards since the SERIAL sequence keeps advancing.
+    async fn clear_all(&self) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM todos").execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn reorder(&self, id: i32, new_position: i32) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let ordered = sqlx::query_scalar::<_, i32>(
+            "SELECT id FROM todos WHERE deleted_at IS NULL ORDER BY position, id",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for (position, id) in reordered(ordered, id, new_position)?
+            .into_iter()
+            .enumerate()
+        {
+            sqlx::query(
+                "UPDATE todos SET position = $1, version = version + 1 WHERE id = $2 AND position <> $1",
+            )
+                .bind(position as i32)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn complete_all(&self) -> anyhow::Result<usize> {
+        let mut tx = self.pool.begin().await?;
+
+        let completed = sqlx::query_as::<_, Todo>(
+            r#"
+UPDATE todos SET completed = TRUE, updated_at = $1, version = version + 1
+WHERE NOT completed AND deleted_at IS NULL AND NOT archived
+RETURNING *
+            "#,
+        )
+        .bind(chrono::Utc::now())
+        .fetch_all(&mut *tx)
+        .await?;
+        for todo in completed.iter() {
+            if let Some(next) = todo.rescheduled() {
+                insert_todo(&mut *tx, &Todo::from_payload(0, next)).await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(completed.len())
+    }
+
+    async fn clear_completed(&self) -> anyhow::Result<usize> {
+        let mut tx = self.pool.begin().await?;
+
+        let ids = sqlx::query_scalar::<_, i32>(
+            "SELECT id FROM todos WHERE completed AND deleted_at IS NULL AND NOT archived ORDER BY id",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut removed = 0;
+        for id in ids {
+            if delete_todo(&mut tx, id, self.on_parent_delete).await? {
+                removed += 1;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(removed)
+    }
+
+    async fn archive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.set_archived(id, true).await
+    }
+
+    async fn unarchive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.set_archived(id, false).await
+    }
+
+    async fn archived(&self) -> Vec<Todo> {
+        sqlx::query_as::<_, Todo>(
+            "SELECT * FROM todos WHERE deleted_at IS NULL AND archived ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("failed to fetch archived todos: {}", e);
+            Vec::new()
+        })
+    }
+
+    async fn log_time(&self, id: i32, minutes: u32) -> anyhow::Result<Todo> {
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+UPDATE todos SET spent_minutes = LEAST(spent_minutes + $1, 4294967295), updated_at = $2,
+    version = version + 1
+WHERE id = $3 AND deleted_at IS NULL
+RETURNING *
+            "#,
+        )
+        .bind(i64::from(minutes))
+        .bind(chrono::Utc::now())
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .context(RepositoryError::NotFound(id))?;
+
+        Ok(todo)
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+
+        Ok(())
+    }
+}
+
+// Returns whether the row existed.
+async fn delete_todo(
+    conn: &mut PgConnection,
+    id: i32,
+    on_parent_delete: OnParentDelete,
+) -> sqlx::Result<bool> {
+    let result = match on_parent_delete {
+        OnParentDelete::Orphan => {
+            sqlx::query(
+                "UPDATE todos SET parent_id = NULL, version = version + 1 WHERE parent_id = $1",
+            )
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query("DELETE FROM todos WHERE id = $1")
+                .bind(id)
+                .execute(&mut *conn)
+                .await?
+        }
+        OnParentDelete::Cascade => {
+            sqlx::query(
+                r#"
+WITH RECURSIVE subtree(id) AS (
+    SELECT id FROM todos WHERE id = $1
+    UNION ALL
+    SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+)
+DELETE FROM todos WHERE id IN (SELECT id FROM subtree)
+                "#,
+            )
+            .bind(id)
+            .execute(&mut *conn)
+            .await?
+        }
+    };
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn insert_todo<'e, E>(executor: E, todo: &Todo) -> sqlx::Result<Todo>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    sqlx::query_as::<_, Todo>(
+        r#"
+INSERT INTO todos (
+    text, completed, created_at, updated_at, due_date, priority, tags, parent_id, recurrence,
+    description, assignee, color, blocked_by, estimate_minutes, attachments, position
+)
+VALUES (
+    $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15,
+    (SELECT COALESCE(MAX(position) + 1, 0) FROM todos WHERE deleted_at IS NULL)
+)
+RETURNING *
+        "#,
+    )
+    .bind(&todo.text)
+    .bind(todo.completed)
+    .bind(todo.created_at)
+    .bind(todo.updated_at)
+    .bind(todo.due_date)
+    .bind(todo.priority)
+    .bind(Json(&todo.tags))
+    .bind(todo.parent_id)
+    .bind(todo.recurrence)
+    .bind(&todo.description)
+    .bind(&todo.assignee)
+    .bind(&todo.color)
+    .bind(Json(&todo.blocked_by))
+    .bind(todo.estimate_minutes.map(i64::from))
+    .bind(Json(&todo.attachments))
+    .fetch_one(executor)
+    .await
+}
+
+// These tests run against a real database and are skipped unless DATABASE_URL
+// is set. Every todo they create is deleted again before they finish.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::{test_utils::run_conformance, Priority};
+
+    async fn repository() -> Option<TodoRepositoryForDb> {
+        let database_url = std::env::var("DATABASE_URL").ok()?;
+        let pool = PgPool::connect(&database_url).await.unwrap();
+        let repository = TodoRepositoryForDb::new(pool);
+        repository.migrate().await.unwrap();
+
+        Some(repository)
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let Some(repository) = repository().await else {
+            return;
+        };
+
+        run_conformance(repository).await;
+    }
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        let Some(repository) = repository().await else {
+            return;
+        };
+
+        let created = repository
+            .create(CreateTodo {
+                priority: Priority::High,
+                tags: vec!["home".to_string(), "home".to_string()],
+                ..CreateTodo::new("todo text".to_string())
+            })
+            .await
+            .unwrap();
+        assert_eq!("todo text", created.text);
+        assert_eq!(vec!["home".to_string()], created.tags);
+
+        let todo = repository.find(created.id).await.unwrap();
+        assert_eq!(created.id, todo.id);
+        assert!(repository
+            .all()
+            .await
+            .iter()
+            .any(|todo| todo.id == created.id));
+
+        let updated = repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: Some("update todo text".to_string()),
+                    completed: Some(true),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!("update todo text", updated.text);
+        assert!(updated.completed);
+        assert_eq!(Priority::High, updated.priority);
+
+        repository.delete(created.id).await.unwrap();
+        assert_eq!(None, repository.find(created.id).await);
+    }
+
+    #[tokio::test]
+    async fn missing_rows_are_not_found() {
+        let Some(repository) = repository().await else {
+            return;
+        };
+
+        let err = repository
+            .update(-1, UpdateTodo::completed(true))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(-1))
+        ));
+
+        let err = repository.delete(-1).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(-1))
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use super::{Page, Priority, Todo, TodoFilter, TodoRepository, TodoStats};
+
+// Hands out the lookups of the wrapped repository and nothing else, e.g. for
+// a reporting task that must not change todos. It does not implement
+// `TodoRepository`, so there is no way to reach `create`, `update` or
+// `delete` through it.
+#[derive(Debug, Clone)]
+pub struct ReadOnlyRepository<R> {
+    inner: R,
+}
+
+impl<R: TodoRepository> ReadOnlyRepository<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    pub async fn find(&self, id: i32) -> Option<Todo> {
+        self.inner.find(id).await
+    }
+
+    pub async fn exists(&self, id: i32) -> bool {
+        self.inner.exists(id).await
+    }
+
+    pub async fn find_many(&self, ids: &[i32]) -> Vec<Todo> {
+        self.inner.find_many(ids).await
+    }
+
+    pub async fn all(&self) -> Vec<Todo> {
+        self.inner.all().await
+    }
+
+    pub async fn count(&self) -> usize {
+        self.inner.count().await
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.inner.is_empty().await
+    }
+
+    pub async fn max_id(&self) -> Option<i32> {
+        self.inner.max_id().await
+    }
+
+    pub async fn count_completed(&self) -> usize {
+        self.inner.count_completed().await
+    }
+
+    pub async fn page(&self, offset: usize, limit: usize) -> Page<Todo> {
+        self.inner.page(offset, limit).await
+    }
+
+    pub async fn page_after(&self, after_id: Option<i32>, limit: usize) -> Vec<Todo> {
+        self.inner.page_after(after_id, limit).await
+    }
+
+    pub async fn query(&self, filter: TodoFilter) -> Vec<Todo> {
+        self.inner.query(filter).await
+    }
+
+    pub async fn query_str(&self, query: &str) -> anyhow::Result<Vec<Todo>> {
+        self.inner.query_str(query).await
+    }
+
+    pub async fn search(&self, query: &str) -> Vec<Todo> {
+        self.inner.search(query).await
+    }
+
+    pub async fn find_by_completed(&self, completed: bool) -> Vec<Todo> {
+        self.inner.find_by_completed(completed).await
+    }
+
+    pub async fn find_by_tag(&self, tag: &str) -> Vec<Todo> {
+        self.inner.find_by_tag(tag).await
+    }
+
+    pub async fn find_by_priority(&self, priority: Priority) -> Vec<Todo> {
+        self.inner.find_by_priority(priority).await
+    }
+
+    pub async fn find_by_assignee(&self, assignee: &str) -> Vec<Todo> {
+        self.inner.find_by_assignee(assignee).await
+    }
+
+    pub async fn overdue(&self, now: DateTime<Utc>) -> Vec<Todo> {
+        self.inner.overdue(now).await
+    }
+
+    pub async fn due_within(&self, now: DateTime<Utc>, window: chrono::Duration) -> Vec<Todo> {
+        self.inner.due_within(now, window).await
+    }
+
+    pub async fn stale(&self, now: DateTime<Utc>, older_than: chrono::Duration) -> Vec<Todo> {
+        self.inner.stale(now, older_than).await
+    }
+
+    pub async fn agenda(&self, now: DateTime<Utc>) -> Vec<Todo> {
+        self.inner.agenda(now).await
+    }
+
+    pub async fn ready(&self) -> Vec<Todo> {
+        self.inner.ready().await
+    }
+
+    pub async fn children(&self, parent_id: i32) -> Vec<Todo> {
+        self.inner.children(parent_id).await
+    }
+
+    pub async fn archived(&self) -> Vec<Todo> {
+        self.inner.archived().await
+    }
+
+    pub async fn stats(&self) -> TodoStats {
+        self.inner.stats().await
+    }
+
+    pub async fn tag_counts(&self) -> HashMap<String, usize> {
+        self.inner.tag_counts().await
+    }
+
+    pub async fn all_tags(&self) -> Vec<String> {
+        self.inner.all_tags().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::{CreateTodo, TodoRepositoryForMemory, UpdateTodo};
+
+    #[tokio::test]
+    async fn lookups_go_to_the_inner_repository() {
+        let repository = TodoRepositoryForMemory::new();
+        let read_only = ReadOnlyRepository::new(repository.clone());
+        assert!(read_only.is_empty().await);
+
+        let payload = CreateTodo::builder()
+            .text("buy milk")
+            .tag("home")
+            .build()
+            .unwrap();
+        let milk = repository.create(payload).await.unwrap();
+        let bread = repository.create("buy bread".into()).await.unwrap();
+        let bread = repository
+            .update(bread.id, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+
+        assert_eq!(Some(milk.clone()), read_only.find(milk.id).await);
+        assert!(read_only.exists(bread.id).await);
+        assert_eq!(None, read_only.find(3).await);
+        assert_eq!(2, read_only.count().await);
+        assert_eq!(1, read_only.count_completed().await);
+        assert_eq!(repository.stats().await, read_only.stats().await);
+        assert_eq!(vec![milk.clone()], read_only.find_by_tag("home").await);
+        assert_eq!(vec![bread.clone()], read_only.find_by_completed(true).await);
+        assert_eq!(vec![bread], read_only.search("bread").await);
+        assert_eq!(
+            vec![milk],
+            read_only.query_str("completed:false").await.unwrap()
+        );
+    }
+}
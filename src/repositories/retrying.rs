@@ -0,0 +1,415 @@
+use async_trait::async_trait;
+use std::{collections::HashMap, future::Future, time::Duration};
+
+use super::{
+    BulkDeleteReport, CompleteOutcome, CreateTodo, RepositoryError, SerializationFormat, Todo,
+    TodoFilter, TodoRepository, TodoStats, UpdateTodo,
+};
+
+const DEFAULT_ATTEMPTS: usize = 3;
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(50);
+
+// Retries `create`, `update` and `delete` while the inner repository reports
+// `StorageUnavailable`, waiting `backoff` before the first retry and twice as
+// long before each one after that. Any other error is returned straight
+// away. Everything else goes to the inner repository as is.
+#[derive(Debug, Clone)]
+pub struct RetryingRepository<R> {
+    inner: R,
+    attempts: usize,
+    backoff: Duration,
+}
+
+impl<R: TodoRepository> RetryingRepository<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            attempts: DEFAULT_ATTEMPTS,
+            backoff: DEFAULT_BACKOFF,
+        }
+    }
+
+    // How many times an operation is tried in all, including the first.
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    async fn retried<T, F, Fut>(&self, mut operation: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(e) if attempt < self.attempts && is_unavailable(&e) => {
+                    tracing::warn!("storage unavailable, retrying in {:?}", backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn is_unavailable(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<RepositoryError>(),
+        Some(RepositoryError::StorageUnavailable)
+    )
+}
+
+#[async_trait]
+impl<R: TodoRepository> TodoRepository for RetryingRepository<R> {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        self.retried(|| self.inner.create(payload.clone())).await
+    }
+
+    async fn find_or_create(&self, text: &str) -> anyhow::Result<Todo> {
+        self.inner.find_or_create(text).await
+    }
+
+    async fn create_unique(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        self.inner.create_unique(payload).await
+    }
+
+    async fn find(&self, id: i32) -> Option<Todo> {
+        self.inner.find(id).await
+    }
+
+    async fn exists(&self, id: i32) -> bool {
+        self.inner.exists(id).await
+    }
+
+    async fn all(&self) -> Vec<Todo> {
+        self.inner.all().await
+    }
+
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        self.retried(|| self.inner.update(id, payload.clone()))
+            .await
+    }
+
+    async fn update_if_version(
+        &self,
+        id: i32,
+        expected_version: u32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo> {
+        self.inner
+            .update_if_version(id, expected_version, payload)
+            .await
+    }
+
+    async fn replace(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<(Todo, Todo)> {
+        self.inner.replace(id, payload).await
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        self.retried(|| self.inner.delete(id)).await
+    }
+
+    async fn count(&self) -> usize {
+        self.inner.count().await
+    }
+
+    async fn count_completed(&self) -> usize {
+        self.inner.count_completed().await
+    }
+
+    async fn bulk_create(&self, payloads: Vec<CreateTodo>) -> anyhow::Result<Vec<Todo>> {
+        self.inner.bulk_create(payloads).await
+    }
+
+    async fn bulk_delete(&self, ids: Vec<i32>) -> anyhow::Result<BulkDeleteReport> {
+        self.inner.bulk_delete(ids).await
+    }
+
+    async fn toggle_completed(&self, id: i32) -> anyhow::Result<Todo> {
+        self.inner.toggle_completed(id).await
+    }
+
+    async fn complete(&self, id: i32) -> anyhow::Result<CompleteOutcome> {
+        self.inner.complete(id).await
+    }
+
+    async fn merge(&self, keep_id: i32, merge_id: i32) -> anyhow::Result<Todo> {
+        self.inner.merge(keep_id, merge_id).await
+    }
+
+    async fn soft_delete(&self, id: i32) -> anyhow::Result<()> {
+        self.inner.soft_delete(id).await
+    }
+
+    async fn restore(&self, id: i32) -> anyhow::Result<Todo> {
+        self.inner.restore(id).await
+    }
+
+    async fn find_many(&self, ids: &[i32]) -> Vec<Todo> {
+        self.inner.find_many(ids).await
+    }
+
+    async fn clear_all(&self) -> anyhow::Result<()> {
+        self.inner.clear_all().await
+    }
+
+    async fn reorder(&self, id: i32, new_position: i32) -> anyhow::Result<()> {
+        self.inner.reorder(id, new_position).await
+    }
+
+    async fn stats(&self) -> TodoStats {
+        self.inner.stats().await
+    }
+
+    async fn tag_counts(&self) -> HashMap<String, usize> {
+        self.inner.tag_counts().await
+    }
+
+    async fn complete_all(&self) -> anyhow::Result<usize> {
+        self.inner.complete_all().await
+    }
+
+    async fn complete_where(&self, filter: TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        self.inner.complete_where(filter).await
+    }
+
+    async fn update_where(
+        &self,
+        filter: TodoFilter,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Vec<Todo>> {
+        self.inner.update_where(filter, payload).await
+    }
+
+    async fn clear_completed(&self) -> anyhow::Result<usize> {
+        self.inner.clear_completed().await
+    }
+
+    async fn archive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.inner.archive(id).await
+    }
+
+    async fn unarchive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.inner.unarchive(id).await
+    }
+
+    async fn archived(&self) -> Vec<Todo> {
+        self.inner.archived().await
+    }
+
+    async fn log_time(&self, id: i32, minutes: u32) -> anyhow::Result<Todo> {
+        self.inner.log_time(id, minutes).await
+    }
+
+    async fn import_from(&self, format: SerializationFormat, data: &[u8]) -> anyhow::Result<usize> {
+        self.inner.import_from(format, data).await
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        self.inner.health_check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::{test_utils::run_conformance, TodoRepositoryForMemory};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    // `create`, `update` and `delete` report the storage as unavailable until
+    // `failures` runs out, then reach the wrapped repository.
+    #[derive(Debug, Clone)]
+    struct FlakyRepository {
+        inner: TodoRepositoryForMemory,
+        failures: Arc<AtomicUsize>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl FlakyRepository {
+        fn failing(failures: usize) -> Self {
+            Self {
+                inner: TodoRepositoryForMemory::new(),
+                failures: Arc::new(AtomicUsize::new(failures)),
+                calls: Arc::default(),
+            }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+
+        fn attempt(&self) -> anyhow::Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let failing = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            if failing {
+                return Err(RepositoryError::StorageUnavailable.into());
+            }
+
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl TodoRepository for FlakyRepository {
+        async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+            self.attempt()?;
+            self.inner.create(payload).await
+        }
+
+        async fn find_or_create(&self, text: &str) -> anyhow::Result<Todo> {
+            self.inner.find_or_create(text).await
+        }
+
+        async fn create_unique(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+            self.inner.create_unique(payload).await
+        }
+
+        async fn find(&self, id: i32) -> Option<Todo> {
+            self.inner.find(id).await
+        }
+
+        async fn all(&self) -> Vec<Todo> {
+            self.inner.all().await
+        }
+
+        async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+            self.attempt()?;
+            self.inner.update(id, payload).await
+        }
+
+        async fn update_if_version(
+            &self,
+            id: i32,
+            expected_version: u32,
+            payload: UpdateTodo,
+        ) -> anyhow::Result<Todo> {
+            self.inner
+                .update_if_version(id, expected_version, payload)
+                .await
+        }
+
+        async fn replace(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<(Todo, Todo)> {
+            self.inner.replace(id, payload).await
+        }
+
+        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+            self.attempt()?;
+            self.inner.delete(id).await
+        }
+
+        async fn soft_delete(&self, id: i32) -> anyhow::Result<()> {
+            self.inner.soft_delete(id).await
+        }
+
+        async fn restore(&self, id: i32) -> anyhow::Result<Todo> {
+            self.inner.restore(id).await
+        }
+
+        async fn clear_all(&self) -> anyhow::Result<()> {
+            self.inner.clear_all().await
+        }
+
+        async fn reorder(&self, id: i32, new_position: i32) -> anyhow::Result<()> {
+            self.inner.reorder(id, new_position).await
+        }
+
+        async fn complete_all(&self) -> anyhow::Result<usize> {
+            self.inner.complete_all().await
+        }
+
+        async fn clear_completed(&self) -> anyhow::Result<usize> {
+            self.inner.clear_completed().await
+        }
+
+        async fn archive(&self, id: i32) -> anyhow::Result<Todo> {
+            self.inner.archive(id).await
+        }
+
+        async fn unarchive(&self, id: i32) -> anyhow::Result<Todo> {
+            self.inner.unarchive(id).await
+        }
+
+        async fn archived(&self) -> Vec<Todo> {
+            self.inner.archived().await
+        }
+
+        async fn log_time(&self, id: i32, minutes: u32) -> anyhow::Result<Todo> {
+            self.inner.log_time(id, minutes).await
+        }
+    }
+
+    fn retrying(flaky: &FlakyRepository) -> RetryingRepository<FlakyRepository> {
+        RetryingRepository::new(flaky.clone()).with_backoff(Duration::from_millis(1))
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        run_conformance(RetryingRepository::new(TodoRepositoryForMemory::new())).await;
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_until_they_pass() {
+        let flaky = FlakyRepository::failing(2);
+        let repository = retrying(&flaky);
+
+        let todo = repository.create("buy milk".into()).await.unwrap();
+        assert_eq!(3, flaky.calls());
+        assert_eq!(Some(todo), repository.find(1).await);
+
+        flaky.failures.store(2, Ordering::SeqCst);
+        let todo = repository
+            .update(1, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+        assert!(todo.completed);
+
+        flaky.failures.store(2, Ordering::SeqCst);
+        repository.delete(1).await.unwrap();
+        assert_eq!(9, flaky.calls());
+        assert_eq!(None, repository.find(1).await);
+    }
+
+    #[tokio::test]
+    async fn retries_give_up_after_the_last_attempt() {
+        let flaky = FlakyRepository::failing(5);
+        let repository = retrying(&flaky).with_attempts(3);
+
+        let err = repository.create("buy milk".into()).await.unwrap_err();
+        assert!(is_unavailable(&err));
+        assert_eq!(3, flaky.calls());
+        assert!(repository.all().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_not_retried() {
+        let flaky = FlakyRepository::failing(0);
+        let repository = retrying(&flaky);
+
+        let err = repository.create(" ".into()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+        let err = repository.delete(7).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(7))
+        ));
+        assert_eq!(2, flaky.calls());
+    }
+}
@@ -0,0 +1,1100 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::{types::Json, Sqlite, SqliteConnection, SqlitePool};
+
+use super::{
+    reordered, CreateTodo, OnParentDelete, OverlongText, RepositoryError, TextLimit, Todo,
+    TodoRepository, UpdateTodo,
+};
+
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForSqlite {
+    pool: SqlitePool,
+    on_parent_delete: OnParentDelete,
+    text_limit: TextLimit,
+}
+
+impl TodoRepositoryForSqlite {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self {
+            pool,
+            on_parent_delete: OnParentDelete::default(),
+            text_limit: TextLimit::default(),
+        }
+    }
+
+    pub fn with_on_parent_delete(mut self, on_parent_delete: OnParentDelete) -> Self {
+        self.on_parent_delete = on_parent_delete;
+        self
+    }
+
+    // Defaults to 10,000 characters, rejecting anything longer.
+    pub fn with_max_text_len(mut self, max_text_len: usize, overlong: OverlongText) -> Self {
+        self.text_limit = TextLimit {
+            max_len: max_text_len,
+            overlong,
+        };
+        self
+    }
+
+    async fn set_archived(&self, id: i32, archived: bool) -> anyhow::Result<Todo> {
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+UPDATE todos SET archived = ?, updated_at = ?, version = version + 1
+WHERE id = ? AND deleted_at IS NULL
+RETURNING *
+            "#,
+        )
+        .bind(archived)
+        .bind(chrono::Utc::now())
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .context(RepositoryError::NotFound(id))?;
+
+        Ok(todo)
+    }
+
+    async fn update_checked(
+        &self,
+        id: i32,
+        expected_version: Option<u32>,
+        mut payload: UpdateTodo,
+    ) -> anyhow::Result<(Todo, Todo)> {
+        payload.validate(id)?;
+        if let Some(text) = payload.text.as_mut() {
+            self.text_limit.apply(text)?;
+        }
+        if let Some(blocked_by) = &payload.blocked_by {
+            self.check_blockers(blocked_by).await?;
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        let before =
+            sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE id = ? AND deleted_at IS NULL")
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .context(RepositoryError::NotFound(id))?;
+        if expected_version.is_some_and(|version| version != before.version) {
+            return Err(RepositoryError::Conflict(id).into());
+        }
+        let todo = before.updated(payload);
+        sqlx::query(
+            r#"
+UPDATE todos
+SET text = ?, completed = ?, updated_at = ?, due_date = ?, priority = ?, tags = ?,
+    description = ?, assignee = ?, color = ?, blocked_by = ?, estimate_minutes = ?,
+    attachments = ?, version = ?
+WHERE id = ?
+            "#,
+        )
+        .bind(&todo.text)
+        .bind(todo.completed)
+        .bind(todo.updated_at)
+        .bind(todo.due_date)
+        .bind(todo.priority)
+        .bind(Json(&todo.tags))
+        .bind(&todo.description)
+        .bind(&todo.assignee)
+        .bind(&todo.color)
+        .bind(Json(&todo.blocked_by))
+        .bind(todo.estimate_minutes.map(i64::from))
+        .bind(Json(&todo.attachments))
+        .bind(todo.version as i64)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        if let Some(next) = before.next_occurrence(&todo) {
+            insert_todo(&mut *tx, &Todo::from_payload(0, next)).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok((before, todo))
+    }
+
+    async fn check_blockers(&self, blocked_by: &[i32]) -> anyhow::Result<()> {
+        for id in blocked_by.iter().copied() {
+            self.find(id).await.context(RepositoryError::NotFound(id))?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn migrate(&self) -> anyhow::Result<()> {
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TodoRepository for TodoRepositoryForSqlite {
+    async fn create(&self, mut payload: CreateTodo) -> anyhow::Result<Todo> {
+        payload.validate()?;
+        payload.reject_client_id()?;
+        self.text_limit.apply(&mut payload.text)?;
+        if let Some(parent_id) = payload.parent_id {
+            self.find(parent_id)
+                .await
+                .context(RepositoryError::NotFound(parent_id))?;
+        }
+        self.check_blockers(&payload.blocked_by).await?;
+
+        // The id is assigned by the database.
+        let todo = Todo::from_payload(0, payload);
+        let todo = insert_todo(&self.pool, &todo).await?;
+
+        Ok(todo)
+    }
+
+    async fn find_or_create(&self, text: &str) -> anyhow::Result<Todo> {
+        let mut payload = CreateTodo::new(text.to_string());
+        payload.validate()?;
+        self.text_limit.apply(&mut payload.text)?;
+
+        let mut tx = self.pool.begin().await?;
+
+        let existing = sqlx::query_as::<_, Todo>(
+            "SELECT * FROM todos WHERE text = ? AND deleted_at IS NULL ORDER BY id LIMIT 1",
+        )
+        .bind(&payload.text)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let todo = match existing {
+            Some(todo) => todo,
+            None => insert_todo(&mut *tx, &Todo::from_payload(0, payload)).await?,
+        };
+
+        tx.commit().await?;
+
+        Ok(todo)
+    }
+
+    async fn create_unique(&self, mut payload: CreateTodo) -> anyhow::Result<Todo> {
+        payload.validate()?;
+        payload.reject_client_id()?;
+        self.text_limit.apply(&mut payload.text)?;
+        if let Some(parent_id) = payload.parent_id {
+            self.find(parent_id)
+                .await
+                .context(RepositoryError::NotFound(parent_id))?;
+        }
+        self.check_blockers(&payload.blocked_by).await?;
+
+        let mut tx = self.pool.begin().await?;
+
+        let duplicate = sqlx::query_scalar::<_, i32>(
+            "SELECT id FROM todos WHERE lower(text) = lower(?) AND deleted_at IS NULL ORDER BY id LIMIT 1",
+        )
+        .bind(&payload.text)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(id) = duplicate {
+            return Err(RepositoryError::Conflict(id).into());
+        }
+        let todo = insert_todo(&mut *tx, &Todo::from_payload(0, payload)).await?;
+
+        tx.commit().await?;
+
+        Ok(todo)
+    }
+
+    async fn find(&self, id: i32) -> Option<Todo> {
+        sqlx::query_as::<_, Todo>("SELECT * FROM todos WHERE id = ? AND deleted_at IS NULL")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("failed to find todo {}: {}", id, e);
+                None
+            })
+    }
+
+    async fn exists(&self, id: i32) -> bool {
+        sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM todos WHERE id = ? AND deleted_at IS NULL)",
+        )
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("failed to check todo {}: {}", id, e);
+            false
+        })
+    }
+
+    async fn all(&self) -> Vec<Todo> {
+        sqlx::query_as::<_, Todo>(
+            "SELECT * FROM todos WHERE deleted_at IS NULL AND NOT archived ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("failed to fetch todos: {}", e);
+            Vec::new()
+        })
+    }
+
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        let (_, todo) = self.update_checked(id, None, payload).await?;
+
+        Ok(todo)
+    }
+
+    async fn update_if_version(
+        &self,
+        id: i32,
+        expected_version: u32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo> {
+        let (_, todo) = self
+            .update_checked(id, Some(expected_version), payload)
+            .await?;
+
+        Ok(todo)
+    }
+
+    async fn replace(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<(Todo, Todo)> {
+        self.update_checked(id, None, payload).await
+    }
+
+    // Removes the row for good, whether or not it was soft-deleted. Its
+    // children are orphaned or deleted depending on `on_parent_delete`.
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        if !delete_todo(&mut tx, id, self.on_parent_delete).await? {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn all_paginated(&self, offset: usize, limit: usize) -> Vec<Todo> {
+        sqlx::query_as::<_, Todo>(
+            "SELECT * FROM todos WHERE deleted_at IS NULL AND NOT archived ORDER BY id LIMIT ? OFFSET ?",
+        )
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("failed to fetch todos: {}", e);
+            Vec::new()
+        })
+    }
+
+    async fn count(&self) -> usize {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM todos WHERE deleted_at IS NULL AND NOT archived",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map(|count| count as usize)
+        .unwrap_or_else(|e| {
+            tracing::error!("failed to count todos: {}", e);
+            0
+        })
+    }
+
+    async fn soft_delete(&self, id: i32) -> anyhow::Result<()> {
+        let result = sqlx::query(
+            r#"
+UPDATE todos SET deleted_at = ?, version = version + 1
+WHERE id = ? AND deleted_at IS NULL
+            "#,
+        )
+        .bind(chrono::Utc::now())
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        Ok(())
+    }
+
+    async fn restore(&self, id: i32) -> anyhow::Result<Todo> {
+        let todo = sqlx::query_as::<_, Todo>(
+            "UPDATE todos SET deleted_at = NULL, version = version + 1 WHERE id = ? RETURNING *",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .context(RepositoryError::NotFound(id))?;
+
+        Ok(todo)
+    }
+
+    // Ids are not reused afterwards since AUTOINCREMENT keeps advancing.
+    async fn clear_all(&self) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM todos").execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn reorder(&self, id: i32, new_position: i32) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let ordered = sqlx::query_scalar::<_, i32>(
+            "SELECT id FROM todos WHERE deleted_at IS NULL ORDER BY position, id",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for (position, id) in reordered(ordered, id, new_position)?
+            .into_iter()
+            .enumerate()
+        {
+            sqlx::query(
+                "UPDATE todos SET position = ?, version = version + 1 WHERE id = ? AND position <> ?",
+            )
+            .bind(position as i32)
+            .bind(id)
+            .bind(position as i32)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn complete_all(&self) -> anyhow::Result<usize> {
+        let mut tx = self.pool.begin().await?;
+
+        let completed = sqlx::query_as::<_, Todo>(
+            r#"
+UPDATE todos SET completed = TRUE, updated_at = ?, version = version + 1
+WHERE NOT completed AND deleted_at IS NULL AND NOT archived
+RETURNING *
+            "#,
+        )
+        .bind(chrono::Utc::now())
+        .fetch_all(&mut *tx)
+        .await?;
+        for todo in completed.iter() {
+            if let Some(next) = todo.rescheduled() {
+                insert_todo(&mut *tx, &Todo::from_payload(0, next)).await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(completed.len())
+    }
+
+    async fn clear_completed(&self) -> anyhow::Result<usize> {
+        let mut tx = self.pool.begin().await?;
+
+        let ids = sqlx::query_scalar::<_, i32>(
+            "SELECT id FROM todos WHERE completed AND deleted_at IS NULL AND NOT archived ORDER BY id",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut removed = 0;
+        for id in ids {
+            if delete_todo(&mut tx, id, self.on_parent_delete).await? {
+                removed += 1;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(removed)
+    }
+
+    async fn archive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.set_archived(id, true).await
+    }
+
+    async fn unarchive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.set_archived(id, false).await
+    }
+
+    async fn archived(&self) -> Vec<Todo> {
+        sqlx::query_as::<_, Todo>(
+            "SELECT * FROM todos WHERE deleted_at IS NULL AND archived ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("failed to fetch archived todos: {}", e);
+            Vec::new()
+        })
+    }
+
+    async fn log_time(&self, id: i32, minutes: u32) -> anyhow::Result<Todo> {
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+UPDATE todos SET spent_minutes = MIN(spent_minutes + ?, 4294967295), updated_at = ?,
+    version = version + 1
+WHERE id = ? AND deleted_at IS NULL
+RETURNING *
+            "#,
+        )
+        .bind(i64::from(minutes))
+        .bind(chrono::Utc::now())
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .context(RepositoryError::NotFound(id))?;
+
+        Ok(todo)
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+
+        Ok(())
+    }
+}
+
+// Returns whether the row existed.
+async fn delete_todo(
+    conn: &mut SqliteConnection,
+    id: i32,
+    on_parent_delete: OnParentDelete,
+) -> sqlx::Result<bool> {
+    let result = match on_parent_delete {
+        OnParentDelete::Orphan => {
+            sqlx::query(
+                "UPDATE todos SET parent_id = NULL, version = version + 1 WHERE parent_id = ?",
+            )
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query("DELETE FROM todos WHERE id = ?")
+                .bind(id)
+                .execute(&mut *conn)
+                .await?
+        }
+        OnParentDelete::Cascade => {
+            sqlx::query(
+                r#"
+WITH RECURSIVE subtree(id) AS (
+    SELECT id FROM todos WHERE id = ?
+    UNION ALL
+    SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+)
+DELETE FROM todos WHERE id IN (SELECT id FROM subtree)
+                "#,
+            )
+            .bind(id)
+            .execute(&mut *conn)
+            .await?
+        }
+    };
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn insert_todo<'e, E>(executor: E, todo: &Todo) -> sqlx::Result<Todo>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    sqlx::query_as::<_, Todo>(
+        r#"
+INSERT INTO todos (
+    text, completed, created_at, updated_at, due_date, priority, tags, parent_id, recurrence,
+    description, assignee, color, blocked_by, estimate_minutes, attachments, position
+)
+VALUES (
+    ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+    (SELECT COALESCE(MAX(position) + 1, 0) FROM todos WHERE deleted_at IS NULL)
+)
+RETURNING *
+        "#,
+    )
+    .bind(&todo.text)
+    .bind(todo.completed)
+    .bind(todo.created_at)
+    .bind(todo.updated_at)
+    .bind(todo.due_date)
+    .bind(todo.priority)
+    .bind(Json(&todo.tags))
+    .bind(todo.parent_id)
+    .bind(todo.recurrence)
+    .bind(&todo.description)
+    .bind(&todo.assignee)
+    .bind(&todo.color)
+    .bind(Json(&todo.blocked_by))
+    .bind(todo.estimate_minutes.map(i64::from))
+    .bind(Json(&todo.attachments))
+    .fetch_one(executor)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::{
+        test_utils::run_conformance, Attachment, Priority, Recurrence, TodoFilter,
+    };
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn repository() -> TodoRepositoryForSqlite {
+        // Every connection to sqlite::memory: opens its own database, so the
+        // pool must hold on to exactly one.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let repository = TodoRepositoryForSqlite::new(pool);
+        repository.migrate().await.unwrap();
+
+        repository
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        run_conformance(repository().await).await;
+    }
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        let repository = repository().await;
+
+        let created = repository
+            .create(CreateTodo {
+                priority: Priority::High,
+                tags: vec!["home".to_string(), "home".to_string()],
+                ..CreateTodo::new("todo text".to_string())
+            })
+            .await
+            .unwrap();
+        assert_eq!(1, created.id);
+        assert_eq!("todo text", created.text);
+        assert_eq!(vec!["home".to_string()], created.tags);
+
+        let todo = repository.find(created.id).await.unwrap();
+        assert_eq!(created, todo);
+
+        let todos = repository.all().await;
+        assert_eq!(vec![created.clone()], todos);
+
+        let updated = repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: Some("update todo text".to_string()),
+                    completed: Some(true),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!("update todo text", updated.text);
+        assert!(updated.completed);
+        assert_eq!(Priority::High, updated.priority);
+        assert_eq!(created.created_at, updated.created_at);
+        assert_eq!(Some(updated.clone()), repository.find(created.id).await);
+
+        repository.delete(created.id).await.unwrap();
+        assert_eq!(None, repository.find(created.id).await);
+        assert_eq!(0, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn missing_rows_are_not_found() {
+        let repository = repository().await;
+
+        let err = repository
+            .update(1, UpdateTodo::completed(true))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(1))
+        ));
+
+        let err = repository.delete(1).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn all_paginated_uses_limit_and_offset() {
+        let repository = repository().await;
+        for i in 1..=5 {
+            repository
+                .create(CreateTodo::new(format!("todo {}", i)))
+                .await
+                .unwrap();
+        }
+
+        let ids: Vec<i32> = repository
+            .all_paginated(1, 2)
+            .await
+            .iter()
+            .map(|todo| todo.id)
+            .collect();
+        assert_eq!(vec![2, 3], ids);
+        assert!(repository.all_paginated(5, 2).await.is_empty());
+        assert_eq!(5, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn soft_deleted_rows_are_hidden_until_restored() {
+        let repository = repository().await;
+        let todo = repository
+            .create(CreateTodo::new("todo text".to_string()))
+            .await
+            .unwrap();
+
+        repository.soft_delete(todo.id).await.unwrap();
+        assert_eq!(None, repository.find(todo.id).await);
+        assert!(repository.all().await.is_empty());
+        assert_eq!(0, repository.count().await);
+
+        let restored = repository.restore(todo.id).await.unwrap();
+        let todo = Todo {
+            version: todo.version + 2,
+            ..todo
+        };
+        assert_eq!(todo, restored);
+        assert_eq!(Some(todo), repository.find(restored.id).await);
+    }
+
+    #[tokio::test]
+    async fn clear_all_removes_every_row() {
+        let repository = repository().await;
+        for i in 1..=3 {
+            repository
+                .create(CreateTodo::new(format!("todo {}", i)))
+                .await
+                .unwrap();
+        }
+        repository.soft_delete(3).await.unwrap();
+
+        repository.clear_all().await.unwrap();
+        assert_eq!(0, repository.count().await);
+        assert!(repository.restore(3).await.is_err());
+
+        let todo = repository
+            .create(CreateTodo::new("after clear".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(4, todo.id);
+    }
+
+    #[tokio::test]
+    async fn reorder_keeps_positions_contiguous() {
+        let repository = repository().await;
+        for i in 1..=4 {
+            repository
+                .create(CreateTodo::new(format!("todo {}", i)))
+                .await
+                .unwrap();
+        }
+
+        repository.reorder(4, 0).await.unwrap();
+        repository.reorder(3, 1).await.unwrap();
+        let positions: Vec<(i32, i32)> = repository
+            .all_by_position()
+            .await
+            .iter()
+            .map(|todo| (todo.id, todo.position))
+            .collect();
+        assert_eq!(vec![(4, 0), (3, 1), (1, 2), (2, 3)], positions);
+        assert!(repository.reorder(5, 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn deleting_a_parent_orphans_or_cascades() {
+        let repository = repository().await;
+        let create_child = |parent_id| CreateTodo {
+            parent_id: Some(parent_id),
+            ..CreateTodo::new(format!("child of {}", parent_id))
+        };
+        repository
+            .create(CreateTodo::new("parent".to_string()))
+            .await
+            .unwrap();
+        repository.create(create_child(1)).await.unwrap();
+        assert!(repository.create(create_child(9)).await.is_err());
+
+        repository.delete(1).await.unwrap();
+        assert_eq!(None, repository.find(2).await.unwrap().parent_id);
+
+        let repository = repository.with_on_parent_delete(OnParentDelete::Cascade);
+        repository.create(create_child(2)).await.unwrap();
+        repository.create(create_child(3)).await.unwrap();
+        repository.delete(2).await.unwrap();
+        assert_eq!(0, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn update_if_version_detects_conflicts() {
+        let repository = repository().await;
+        let todo = repository
+            .create(CreateTodo::new("todo text".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(1, todo.version);
+
+        let updated = repository
+            .update_if_version(todo.id, todo.version, UpdateTodo::completed(true))
+            .await
+            .unwrap();
+        assert_eq!(2, updated.version);
+        assert_eq!(Some(updated), repository.find(todo.id).await);
+
+        let err = repository
+            .update_if_version(todo.id, todo.version, UpdateTodo::completed(false))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn find_or_create_does_not_duplicate() {
+        let repository = repository().await;
+
+        let first = repository.find_or_create("buy milk").await.unwrap();
+        let second = repository.find_or_create("buy milk").await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(1, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn archived_rows_are_left_out_of_all() {
+        let repository = repository().await;
+        for i in 1..=2 {
+            repository
+                .create(CreateTodo::new(format!("todo {}", i)))
+                .await
+                .unwrap();
+        }
+
+        let archived = repository.archive(1).await.unwrap();
+        assert!(archived.archived);
+        assert_eq!(
+            vec![2],
+            repository
+                .all()
+                .await
+                .iter()
+                .map(|todo| todo.id)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![archived], repository.archived().await);
+        assert_eq!(1, repository.count().await);
+
+        repository.unarchive(1).await.unwrap();
+        assert_eq!(2, repository.all().await.len());
+        assert!(repository.archived().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn exists_ignores_deleted_rows() {
+        let repository = repository().await;
+        let todo = repository.create("buy milk".into()).await.unwrap();
+
+        assert!(repository.exists(todo.id).await);
+        repository.soft_delete(todo.id).await.unwrap();
+        assert!(!repository.exists(todo.id).await);
+        assert!(!repository.exists(2).await);
+    }
+
+    #[tokio::test]
+    async fn completing_a_weekly_todo_schedules_the_next_one() {
+        let repository = repository().await;
+        let due_date = chrono::Utc::now();
+        let todo = repository
+            .create(
+                CreateTodo::builder()
+                    .text("take out the bins")
+                    .due_date(due_date)
+                    .recurrence(Recurrence::Weekly)
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        repository.toggle_completed(todo.id).await.unwrap();
+        let next = repository.find(2).await.unwrap();
+        assert!(!next.completed);
+        assert_eq!(Some(Recurrence::Weekly), next.recurrence);
+        assert_eq!(Some(due_date + chrono::Duration::weeks(1)), next.due_date);
+    }
+
+    #[tokio::test]
+    async fn create_unique_rejects_case_insensitive_duplicates() {
+        let repository = repository().await;
+        repository.create_unique("Buy Milk".into()).await.unwrap();
+
+        let err = repository
+            .create_unique("buy milk".into())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Conflict(1))
+        ));
+        assert_eq!(
+            2,
+            repository
+                .create_unique("buy bread".into())
+                .await
+                .unwrap()
+                .id
+        );
+    }
+
+    #[tokio::test]
+    async fn complete_all_then_clear_completed() {
+        let repository = repository().await;
+        for i in 1..=3 {
+            repository
+                .create(CreateTodo::new(format!("todo {}", i)))
+                .await
+                .unwrap();
+        }
+        repository.toggle_completed(2).await.unwrap();
+
+        assert_eq!(2, repository.complete_all().await.unwrap());
+        assert_eq!(3, repository.count_completed().await);
+        assert_eq!(3, repository.clear_completed().await.unwrap());
+        assert_eq!(0, repository.count().await);
+    }
+
+    #[tokio::test]
+    async fn overlong_text_is_truncated_on_create_and_update() {
+        let repository = repository()
+            .await
+            .with_max_text_len(4, OverlongText::Truncate);
+
+        let todo = repository.create("abcdef".into()).await.unwrap();
+        assert_eq!("abcd", todo.text);
+
+        let todo = repository
+            .update(
+                todo.id,
+                UpdateTodo {
+                    text: Some("ghijkl".to_string()),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!("ghij", todo.text);
+        assert_eq!(Some(todo.clone()), repository.find(todo.id).await);
+    }
+
+    #[tokio::test]
+    async fn blocked_by_is_stored_and_drives_ready() {
+        let repository = repository().await;
+        let blocker = repository.create("first".into()).await.unwrap();
+        let payload = CreateTodo::builder()
+            .text("second")
+            .blocked_by(blocker.id)
+            .build()
+            .unwrap();
+        let blocked = repository.create(payload).await.unwrap();
+        assert_eq!(vec![blocker.id], blocked.blocked_by);
+        assert_eq!(Some(blocked.clone()), repository.find(blocked.id).await);
+
+        assert_eq!(vec![blocker.clone()], repository.ready().await);
+        repository.toggle_completed(blocker.id).await.unwrap();
+        let ready = repository.ready().await;
+        assert_eq!(
+            vec![blocked.id],
+            ready.iter().map(|todo| todo.id).collect::<Vec<_>>()
+        );
+
+        let err = repository
+            .update(
+                blocked.id,
+                UpdateTodo {
+                    blocked_by: Some(vec![blocked.id]),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn attachments_are_stored_as_json() {
+        let repository = repository().await;
+        let todo = repository.create("send the report".into()).await.unwrap();
+        let draft = Attachment {
+            name: "draft.pdf".to_string(),
+            url: "https://example.com/draft.pdf".to_string(),
+            size_bytes: 1024,
+        };
+
+        let todo = repository
+            .add_attachment(todo.id, draft.clone())
+            .await
+            .unwrap();
+        assert_eq!(vec![draft], todo.attachments);
+        assert_eq!(Some(todo.clone()), repository.find(todo.id).await);
+
+        let todo = repository
+            .remove_attachment(todo.id, "draft.pdf")
+            .await
+            .unwrap();
+        assert!(repository
+            .find(todo.id)
+            .await
+            .unwrap()
+            .attachments
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn merge_keeps_one_todo_with_both_tag_sets() {
+        let repository = repository().await;
+        for tags in [["a", "b"], ["b", "c"]] {
+            let payload = CreateTodo::builder()
+                .text("dup")
+                .tag(tags[0])
+                .tag(tags[1])
+                .build()
+                .unwrap();
+            repository.create(payload).await.unwrap();
+        }
+
+        let todo = repository.merge(1, 2).await.unwrap();
+        assert_eq!(vec!["a", "b", "c"], todo.tags);
+        assert_eq!(Some(todo), repository.find(1).await);
+        assert_eq!(None, repository.find(2).await);
+    }
+
+    #[tokio::test]
+    async fn complete_where_leaves_other_todos_alone() {
+        let repository = repository().await;
+        for tag in ["home", "work", "home"] {
+            let payload = CreateTodo::builder().text(tag).tag(tag).build().unwrap();
+            repository.create(payload).await.unwrap();
+        }
+
+        let completed = repository
+            .complete_where(TodoFilter {
+                tag: Some("home".to_string()),
+                ..TodoFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![1, 3],
+            completed.iter().map(|todo| todo.id).collect::<Vec<_>>()
+        );
+        assert!(!repository.find(2).await.unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn description_is_stored_and_can_be_cleared() {
+        let repository = repository().await;
+        let payload = CreateTodo::builder()
+            .text("trip")
+            .description("pack the tent")
+            .build()
+            .unwrap();
+        let todo = repository.create(payload).await.unwrap();
+        assert_eq!(Some("pack the tent".to_string()), todo.description);
+
+        let cleared = repository
+            .update(
+                todo.id,
+                UpdateTodo {
+                    description: Some(None),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(None, cleared.description);
+        assert_eq!(Some(cleared), repository.find(todo.id).await);
+    }
+
+    #[tokio::test]
+    async fn color_is_stored_and_validated_on_update() {
+        let repository = repository().await;
+        let payload = CreateTodo::builder()
+            .text("paint")
+            .color("#ff8800")
+            .build()
+            .unwrap();
+        let todo = repository.create(payload).await.unwrap();
+        assert_eq!(Some(todo.clone()), repository.find(todo.id).await);
+
+        let err = repository
+            .update(
+                todo.id,
+                UpdateTodo {
+                    color: Some(Some("orange".to_string())),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+        assert_eq!(Some(todo.clone()), repository.find(todo.id).await);
+    }
+
+    #[tokio::test]
+    async fn client_supplied_ids_are_rejected() {
+        let repository = repository().await;
+        let payload = CreateTodo::builder().id(7).text("offline").build().unwrap();
+
+        let err = repository.create(payload).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Validation(_))
+        ));
+        assert!(repository.all().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn estimate_and_logged_time_are_stored() {
+        let repository = repository().await;
+        let payload = CreateTodo::builder()
+            .text("write report")
+            .estimate_minutes(90)
+            .build()
+            .unwrap();
+        let todo = repository.create(payload).await.unwrap();
+
+        repository.log_time(todo.id, 25).await.unwrap();
+        let logged = repository.log_time(todo.id, 40).await.unwrap();
+        assert_eq!(Some(90), logged.estimate_minutes);
+        assert_eq!(65, logged.spent_minutes);
+        assert_eq!(todo.version + 2, logged.version);
+        assert_eq!(Some(logged), repository.find(todo.id).await);
+        assert!(matches!(
+            repository
+                .log_time(-1, 5)
+                .await
+                .unwrap_err()
+                .downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(-1))
+        ));
+    }
+}
@@ -0,0 +1,201 @@
+use super::{BulkDeleteReport, CompleteOutcome, RepositoryError, Todo, TodoRepository, UpdateTodo};
+
+// Scenarios every `TodoRepository` must pass the same way. A backend opts in
+// with a `#[tokio::test]` that hands `run_conformance` a fresh instance. The
+// scenarios only look at the todos they create, so a shared database that
+// already holds rows is fine.
+pub async fn run_conformance<R: TodoRepository>(repository: R) {
+    repository.health_check().await.unwrap();
+    crud(&repository).await;
+    blank_text_is_rejected(&repository).await;
+    missing_ids_are_not_found(&repository).await;
+    ids_are_not_reused(&repository).await;
+    soft_delete_and_restore(&repository).await;
+    toggle_completed(&repository).await;
+    complete(&repository).await;
+    update_if_version(&repository).await;
+    replace(&repository).await;
+    archive_and_unarchive(&repository).await;
+    bulk_delete(&repository).await;
+}
+
+fn is_error(err: &anyhow::Error, expected: fn(&RepositoryError) -> bool) -> bool {
+    err.downcast_ref::<RepositoryError>().is_some_and(expected)
+}
+
+fn is_validation(e: &RepositoryError) -> bool {
+    matches!(e, RepositoryError::Validation(_))
+}
+
+fn is_conflict(e: &RepositoryError) -> bool {
+    matches!(e, RepositoryError::Conflict(_))
+}
+
+fn listed(todos: &[Todo], id: i32) -> bool {
+    todos.iter().any(|todo| todo.id == id)
+}
+
+async fn crud<R: TodoRepository>(repository: &R) {
+    let created = repository.create("todo text".into()).await.unwrap();
+    assert_eq!("todo text", created.text);
+    assert!(!created.completed);
+    assert_eq!(1, created.version);
+    assert_eq!(Some(created.clone()), repository.find(created.id).await);
+    assert!(listed(&repository.all().await, created.id));
+
+    let updated = repository
+        .update(
+            created.id,
+            UpdateTodo {
+                text: Some("update todo text".to_string()),
+                completed: Some(true),
+                ..UpdateTodo::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!("update todo text", updated.text);
+    assert!(updated.completed);
+    assert_eq!(2, updated.version);
+    assert_eq!(created.created_at, updated.created_at);
+    assert_eq!(Some(updated), repository.find(created.id).await);
+
+    repository.delete(created.id).await.unwrap();
+    assert_eq!(None, repository.find(created.id).await);
+    assert!(!listed(&repository.all().await, created.id));
+}
+
+async fn blank_text_is_rejected<R: TodoRepository>(repository: &R) {
+    let err = repository.create("  ".into()).await.unwrap_err();
+    assert!(is_error(&err, is_validation));
+}
+
+async fn missing_ids_are_not_found<R: TodoRepository>(repository: &R) {
+    let not_found = |e: &RepositoryError| matches!(e, RepositoryError::NotFound(-1));
+
+    assert_eq!(None, repository.find(-1).await);
+    let err = repository
+        .update(-1, UpdateTodo::completed(true))
+        .await
+        .unwrap_err();
+    assert!(is_error(&err, not_found));
+    let err = repository.delete(-1).await.unwrap_err();
+    assert!(is_error(&err, not_found));
+    let err = repository.toggle_completed(-1).await.unwrap_err();
+    assert!(is_error(&err, not_found));
+}
+
+async fn ids_are_not_reused<R: TodoRepository>(repository: &R) {
+    let first = repository.create("first".into()).await.unwrap();
+    repository.delete(first.id).await.unwrap();
+
+    let second = repository.create("second".into()).await.unwrap();
+    assert!(second.id > first.id);
+
+    repository.delete(second.id).await.unwrap();
+}
+
+async fn soft_delete_and_restore<R: TodoRepository>(repository: &R) {
+    let todo = repository.create("soft".into()).await.unwrap();
+
+    repository.soft_delete(todo.id).await.unwrap();
+    assert_eq!(None, repository.find(todo.id).await);
+    assert!(!listed(&repository.all().await, todo.id));
+
+    let restored = repository.restore(todo.id).await.unwrap();
+    assert_eq!(todo.id, restored.id);
+    assert!(listed(&repository.all().await, todo.id));
+
+    repository.delete(todo.id).await.unwrap();
+}
+
+async fn toggle_completed<R: TodoRepository>(repository: &R) {
+    let todo = repository.create("toggle".into()).await.unwrap();
+
+    let toggled = repository.toggle_completed(todo.id).await.unwrap();
+    assert!(toggled.completed);
+    let toggled = repository.toggle_completed(todo.id).await.unwrap();
+    assert!(!toggled.completed);
+
+    repository.delete(todo.id).await.unwrap();
+}
+
+async fn complete<R: TodoRepository>(repository: &R) {
+    let todo = repository.create("complete".into()).await.unwrap();
+
+    let CompleteOutcome::JustCompleted(completed) = repository.complete(todo.id).await.unwrap()
+    else {
+        panic!("the first complete should complete the todo");
+    };
+    assert!(completed.completed);
+    assert_eq!(
+        CompleteOutcome::AlreadyComplete(completed),
+        repository.complete(todo.id).await.unwrap()
+    );
+    let not_found = |e: &RepositoryError| matches!(e, RepositoryError::NotFound(-1));
+    assert!(is_error(
+        &repository.complete(-1).await.unwrap_err(),
+        not_found
+    ));
+
+    repository.delete(todo.id).await.unwrap();
+}
+
+async fn update_if_version<R: TodoRepository>(repository: &R) {
+    let todo = repository.create("versioned".into()).await.unwrap();
+
+    let updated = repository
+        .update_if_version(todo.id, todo.version, UpdateTodo::completed(true))
+        .await
+        .unwrap();
+    let err = repository
+        .update_if_version(todo.id, todo.version, UpdateTodo::completed(false))
+        .await
+        .unwrap_err();
+    assert!(is_error(&err, is_conflict));
+    assert_eq!(Some(updated), repository.find(todo.id).await);
+
+    repository.delete(todo.id).await.unwrap();
+}
+
+async fn replace<R: TodoRepository>(repository: &R) {
+    let todo = repository.create("replaced".into()).await.unwrap();
+
+    let (previous, updated) = repository
+        .replace(todo.id, UpdateTodo::completed(true))
+        .await
+        .unwrap();
+    assert_eq!(todo, previous);
+    assert!(updated.completed);
+    assert_eq!(Some(updated), repository.find(todo.id).await);
+
+    repository.delete(todo.id).await.unwrap();
+}
+
+async fn archive_and_unarchive<R: TodoRepository>(repository: &R) {
+    let todo = repository.create("archive".into()).await.unwrap();
+
+    repository.archive(todo.id).await.unwrap();
+    assert!(!listed(&repository.all().await, todo.id));
+    assert!(listed(&repository.archived().await, todo.id));
+
+    repository.unarchive(todo.id).await.unwrap();
+    assert!(listed(&repository.all().await, todo.id));
+    assert!(!listed(&repository.archived().await, todo.id));
+
+    repository.delete(todo.id).await.unwrap();
+}
+
+async fn bulk_delete<R: TodoRepository>(repository: &R) {
+    let todo = repository.create("bulk".into()).await.unwrap();
+
+    let report = repository.bulk_delete(vec![todo.id, -1]).await.unwrap();
+    assert_eq!(
+        BulkDeleteReport {
+            deleted: vec![todo.id],
+            not_found: vec![-1],
+        },
+        report
+    );
+    assert_eq!(None, repository.find(todo.id).await);
+}
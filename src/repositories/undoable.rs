@@ -0,0 +1,365 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+
+use super::{
+    BulkDeleteReport, CompleteOutcome, CreateTodo, SerializationFormat, Todo, TodoFilter,
+    TodoRepository, TodoRepositoryForMemory, TodoSnapshot, TodoStats, UpdateTodo,
+};
+
+const DEFAULT_DEPTH: usize = 50;
+
+// Repositories whose whole state can be captured and put back later.
+pub trait Checkpoint {
+    type Snapshot: Send + 'static;
+
+    fn snapshot(&self) -> Self::Snapshot;
+    fn restore_snapshot(&self, snapshot: Self::Snapshot);
+}
+
+impl Checkpoint for TodoRepositoryForMemory {
+    type Snapshot = TodoSnapshot;
+
+    fn snapshot(&self) -> TodoSnapshot {
+        TodoRepositoryForMemory::snapshot(self)
+    }
+
+    fn restore_snapshot(&self, snapshot: TodoSnapshot) {
+        TodoRepositoryForMemory::restore_snapshot(self, snapshot)
+    }
+}
+
+// Keeps a snapshot from before every successful mutation so `undo` can step
+// back one operation at a time. Only the newest `depth` snapshots are kept.
+// Clones share the history, and mutations and undos through any of them run
+// one at a time, so each snapshot is exactly the state the next mutation
+// started from. The history lives in this process only: writes that reach the
+// inner repository some other way are not recorded, and `undo` rolls them
+// back along with the mutation it undoes.
+#[derive(Debug)]
+pub struct UndoableRepository<R: Checkpoint> {
+    inner: R,
+    history: Arc<Mutex<VecDeque<R::Snapshot>>>,
+    depth: usize,
+}
+
+impl<R: Checkpoint> Clone for UndoableRepository<R>
+where
+    R: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            history: Arc::clone(&self.history),
+            depth: self.depth,
+        }
+    }
+}
+
+impl<R: Checkpoint> UndoableRepository<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            history: Arc::default(),
+            depth: DEFAULT_DEPTH,
+        }
+    }
+
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    // Rolls back the most recent mutation that has not been undone yet.
+    pub async fn undo(&self) -> anyhow::Result<()> {
+        let mut history = self.history.lock().await;
+        let snapshot = history.pop_back().context("nothing to undo")?;
+        self.inner.restore_snapshot(snapshot);
+
+        Ok(())
+    }
+
+    pub async fn undo_depth(&self) -> usize {
+        self.history.lock().await.len()
+    }
+
+    // Failed mutations leave the inner repository untouched, so they are not
+    // recorded.
+    async fn checkpointed<T>(
+        &self,
+        mutation: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        let mut history = self.history.lock().await;
+        let snapshot = self.inner.snapshot();
+        let result = mutation.await;
+        if result.is_ok() {
+            history.push_back(snapshot);
+            while history.len() > self.depth {
+                history.pop_front();
+            }
+        }
+
+        result
+    }
+}
+
+#[async_trait]
+impl<R> TodoRepository for UndoableRepository<R>
+where
+    R: TodoRepository + Checkpoint,
+{
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.create(payload)).await
+    }
+
+    async fn find_or_create(&self, text: &str) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.find_or_create(text)).await
+    }
+
+    async fn create_unique(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.create_unique(payload)).await
+    }
+
+    async fn find(&self, id: i32) -> Option<Todo> {
+        self.inner.find(id).await
+    }
+
+    async fn exists(&self, id: i32) -> bool {
+        self.inner.exists(id).await
+    }
+
+    async fn all(&self) -> Vec<Todo> {
+        self.inner.all().await
+    }
+
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.update(id, payload)).await
+    }
+
+    async fn update_if_version(
+        &self,
+        id: i32,
+        expected_version: u32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.update_if_version(id, expected_version, payload))
+            .await
+    }
+
+    async fn replace(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<(Todo, Todo)> {
+        self.checkpointed(self.inner.replace(id, payload)).await
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        self.checkpointed(self.inner.delete(id)).await
+    }
+
+    async fn count(&self) -> usize {
+        self.inner.count().await
+    }
+
+    async fn count_completed(&self) -> usize {
+        self.inner.count_completed().await
+    }
+
+    async fn bulk_create(&self, payloads: Vec<CreateTodo>) -> anyhow::Result<Vec<Todo>> {
+        self.checkpointed(self.inner.bulk_create(payloads)).await
+    }
+
+    async fn bulk_delete(&self, ids: Vec<i32>) -> anyhow::Result<BulkDeleteReport> {
+        self.checkpointed(self.inner.bulk_delete(ids)).await
+    }
+
+    async fn toggle_completed(&self, id: i32) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.toggle_completed(id)).await
+    }
+
+    async fn complete(&self, id: i32) -> anyhow::Result<CompleteOutcome> {
+        self.checkpointed(self.inner.complete(id)).await
+    }
+
+    async fn merge(&self, keep_id: i32, merge_id: i32) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.merge(keep_id, merge_id)).await
+    }
+
+    async fn soft_delete(&self, id: i32) -> anyhow::Result<()> {
+        self.checkpointed(self.inner.soft_delete(id)).await
+    }
+
+    async fn restore(&self, id: i32) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.restore(id)).await
+    }
+
+    async fn find_many(&self, ids: &[i32]) -> Vec<Todo> {
+        self.inner.find_many(ids).await
+    }
+
+    async fn clear_all(&self) -> anyhow::Result<()> {
+        self.checkpointed(self.inner.clear_all()).await
+    }
+
+    async fn reorder(&self, id: i32, new_position: i32) -> anyhow::Result<()> {
+        self.checkpointed(self.inner.reorder(id, new_position))
+            .await
+    }
+
+    async fn stats(&self) -> TodoStats {
+        self.inner.stats().await
+    }
+
+    async fn tag_counts(&self) -> HashMap<String, usize> {
+        self.inner.tag_counts().await
+    }
+
+    async fn complete_all(&self) -> anyhow::Result<usize> {
+        self.checkpointed(self.inner.complete_all()).await
+    }
+
+    async fn complete_where(&self, filter: TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        self.checkpointed(self.inner.complete_where(filter)).await
+    }
+
+    async fn update_where(
+        &self,
+        filter: TodoFilter,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Vec<Todo>> {
+        self.checkpointed(self.inner.update_where(filter, payload))
+            .await
+    }
+
+    async fn clear_completed(&self) -> anyhow::Result<usize> {
+        self.checkpointed(self.inner.clear_completed()).await
+    }
+
+    async fn archive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.archive(id)).await
+    }
+
+    async fn unarchive(&self, id: i32) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.unarchive(id)).await
+    }
+
+    async fn archived(&self) -> Vec<Todo> {
+        self.inner.archived().await
+    }
+
+    async fn import_from(&self, format: SerializationFormat, data: &[u8]) -> anyhow::Result<usize> {
+        self.checkpointed(self.inner.import_from(format, data))
+            .await
+    }
+
+    async fn log_time(&self, id: i32, minutes: u32) -> anyhow::Result<Todo> {
+        self.checkpointed(self.inner.log_time(id, minutes)).await
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        self.inner.health_check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::test_utils::run_conformance;
+
+    fn texts(todos: &[Todo]) -> Vec<String> {
+        let mut texts: Vec<String> = todos.iter().map(|todo| todo.text.clone()).collect();
+        texts.sort();
+        texts
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        run_conformance(UndoableRepository::new(TodoRepositoryForMemory::new())).await;
+    }
+
+    #[tokio::test]
+    async fn undo_steps_back_one_mutation_at_a_time() {
+        let repository = UndoableRepository::new(TodoRepositoryForMemory::new());
+        repository.create("first".into()).await.unwrap();
+        repository.create("second".into()).await.unwrap();
+        repository.toggle_completed(1).await.unwrap();
+        repository.delete(2).await.unwrap();
+        assert_eq!(4, repository.undo_depth().await);
+
+        repository.undo().await.unwrap();
+        assert_eq!(vec!["first", "second"], texts(&repository.all().await));
+        assert!(repository.find(1).await.unwrap().completed);
+
+        repository.undo().await.unwrap();
+        assert!(!repository.find(1).await.unwrap().completed);
+
+        repository.undo().await.unwrap();
+        assert_eq!(vec!["first"], texts(&repository.all().await));
+
+        repository.undo().await.unwrap();
+        assert!(repository.all().await.is_empty());
+        assert!(repository.undo().await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn concurrent_mutations_are_undone_one_at_a_time() {
+        let repository = UndoableRepository::new(TodoRepositoryForMemory::new()).with_depth(100);
+
+        let handles: Vec<_> = (0..8)
+            .map(|task| {
+                let repository = repository.clone();
+                tokio::spawn(async move {
+                    for i in 0..5 {
+                        let text = format!("task {} todo {}", task, i);
+                        repository.create(text.into()).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(40, repository.undo_depth().await);
+        for left in (0..40).rev() {
+            repository.undo().await.unwrap();
+            assert_eq!(left, repository.all().await.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn undo_restores_the_id_counter() {
+        let repository = UndoableRepository::new(TodoRepositoryForMemory::new());
+        repository.create("first".into()).await.unwrap();
+        repository.create("second".into()).await.unwrap();
+
+        repository.undo().await.unwrap();
+        assert_eq!(2, repository.create("again".into()).await.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn failed_mutations_are_not_recorded() {
+        let repository = UndoableRepository::new(TodoRepositoryForMemory::new());
+        repository.create("first".into()).await.unwrap();
+
+        assert!(repository.delete(7).await.is_err());
+        assert!(repository.create(" ".into()).await.is_err());
+        assert_eq!(1, repository.undo_depth().await);
+    }
+
+    #[tokio::test]
+    async fn history_is_capped_at_depth() {
+        let repository = UndoableRepository::new(TodoRepositoryForMemory::new()).with_depth(2);
+        for text in ["first", "second", "third"] {
+            repository.create(text.into()).await.unwrap();
+        }
+        assert_eq!(2, repository.undo_depth().await);
+
+        repository.undo().await.unwrap();
+        repository.undo().await.unwrap();
+        assert!(repository.undo().await.is_err());
+        assert_eq!(vec!["first"], texts(&repository.all().await));
+    }
+}